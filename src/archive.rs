@@ -0,0 +1,1020 @@
+use crate::block::{self, Authenticity, Block, EndHeader, FileBlock};
+use crate::error::{Error, Result};
+use crate::hash::VerifyReader;
+use crate::index::{ArchiveIndex, CHECKED_LEN};
+use crate::links::Link;
+use crate::owner::UnixOwner;
+use crate::streams::NamedStream;
+use crate::traits::AsyncFile;
+use crate::unpack::{EntryReader, Store};
+use futures::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use futures::task::{Context, Poll};
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+
+// Signature of RAR 1.4 archives, which is also the start of the main header.
+pub const RAR14_SIGNATURE: [u8; 4] = [0x52, 0x45, 0x7e, 0x5e];
+// Marker block of RAR 1.5 to 4.x archives.
+pub const RAR15_SIGNATURE: [u8; 7] = [0x52, 0x61, 0x72, 0x21, 0x1a, 0x07, 0x00];
+// Signature of RAR 5.0 archives.
+pub const RAR50_SIGNATURE: [u8; 8] = [0x52, 0x61, 0x72, 0x21, 0x1a, 0x07, 0x01, 0x00];
+
+// How far into a file the signature may start, which leaves room for the
+// executable stub of a self-extracting archive.
+pub const DEFAULT_SFX_SCAN_LIMIT: u64 = 0x40_0000;
+
+// How much to read at a time while looking for the signature.
+const SCAN_CHUNK: usize = 0x1000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Rar14,
+    Rar15,
+    Rar50,
+}
+
+enum Scan {
+    Found(Format, usize),
+    // The data so far is a prefix of a signature, so more is needed to tell.
+    Partial,
+    NotFound,
+}
+
+fn match_signature(buf: &[u8]) -> Scan {
+    for &(format, signature) in &[
+        (Format::Rar14, &RAR14_SIGNATURE[..]),
+        (Format::Rar15, &RAR15_SIGNATURE[..]),
+        (Format::Rar50, &RAR50_SIGNATURE[..]),
+    ] {
+        if buf.starts_with(signature) {
+            return Scan::Found(format, signature.len());
+        }
+        if signature.starts_with(buf) {
+            return Scan::Partial;
+        }
+    }
+    Scan::NotFound
+}
+
+// Find the signature in the first `sfx_limit` bytes of `buf`, returning the
+// format and where the blocks start. Asks for more data with
+// ErrorKind::BufferTooSmall while the signature could still follow.
+pub fn find_signature(buf: &[u8], sfx_limit: u64) -> Result<(Format, usize)> {
+    for pos in 0..buf.len() {
+        if pos as u64 > sfx_limit {
+            break;
+        }
+        match match_signature(&buf[pos..]) {
+            Scan::Found(format, len) => return Ok((format, pos + len)),
+            Scan::Partial => return Err(Error::buffer_too_small(pos + RAR50_SIGNATURE.len())),
+            Scan::NotFound => {}
+        }
+    }
+    if (buf.len() as u64) <= sfx_limit {
+        return Err(Error::buffer_too_small(buf.len() + 1));
+    }
+    Err(Error::bad_block(format!(
+        "No RAR signature found in the first {} bytes",
+        sfx_limit
+    )))
+}
+
+// Which versions of files archived with version control `read_entry` returns.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Versions {
+    // Only the current version of each file.
+    Latest,
+    All,
+}
+
+// An opened archive. Reading from it continues right after the signature.
+pub struct Archive<T> {
+    inner: T,
+    format: Format,
+    sfx_size: u64,
+    // Bytes read past the signature while scanning for it.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    authenticity: Option<Authenticity>,
+    // A block read past the end of an entry by `read_entry`, and where its
+    // header starts.
+    peeked: Option<(u64, Block)>,
+    versions: Versions,
+    include_deleted: bool,
+    // Offset in the file of the next byte read from the archive.
+    position: u64,
+    // Files seen so far, in archive order.
+    index: Vec<IndexEntry>,
+    // Whether `index` covers the whole archive.
+    indexed: bool,
+    // CRC32 of the archive read so far, from the signature on, or `None`
+    // once a seek skipped part of it, which leaves the archive data CRC of
+    // the end block unchecked.
+    data_crc: Option<u32>,
+    // The end of archive block, once reached.
+    end: Option<EndHeader>,
+}
+
+// A file of a RAR 1.5-4.x archive along with the service blocks attached to
+// it: NTFS streams and the Unix owner.
+#[derive(Debug)]
+pub struct Entry {
+    pub file: FileBlock,
+    // Offset in the file of the packed data.
+    pub data_offset: u64,
+    // The target of a symlink, when it's stored.
+    pub link: Option<Link>,
+    pub streams: Vec<NamedStream>,
+    pub owner: Option<UnixOwner>,
+}
+
+// Where a file of a RAR 1.5-4.x archive is stored, to go back to it without
+// reading the archive again.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IndexEntry {
+    // The name including any version number.
+    pub name: String,
+    pub header_offset: u64,
+    pub data_offset: u64,
+    pub packed_size: u64,
+    pub unpacked_size: u64,
+    pub crc32: u32,
+    // Whether unpacking depends on the files before this one.
+    pub solid: bool,
+}
+
+impl<T: AsyncFile> Archive<T> {
+    pub async fn open(inner: T) -> Result<Archive<T>> {
+        Archive::open_with_sfx_limit(inner, DEFAULT_SFX_SCAN_LIMIT).await
+    }
+
+    // Look for the signature in the first `sfx_limit` bytes of `inner`. Pass
+    // 0 to only accept archives that start with the signature.
+    pub async fn open_with_sfx_limit(mut inner: T, sfx_limit: u64) -> Result<Archive<T>> {
+        let mut buf = Vec::new();
+        // Offset in the file of buf[0].
+        let mut base: u64 = 0;
+        let mut eof = false;
+
+        loop {
+            let mut pos = 0;
+            while pos < buf.len() && base + pos as u64 <= sfx_limit {
+                match match_signature(&buf[pos..]) {
+                    Scan::Found(format, len) => {
+                        let data_crc = crc::crc32::checksum_ieee(&buf[pos..pos + len]);
+                        buf.drain(..pos + len);
+                        let position = base + (pos + len) as u64;
+                        return Ok(Archive {
+                            inner,
+                            format,
+                            sfx_size: base + pos as u64,
+                            pending: buf,
+                            pending_pos: 0,
+                            authenticity: None,
+                            peeked: None,
+                            versions: Versions::Latest,
+                            include_deleted: false,
+                            position,
+                            index: Vec::new(),
+                            indexed: false,
+                            data_crc: Some(data_crc),
+                            end: None,
+                        });
+                    }
+                    Scan::Partial if !eof => break,
+                    _ => pos += 1,
+                }
+            }
+
+            if eof || base + pos as u64 > sfx_limit {
+                return Err(Error::bad_block(format!(
+                    "No RAR signature found in the first {} bytes",
+                    sfx_limit
+                )));
+            }
+
+            buf.drain(..pos);
+            base += pos as u64;
+            let len = buf.len();
+            buf.resize(len + SCAN_CHUNK, 0);
+            let n = inner.read(&mut buf[len..]).await?;
+            buf.truncate(len + n);
+            eof = n == 0;
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    // Size of the self-extracting stub before the signature, 0 for plain
+    // archives.
+    pub fn sfx_size(&self) -> u64 {
+        self.sfx_size
+    }
+
+    pub fn is_sfx(&self) -> bool {
+        self.sfx_size > 0
+    }
+
+    // Read the next block of a RAR 1.5-4.x archive.
+    pub async fn read_block(&mut self) -> Result<Block> {
+        let data_crc = self.data_crc;
+        let mut block = block::read_block(self).await?;
+        match block {
+            Block::Av(ref av) => self
+                .authenticity
+                .get_or_insert_with(Authenticity::default)
+                .add_av(av),
+            Block::Sign(ref sign) => self
+                .authenticity
+                .get_or_insert_with(Authenticity::default)
+                .add_sign(sign),
+            // Only checked when the whole archive before the block was read.
+            Block::End(ref mut end) if !end.is_volume() => {
+                end.data_crc_matches = match (end.data_crc, data_crc) {
+                    (Some(expected), Some(actual)) => Some(expected == actual),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+        Ok(block)
+    }
+
+    pub fn set_versions(&mut self, versions: Versions) {
+        self.versions = versions;
+    }
+
+    // Return files whose blocks are marked deleted from `read_entry` too, to
+    // recover what's left of them.
+    pub fn set_include_deleted(&mut self, include_deleted: bool) {
+        self.include_deleted = include_deleted;
+    }
+
+    // Read the next file of a RAR 1.5-4.x archive along with its streams,
+    // or `None` at the end of the archive. The file data is skipped, and so
+    // are old versions of files and deleted files unless asked for with
+    // `set_versions` and `set_include_deleted`.
+    pub async fn read_entry(&mut self) -> Result<Option<Entry>> {
+        let (header_offset, file) = loop {
+            let (offset, block) = match self.peeked.take() {
+                Some(peeked) => peeked,
+                None if self.end.is_some() || self.at_eof().await? => return Ok(None),
+                None => (self.position, self.read_block().await?),
+            };
+            match block {
+                Block::File(ref file) if !self.wants(file) => file.skip_data(self).await?,
+                Block::File(file) => break (offset, file),
+                // Service blocks of skipped files end up here too.
+                Block::Service(ref service) => service.skip_data(self).await?,
+                Block::Protect(ref protect) => {
+                    block::skip(self, u64::from(protect.prefix.data_size())).await?
+                }
+                Block::End(end) => {
+                    self.end = Some(end);
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        };
+        let data_offset = self.position;
+        self.record(header_offset, data_offset, &file);
+        let link = if file.header().is_symlink() {
+            file.read_link(self).await?
+        } else {
+            file.skip_data(self).await?;
+            None
+        };
+
+        let mut streams = Vec::new();
+        let mut owner = None;
+        while !self.at_eof().await? {
+            let offset = self.position;
+            match self.read_block().await? {
+                Block::Service(ref service)
+                    if service.prefix.is_deleted() && !self.include_deleted =>
+                {
+                    service.skip_data(self).await?
+                }
+                Block::Service(ref service) if service.is_stream() => {
+                    streams.push(service.read_stream(self).await?)
+                }
+                Block::Service(ref service) if service.is_owner() => {
+                    owner = Some(service.read_owner()?);
+                    service.skip_data(self).await?
+                }
+                Block::Service(ref service) => service.skip_data(self).await?,
+                block => {
+                    self.peeked = Some((offset, block));
+                    break;
+                }
+            }
+        }
+        Ok(Some(Entry {
+            file,
+            data_offset,
+            link,
+            streams,
+            owner,
+        }))
+    }
+
+    // Add a file to the index, unless it's there from an earlier pass.
+    fn record(&mut self, header_offset: u64, data_offset: u64, file: &FileBlock) {
+        if self
+            .index
+            .last()
+            .is_some_and(|last| last.header_offset >= header_offset)
+        {
+            return;
+        }
+        let header = file.header();
+        self.index.push(IndexEntry {
+            name: header.full_name(),
+            header_offset,
+            data_offset,
+            packed_size: header.packed_size(),
+            unpacked_size: header.unpacked_size(),
+            crc32: header.prefix().file_crc32(),
+            solid: header.is_solid(),
+        });
+    }
+
+    // Files seen so far by `read_entry` and `build_index`.
+    pub fn index(&self) -> &[IndexEntry] {
+        &self.index
+    }
+
+    fn wants(&self, file: &FileBlock) -> bool {
+        (self.versions == Versions::All || file.header().version().is_none())
+            && (self.include_deleted || !file.prefix.is_deleted())
+    }
+
+    // Whether the whole archive has been read. Reads ahead into `pending`.
+    pub(crate) async fn at_eof(&mut self) -> Result<bool> {
+        if self.pending_pos < self.pending.len() {
+            return Ok(false);
+        }
+        self.pending.resize(SCAN_CHUNK, 0);
+        let n = self.inner.read(&mut self.pending).await?;
+        self.pending.truncate(n);
+        self.pending_pos = 0;
+        Ok(n == 0)
+    }
+
+    // Offset in the file of the next byte read from the archive.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    // Reading from the underlying reader directly skips data buffered while
+    // opening the archive; seeking it is fine as long as it's moved back.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    // The end of archive block, once `read_entry` or `build_index` reached
+    // it. Archives written before RAR 3.0 don't have one.
+    pub fn end(&self) -> Option<&EndHeader> {
+        self.end.as_ref()
+    }
+
+    // Authenticity information from the blocks read so far.
+    pub fn authenticity(&self) -> Option<&Authenticity> {
+        self.authenticity.as_ref()
+    }
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Continue reading the archive at `offset`, which has to be the start of
+    // a block. Seeks only when the buffered data doesn't reach that far.
+    pub(crate) async fn skip_to(&mut self, offset: u64) -> Result<()> {
+        let buffered = (self.pending.len() - self.pending_pos) as u64;
+        if offset >= self.position && offset - self.position <= buffered {
+            let skipped = (offset - self.position) as usize;
+            let bytes = &self.pending[self.pending_pos..self.pending_pos + skipped];
+            self.data_crc = update_crc(self.data_crc, bytes);
+            self.pending_pos += skipped;
+        } else {
+            self.inner.seek(SeekFrom::Start(offset)).await?;
+            self.pending.clear();
+            self.pending_pos = 0;
+            self.data_crc = None;
+        }
+        self.position = offset;
+        self.peeked = None;
+        Ok(())
+    }
+
+    // Walk the rest of the blocks, seeking past file data instead of reading
+    // it, and return the index of the whole archive.
+    pub async fn build_index(&mut self) -> Result<&[IndexEntry]> {
+        if self.indexed {
+            return Ok(&self.index);
+        }
+        if let Some((offset, _)) = self.peeked {
+            self.skip_to(offset).await?;
+        }
+        while self.end.is_none() && !self.at_eof().await? {
+            let offset = self.position;
+            let block = self.read_block().await?;
+            let data_size = match block {
+                Block::File(ref file) => {
+                    if self.wants(file) {
+                        self.record(offset, self.position, file);
+                    }
+                    file.header().packed_size()
+                }
+                Block::Service(ref service) => service.header().packed_size(),
+                Block::Protect(ref protect) => u64::from(protect.prefix.data_size()),
+                Block::End(end) => {
+                    self.end = Some(end);
+                    break;
+                }
+                _ => 0,
+            };
+            self.skip_to(self.position + data_size).await?;
+        }
+        self.indexed = true;
+        Ok(&self.index)
+    }
+
+    // Open an archive using an index saved with `export_index` instead of
+    // scanning it. Fails if the index doesn't match the archive anymore. The
+    // archive picks versions and deleted files the way it did when the index
+    // was made.
+    pub async fn open_with_index(inner: T, index: &[u8]) -> Result<Archive<T>> {
+        let index = ArchiveIndex::from_bytes(index)?;
+        let mut archive = Archive::open(inner).await?;
+        if archive.format != index.format
+            || archive.fingerprint().await? != (index.archive_size, index.header_crc)
+        {
+            return Err(Error::bad_data(
+                "Archive index doesn't match the archive".into(),
+            ));
+        }
+        archive.versions = index.versions;
+        archive.include_deleted = index.include_deleted;
+        archive.index = index.entries;
+        archive.indexed = true;
+        Ok(archive)
+    }
+
+    // Index the whole archive and save the index for `open_with_index`.
+    pub async fn export_index(&mut self) -> Result<Vec<u8>> {
+        self.build_index().await?;
+        let (archive_size, header_crc) = self.fingerprint().await?;
+        Ok(ArchiveIndex {
+            format: self.format,
+            versions: self.versions,
+            include_deleted: self.include_deleted,
+            archive_size,
+            header_crc,
+            entries: self.index.clone(),
+        }
+        .to_bytes())
+    }
+
+    // The size of the archive and a CRC32 of its start, to tell whether an
+    // index still matches. Leaves `inner` where it was.
+    async fn fingerprint(&mut self) -> Result<(u64, u32)> {
+        let resume = self.inner.seek(SeekFrom::Current(0)).await?;
+        let size = self.inner.seek(SeekFrom::End(0)).await?;
+        let mut buf = vec![0; (size - self.sfx_size).min(CHECKED_LEN) as usize];
+        self.inner.seek(SeekFrom::Start(self.sfx_size)).await?;
+        self.inner.read_exact(&mut buf).await?;
+        self.inner.seek(SeekFrom::Start(resume)).await?;
+        Ok((size, crc::crc32::checksum_ieee(&buf)))
+    }
+
+    // Jump to the data of the file named `name`, including any version
+    // number, and stream its contents. Builds the index first if the file
+    // hasn't been seen yet.
+    pub async fn open_entry_by_name<'a>(
+        &'a mut self,
+        name: &'a str,
+    ) -> Result<Option<VerifyReader<EntryReader<&'a mut Archive<T>, Store>>>> {
+        let mut found = self.index.iter().find(|e| e.name == name).cloned();
+        if found.is_none() && !self.indexed {
+            self.build_index().await?;
+            found = self.index.iter().find(|e| e.name == name).cloned();
+        }
+        let found = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        self.skip_to(found.header_offset).await?;
+        let file = match self.read_block().await? {
+            Block::File(file) => file,
+            _ => {
+                return Err(Error::bad_block(format!(
+                    "No file header at offset {}",
+                    found.header_offset
+                )))
+            }
+        };
+        if file.header().full_name() != found.name {
+            return Err(Error::bad_block(format!(
+                "File header at offset {} is for {}, not {}",
+                found.header_offset,
+                file.header().full_name(),
+                found.name
+            )));
+        }
+        Ok(Some(file.reader(self)?))
+    }
+}
+
+fn update_crc(crc: Option<u32>, bytes: &[u8]) -> Option<u32> {
+    crc.map(|crc| crc::crc32::update(crc, &crc::crc32::IEEE_TABLE, bytes))
+}
+
+impl<T: AsyncFile> AsyncRead for Archive<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.pending_pos < this.pending.len() {
+            let rest = &this.pending[this.pending_pos..];
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            this.pending_pos += n;
+            this.position += n as u64;
+            this.data_crc = update_crc(this.data_crc, &buf[..n]);
+            return Poll::Ready(Ok(n));
+        }
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            this.position += n as u64;
+            this.data_crc = update_crc(this.data_crc, &buf[..n]);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::{file_block, service_block, with_header_crc, FileBlock};
+    use futures::executor::block_on;
+
+    // Hands out its data a few bytes at a time, to split the signature
+    // across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    fn with_stub(stub_len: usize, signature: &[u8]) -> Vec<u8> {
+        let mut data = vec![b'M'; stub_len];
+        // Something that looks like the start of a signature but isn't one.
+        data.extend(b"Rar!\x1a");
+        data.extend(signature);
+        data.extend(b"body");
+        data
+    }
+
+    fn rest<T: AsyncFile>(mut archive: Archive<T>) -> Vec<u8> {
+        let mut out = Vec::new();
+        block_on(archive.read_to_end(&mut out)).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_open_plain_archive() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(b"body");
+        let archive = block_on(Archive::open(&data[..])).unwrap();
+        assert_eq!(archive.format(), Format::Rar15);
+        assert!(!archive.is_sfx());
+        assert_eq!(rest(archive), b"body".to_vec());
+    }
+
+    #[test]
+    fn test_open_finds_signature_after_stub() {
+        let data = with_stub(10_000, &RAR50_SIGNATURE);
+        let archive = block_on(Archive::open(&data[..])).unwrap();
+        assert_eq!(archive.format(), Format::Rar50);
+        assert_eq!(archive.sfx_size(), 10_005);
+        assert_eq!(rest(archive), b"body".to_vec());
+    }
+
+    #[test]
+    fn test_open_signature_split_across_reads() {
+        let data = with_stub(4, &RAR15_SIGNATURE);
+        let archive = block_on(Archive::open(Trickle(&data))).unwrap();
+        assert_eq!(archive.format(), Format::Rar15);
+        assert_eq!(archive.sfx_size(), 9);
+        assert_eq!(rest(archive), b"body".to_vec());
+    }
+
+    #[test]
+    fn test_open_rar14_archive() {
+        let data = with_stub(0, &RAR14_SIGNATURE);
+        let archive = block_on(Archive::open(&data[..])).unwrap();
+        assert_eq!(archive.format(), Format::Rar14);
+        assert_eq!(archive.sfx_size(), 5);
+        assert_eq!(rest(archive), b"body".to_vec());
+    }
+
+    #[test]
+    fn test_open_respects_scan_limit() {
+        let data = with_stub(100, &RAR15_SIGNATURE);
+        assert!(block_on(Archive::open_with_sfx_limit(&data[..], 104)).is_err());
+        assert!(block_on(Archive::open_with_sfx_limit(&data[..], 105)).is_ok());
+        assert!(block_on(Archive::open_with_sfx_limit(&data[..], 0)).is_err());
+    }
+
+    #[test]
+    fn test_read_block_collects_authenticity() {
+        let mut sign = vec![0, 0, 0x79, 0, 0, 7 + 8 + 8 + 5, 0];
+        sign.extend(&0x3173_b82bu32.to_le_bytes());
+        sign.extend(&[8, 0, 5, 0]);
+        sign.extend(b"roar.rarErich");
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(with_header_crc(sign));
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        assert!(archive.authenticity().is_none());
+        match block_on(archive.read_block()).unwrap() {
+            Block::Sign(ref sign) => assert_eq!(sign.archive_name, "roar.rar"),
+            ref x => panic!("unexpected block {:?}", x),
+        }
+        let info = archive.authenticity().unwrap();
+        assert_eq!(info.creator, Some("Erich".to_string()));
+        assert_eq!(info.created.unwrap().year, 2004);
+    }
+
+    #[test]
+    fn test_read_entry_attaches_streams() {
+        let stream_name: Vec<u8> = ":thumb:$DATA"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"first"));
+        data.extend(service_block(b"STM", &stream_name, b"stream"));
+        data.extend(service_block(b"ACL", b"", b"acl"));
+        data.extend(service_block(b"UOW", b"erich\0staff", b""));
+        data.extend(file_block(b"b.txt", b"second"));
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        let first = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(first.file.header().name(), "a.txt");
+        assert_eq!(first.data_offset, 7 + 37);
+        assert_eq!(first.streams.len(), 1);
+        assert_eq!(first.streams[0].name, "thumb");
+        assert_eq!(first.streams[0].data, Some(b"stream".to_vec()));
+        assert_eq!(first.owner.unwrap().to_string(), "erich:staff");
+
+        let second = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(second.file.header().name(), "b.txt");
+        assert!(second.streams.is_empty());
+        assert!(second.owner.is_none());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_entry_versions() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(
+            FileBlock {
+                flags: 0x0800,
+                ..FileBlock::new(b"a.txt;1", b"old")
+            }
+            .build(),
+        );
+        data.extend(service_block(b"UOW", b"erich\0staff", b""));
+        data.extend(file_block(b"a.txt", b"new"));
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        let entry = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(entry.file.header().name(), "a.txt");
+        assert_eq!(entry.file.header().version(), None);
+        assert!(entry.owner.is_none());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        archive.set_versions(Versions::All);
+        let entry = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(entry.file.header().name(), "a.txt");
+        assert_eq!(entry.file.header().version(), Some(1));
+        assert!(entry.owner.is_some());
+        assert!(block_on(archive.read_entry()).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_read_entry_skips_deleted_files() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(
+            FileBlock {
+                flags: 0x4000,
+                ..FileBlock::new(b"gone.txt", b"gone")
+            }
+            .build(),
+        );
+        data.extend(file_block(b"a.txt", b"here"));
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        let entry = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(entry.file.header().name(), "a.txt");
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        archive.set_include_deleted(true);
+        let entry = block_on(archive.read_entry()).unwrap().unwrap();
+        assert_eq!(entry.file.header().name(), "gone.txt");
+        assert!(entry.file.prefix.is_deleted());
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        assert!(block_on(archive.read_block()).unwrap().is_deleted());
+    }
+
+    fn with_end_block(mut data: Vec<u8>, volume_number: Option<u16>) -> Vec<u8> {
+        let mut end = vec![0, 0, 0x7b, 0x02, 0x40, 11, 0];
+        end.extend(&crc::crc32::checksum_ieee(&data).to_le_bytes());
+        if let Some(number) = volume_number {
+            end[3] |= 0x08;
+            end[5] += 2;
+            end.extend(&number.to_le_bytes());
+        }
+        data.extend(with_header_crc(end));
+        data
+    }
+
+    #[test]
+    fn test_read_entry_stops_at_end_block() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        let mut data = with_end_block(data, Some(2));
+        data.extend(b"trailing junk");
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        assert!(block_on(archive.read_entry()).unwrap().is_some());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+        let end = archive.end().unwrap();
+        assert_eq!(end.volume_number, Some(2));
+        assert!(!end.has_next_volume());
+    }
+
+    #[test]
+    fn test_checks_archive_data_crc() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        // The stub isn't covered.
+        let mut stub = b"MZ stub".to_vec();
+        stub.extend(with_end_block(data.clone(), None));
+
+        let mut archive = block_on(Archive::open(&stub[..])).unwrap();
+        assert!(block_on(archive.read_entry()).unwrap().is_some());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+        assert_eq!(archive.end().unwrap().data_crc_matches, Some(true));
+
+        let data_end = stub.len() - 12;
+        stub[data_end] ^= 1;
+        // The files can still be read.
+        let mut archive = block_on(Archive::open(&stub[..])).unwrap();
+        assert!(block_on(archive.read_entry()).unwrap().is_some());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+        assert_eq!(archive.end().unwrap().data_crc_matches, Some(false));
+        stub[data_end] ^= 1;
+
+        // Seeking leaves it unchecked.
+        let mut archive = block_on(Archive::open(io::Cursor::new(stub))).unwrap();
+        block_on(archive.read_block()).unwrap();
+        block_on(archive.skip_to(14)).unwrap();
+        block_on(archive.build_index()).unwrap();
+        assert_eq!(archive.end().unwrap().data_crc_matches, None);
+
+        // So do volumes.
+        let mut volume = with_end_block(data, Some(2));
+        let data_end = volume.len() - 14;
+        volume[data_end] ^= 1;
+        let mut archive = block_on(Archive::open(&volume[..])).unwrap();
+        assert!(block_on(archive.read_entry()).unwrap().is_some());
+        assert!(block_on(archive.read_entry()).unwrap().is_none());
+        assert_eq!(archive.end().unwrap().data_crc_matches, None);
+    }
+
+    // Counts the bytes read from a seekable buffer.
+    struct Counting {
+        inner: io::Cursor<Vec<u8>>,
+        read: usize,
+    }
+
+    impl AsyncRead for Counting {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+            if let Poll::Ready(Ok(n)) = result {
+                self.read += n;
+            }
+            result
+        }
+    }
+
+    impl AsyncSeek for Counting {
+        fn poll_seek(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            pos: SeekFrom,
+        ) -> Poll<io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_seek(cx, pos)
+        }
+    }
+
+    #[test]
+    fn test_open_entry_by_name_seeks_past_data() {
+        let big = vec![0x55; 100_000];
+        let mut data = b"stub".to_vec();
+        data.extend(&RAR15_SIGNATURE);
+        data.extend(file_block(b"big.bin", &big));
+        data.extend(service_block(b"CMT", b"", b"comment"));
+        data.extend(file_block(b"a.txt", b"roar"));
+        let file = Counting {
+            inner: io::Cursor::new(data),
+            read: 0,
+        };
+
+        let mut archive = block_on(Archive::open(file)).unwrap();
+        let mut reader = block_on(archive.open_entry_by_name("a.txt"))
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"roar");
+        assert!(archive.get_ref().read < 20_000);
+
+        let index = archive.index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0].name, "big.bin");
+        assert_eq!(index[0].header_offset, 11);
+        assert_eq!(index[0].data_offset, 11 + 39);
+        assert_eq!(index[1].unpacked_size, 4);
+        assert_eq!(index[1].crc32, crc::crc32::checksum_ieee(b"roar"));
+
+        // Back to a file that was already passed.
+        let mut reader = block_on(archive.open_entry_by_name("big.bin"))
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, big);
+        assert!(block_on(archive.open_entry_by_name("missing"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_reopens_from_exported_index() {
+        let mut data = b"stub".to_vec();
+        data.extend(&RAR15_SIGNATURE);
+        data.extend(file_block(b"a.txt", b"first"));
+        data.extend(
+            FileBlock {
+                flags: 0x10,
+                ..FileBlock::new(b"b.txt", b"second")
+            }
+            .build(),
+        );
+
+        let mut archive = block_on(Archive::open(io::Cursor::new(data.clone()))).unwrap();
+        let saved = block_on(archive.export_index()).unwrap();
+        assert!(archive.index()[1].solid);
+
+        let file = Counting {
+            inner: io::Cursor::new(data.clone()),
+            read: 0,
+        };
+        let mut archive = block_on(Archive::open_with_index(file, &saved)).unwrap();
+        assert_eq!(archive.index().len(), 2);
+        assert_eq!(archive.index()[1].name, "b.txt");
+        let mut reader = block_on(archive.open_entry_by_name("b.txt"))
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"second");
+        assert!(block_on(archive.open_entry_by_name("c.txt"))
+            .unwrap()
+            .is_none());
+
+        // Changed after the index was made.
+        data[12] ^= 1;
+        assert!(block_on(Archive::open_with_index(
+            io::Cursor::new(data.clone()),
+            &saved
+        ))
+        .is_err());
+        data[12] ^= 1;
+        let mut longer = data.clone();
+        longer.push(0);
+        assert!(block_on(Archive::open_with_index(io::Cursor::new(longer), &saved)).is_err());
+
+        // An index of the same archive read as another format.
+        let mut index = ArchiveIndex::from_bytes(&saved).unwrap();
+        index.format = Format::Rar50;
+        assert!(block_on(Archive::open_with_index(
+            io::Cursor::new(data.clone()),
+            &index.to_bytes()
+        ))
+        .is_err());
+
+        // Entries that point at the wrong file header.
+        let mut index = ArchiveIndex::from_bytes(&saved).unwrap();
+        index.entries[1].name = "c.txt".into();
+        let mut archive = block_on(Archive::open_with_index(
+            io::Cursor::new(data),
+            &index.to_bytes(),
+        ))
+        .unwrap();
+        assert!(block_on(archive.open_entry_by_name("c.txt")).is_err());
+    }
+
+    #[test]
+    fn test_index_keeps_the_version_settings() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(
+            FileBlock {
+                flags: 0x0800,
+                ..FileBlock::new(b"a.txt;1", b"old")
+            }
+            .build(),
+        );
+        data.extend(file_block(b"a.txt", b"new"));
+
+        let mut archive = block_on(Archive::open(io::Cursor::new(data.clone()))).unwrap();
+        archive.set_versions(Versions::All);
+        let saved = block_on(archive.export_index()).unwrap();
+        assert_eq!(archive.index().len(), 2);
+
+        let mut archive =
+            block_on(Archive::open_with_index(io::Cursor::new(data), &saved)).unwrap();
+        assert_eq!(archive.versions, Versions::All);
+        let mut reader = block_on(archive.open_entry_by_name("a.txt;1"))
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"old");
+    }
+
+    #[test]
+    fn test_read_entry_records_index() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"first"));
+        data.extend(file_block(b"b.txt", b"second"));
+
+        let mut archive = block_on(Archive::open(&data[..])).unwrap();
+        while block_on(archive.read_entry()).unwrap().is_some() {}
+        let index = archive.index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[1].name, "b.txt");
+        assert_eq!(index[1].header_offset, 7 + 37 + 5);
+        assert_eq!(index[1].packed_size, 6);
+    }
+
+    #[test]
+    fn test_find_signature_in_buffer() {
+        let data = with_stub(100, &RAR15_SIGNATURE);
+        assert_eq!(
+            find_signature(&data, DEFAULT_SFX_SCAN_LIMIT).unwrap(),
+            (Format::Rar15, 112)
+        );
+        match *find_signature(&data[..103], DEFAULT_SFX_SCAN_LIMIT)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::BufferTooSmall(required) => assert!(required > 103),
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+        assert!(find_signature(&data, 50).is_err());
+    }
+
+    #[test]
+    fn test_open_errors_without_signature() {
+        assert!(block_on(Archive::open(&b"MZ not an archive"[..])).is_err());
+        assert!(block_on(Archive::open(&b"Rar!\x1a\x07"[..])).is_err());
+    }
+}
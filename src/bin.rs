@@ -2,30 +2,193 @@
 #![feature(trait_alias)]
 #![feature(futures_api)]
 
-extern crate num;
-
-#[macro_use]
-extern crate num_derive;
-
-mod block;
-mod error;
-mod traits;
-
 use async_std::{fs, io, prelude::*};
-use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg};
-use error::{Error, Result};
+use clap::{
+    crate_authors, crate_description, crate_name, crate_version, App, AppSettings, Arg, SubCommand,
+};
 use futures::executor::block_on;
 use futures::io::BufReader;
+use roar::error::Result;
+use roar::extract::{ExtractOptions, Extracted, Overwrite};
+use roar::integrity::Outcome;
+use roar::listing::ListEntry;
+use roar::owner::OwnerPolicy;
+use roar::streams::StreamMode;
+use roar::{archive, rar14, sync};
 
 fn main() {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("file").required(true))
+        .arg(
+            Arg::with_name("all-versions")
+                .long("all-versions")
+                .global(true)
+                .help("Include old versions of files archived with version control"),
+        )
+        .arg(
+            Arg::with_name("include-deleted")
+                .long("include-deleted")
+                .global(true)
+                .help("Include files marked deleted, to recover what's left of them"),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the files of an archive")
+                .arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("technical")
+                        .short("v")
+                        .conflicts_with("bare")
+                        .help("Show all details of each file"),
+                )
+                .arg(
+                    Arg::with_name("bare")
+                        .short("b")
+                        .help("Only show the names of the files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Check every file of an archive without extracting anything")
+                .arg(Arg::with_name("file").required(true))
+                .arg(password_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("Write a copy of an archive repaired with its recovery record")
+                .arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("Where to write the repaired copy, fixed.<file> by default"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rebuild")
+                .about("Rebuild missing or damaged volumes from recovery volumes")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The first volume"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Extract files from an archive")
+                .arg(Arg::with_name("file").required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("Directory to extract into"),
+                )
+                .arg(
+                    Arg::with_name("flat")
+                        .long("flat")
+                        .help("Put every file straight into the output, without its path"),
+                )
+                .arg(
+                    Arg::with_name("overwrite")
+                        .long("overwrite")
+                        .takes_value(true)
+                        .possible_values(&["always", "never", "rename", "newer"])
+                        .default_value("never")
+                        .help("What to do with files that are already there"),
+                )
+                .arg(
+                    Arg::with_name("include")
+                        .short("i")
+                        .long("include")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Only extract files matching this pattern"),
+                )
+                .arg(
+                    Arg::with_name("exclude")
+                        .short("x")
+                        .long("exclude")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Don't extract files matching this pattern"),
+                )
+                .arg(password_arg())
+                .arg(
+                    Arg::with_name("streams")
+                        .long("streams")
+                        .takes_value(true)
+                        .possible_values(&["skip", "sidecar", "xattr"])
+                        .default_value("skip")
+                        .help("How to write NTFS alternate data streams of extracted files"),
+                )
+                .arg(
+                    Arg::with_name("owner")
+                        .long("owner")
+                        .takes_value(true)
+                        .possible_values(&["ignore", "name", "id"])
+                        .default_value("ignore")
+                        .help("Give extracted files their archived owner, by name or id, as root"),
+                ),
+        )
         .get_matches();
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let mode = if matches.is_present("technical") {
+            ListMode::Technical
+        } else if matches.is_present("bare") {
+            ListMode::Bare
+        } else {
+            ListMode::Normal
+        };
+        if let Err(e) = run_list(matches, mode) {
+            eprintln!("An error ocurred: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("test") {
+        let passed = run_test(matches).unwrap_or_else(|e| {
+            eprintln!("An error ocurred: {}", e);
+            false
+        });
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if let Some(matches) = matches.subcommand_matches("repair") {
+        let filename = matches.value_of("file").unwrap();
+        let passed = run_repair(filename, matches.value_of("output")).unwrap_or_else(|e| {
+            eprintln!("An error ocurred: {}", e);
+            false
+        });
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if let Some(matches) = matches.subcommand_matches("rebuild") {
+        let filename = matches.value_of("file").unwrap();
+        if let Err(e) = run_rebuild(filename) {
+            eprintln!("An error ocurred: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("extract") {
+        let passed = extract_options(matches)
+            .and_then(|options| run_extract(matches, &options))
+            .unwrap_or_else(|e| {
+                eprintln!("An error ocurred: {}", e);
+                false
+            });
+        std::process::exit(if passed { 0 } else { 1 });
+    }
     let filename = matches.value_of("file").unwrap();
-    if let Err(e) = block_on(run(filename)) {
+    let include_deleted = matches.is_present("include-deleted");
+    if let Err(e) = block_on(run(filename, versions(&matches), include_deleted)) {
         use failure::Fail;
         eprintln!("An error ocurred: {}", e);
         if let Some(bt) = e.backtrace() {
@@ -35,18 +198,362 @@ fn main() {
     }
 }
 
-async fn run(filename: &str) -> Result<()> {
+async fn run(filename: &str, versions: archive::Versions, include_deleted: bool) -> Result<()> {
     println!("Attempting to open file {}", filename);
-    let mut file = BufReader::new(fs::File::open(filename).await?);
+    let file = BufReader::new(fs::File::open(filename).await?);
+    let mut file = archive::Archive::open(file).await?;
+    if file.is_sfx() {
+        println!("Found self-extracting stub of {} bytes", file.sfx_size());
+    }
+    println!("Archive format: {:?}", file.format());
+
+    if file.format() == archive::Format::Rar14 {
+        let main = rar14::MainHeader14::read(&mut file).await?;
+        println!("Found archive header: {:?}", main);
+        while let Some(entry) = rar14::FileHeader14::read(&mut file).await? {
+            println!(
+                "Found file: {} {} ({} bytes)",
+                entry.permissions(),
+                entry.name,
+                entry.unpacked_size
+            );
+            entry.skip_data(&mut file).await?;
+        }
+        return Ok(());
+    }
+
+    file.set_versions(versions);
+    file.set_include_deleted(include_deleted);
+    loop {
+        match file.read_entry().await {
+            Ok(Some(entry)) => {
+                let header = entry.file.header();
+                println!(
+                    "Found file: {} {} ({} bytes)",
+                    header.permissions(),
+                    header.full_name(),
+                    header.unpacked_size()
+                );
+                if entry.file.prefix.is_deleted() {
+                    println!("  deleted");
+                }
+                if let Some(ref link) = entry.link {
+                    println!("  {:?} to: {}", link.kind, link.target);
+                }
+                if let Some(ref owner) = entry.owner {
+                    println!("  owned by: {}", owner);
+                }
+                for stream in &entry.streams {
+                    println!("  with stream: {} ({} bytes)", stream.name, stream.size);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                println!("Stopped reading entries: {}", e);
+                break;
+            }
+        }
+    }
+    if let Some(info) = file.authenticity() {
+        println!("Authenticity information: {}", info);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ListMode {
+    // One line per file.
+    Normal,
+    // Everything known about each file.
+    Technical,
+    // Only the names.
+    Bare,
+}
+
+fn password_arg() -> Arg<'static, 'static> {
+    Arg::with_name("password")
+        .short("p")
+        .long("password")
+        .takes_value(true)
+        .min_values(0)
+        .max_values(1)
+        .help("Password of encrypted files, asked for if left out")
+}
+
+// The password given with -p, read from the terminal if -p has no value.
+fn password(matches: &clap::ArgMatches) -> Result<Option<String>> {
+    if !matches.is_present("password") {
+        return Ok(None);
+    }
+    match matches.value_of("password") {
+        Some(password) => Ok(Some(password.into())),
+        None => read_password().map(Some),
+    }
+}
+
+fn versions(matches: &clap::ArgMatches) -> archive::Versions {
+    if matches.is_present("all-versions") {
+        archive::Versions::All
+    } else {
+        archive::Versions::Latest
+    }
+}
+
+// Open the archive given to a subcommand, with the file listing options it
+// was given.
+fn open_archive(
+    matches: &clap::ArgMatches,
+) -> Result<sync::Archive<std::io::BufReader<std::fs::File>>> {
+    let file = std::io::BufReader::new(std::fs::File::open(matches.value_of("file").unwrap())?);
+    let mut archive = sync::Archive::open(file)?;
+    archive.set_versions(versions(matches));
+    archive.set_include_deleted(matches.is_present("include-deleted"));
+    Ok(archive)
+}
+
+fn run_list(matches: &clap::ArgMatches, mode: ListMode) -> Result<()> {
+    let entries = open_archive(matches)?.list()?;
+    match mode {
+        ListMode::Bare => {
+            for entry in &entries {
+                println!("{}", entry.name);
+            }
+        }
+        ListMode::Technical => {
+            for entry in &entries {
+                print_technical(entry);
+            }
+        }
+        ListMode::Normal => {
+            println!(
+                " {:<10} {:>12} {:>12} {:>5}  {:<19}  {:<8}  {:<7}  {:<7}  Name",
+                "Attributes", "Size", "Packed", "Ratio", "Date", "CRC", "Method", "OS"
+            );
+            for entry in &entries {
+                // Encrypted files are marked like unrar does.
+                println!(
+                    "{}{:<10} {:>12} {:>12} {:>5}  {:<19}  {:<8.8}  {:<7}  {:<7}  {}",
+                    if entry.encrypted { '*' } else { ' ' },
+                    entry.permissions.to_string(),
+                    or_unknown(entry.unpacked_size),
+                    entry.packed_size,
+                    entry.ratio().map_or("-".into(), |r| format!("{}%", r)),
+                    or_unknown(entry.mtime),
+                    or_unknown(entry.hash),
+                    entry.method_name(),
+                    entry.host_os.as_ref().map_or("Unknown", |os| &os[..]),
+                    entry.name
+                );
+            }
+            let unpacked: u64 = entries.iter().filter_map(|e| e.unpacked_size).sum();
+            let packed: u64 = entries.iter().map(|e| e.packed_size).sum();
+            println!(
+                " {:<10} {:>12} {:>12}  {} files",
+                "",
+                unpacked,
+                packed,
+                entries.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_technical(entry: &ListEntry) {
+    println!("        Name: {}", entry.name);
+    println!(
+        "        Type: {}",
+        if entry.directory { "Directory" } else { "File" }
+    );
+    println!("        Size: {}", or_unknown(entry.unpacked_size));
+    println!("      Packed: {}", entry.packed_size);
+    if let Some(ratio) = entry.ratio() {
+        println!("       Ratio: {}%", ratio);
+    }
+    println!("    Modified: {}", or_unknown(entry.mtime));
+    println!("  Attributes: {}", entry.permissions);
+    if let Some(hash) = entry.hash {
+        println!("{:>12}: {}", hash.hash_type().to_string(), hash);
+    }
+    println!("     Host OS: {}", or_unknown(entry.host_os.as_ref()));
+    println!("      Method: {}", entry.method_name());
+    if let Some(size) = entry.dictionary_size {
+        println!("  Dictionary: {} KB", size / 1024);
+    }
+    println!(
+        "     Version: RAR {}.{} to unpack",
+        entry.version_needed / 10,
+        entry.version_needed % 10
+    );
+    println!("       Solid: {}", if entry.solid { "yes" } else { "no" });
+    println!(
+        "   Encrypted: {}",
+        if entry.encrypted { "yes" } else { "no" }
+    );
+    println!();
+}
+
+fn or_unknown<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or("?".into(), |v| v.to_string())
+}
+
+// Test every file of the archive and return whether it's intact.
+fn run_test(matches: &clap::ArgMatches) -> Result<bool> {
+    let password = password(matches)?;
+    let report = open_archive(matches)?.test_with_password(password.as_ref().map(|p| &p[..]))?;
+    for entry in &report.entries {
+        match entry.outcome {
+            Outcome::Passed => println!("Testing {:<48} OK", entry.name),
+            Outcome::Failed(ref why) => println!("Testing {:<48} FAILED: {}", entry.name, why),
+            Outcome::Skipped(ref why) => println!("Testing {:<48} skipped: {}", entry.name, why),
+        }
+    }
+    if let Some(ref error) = report.error {
+        println!("Could not read the whole archive: {}", error);
+    }
+    if report.data_crc_matches == Some(false) {
+        println!("Archive data CRC mismatch");
+    }
+    if report.passed() {
+        println!("All OK");
+    } else {
+        println!(
+            "{} of {} files failed",
+            report.failures(),
+            report.entries.len()
+        );
+    }
+    Ok(report.passed())
+}
 
-    match block::read_block(&mut file).await? {
-        block::Block::Marker => println!("Found marker block!"),
-        block::Block::Archive(ref x) => println!("Found archive header: {:?}", x),
-        x => println!("unimplemented: {:?}", x),
+// Write a repaired copy of the archive and return whether all damage could
+// be repaired.
+fn run_repair(filename: &str, output: Option<&str>) -> Result<bool> {
+    let path = std::path::Path::new(filename);
+    let output = match output {
+        Some(output) => output.into(),
+        None => {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!("fixed.{}", name))
+        }
+    };
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&output)?);
+    let report = sync::Archive::open(file)?.repair(&mut out)?;
+    std::io::Write::flush(&mut out)?;
+    if report.damaged.is_empty() {
+        println!("No damage found");
+    } else {
+        println!(
+            "Repaired {} of {} damaged sectors",
+            report.repaired.len(),
+            report.damaged.len()
+        );
+    }
+    println!("Written to {}", output.display());
+    Ok(report.is_complete())
+}
+
+// Rebuild the volumes of a set from its .rev files. Fails, leaving the set
+// alone, unless every missing or damaged volume can be rebuilt.
+fn run_rebuild(filename: &str) -> Result<()> {
+    let report = sync::rebuild_volumes(std::path::Path::new(filename))?;
+    if report.rebuilt.is_empty() {
+        println!("No damaged volumes found");
+    }
+    for path in &report.rebuilt {
+        println!("Rebuilt {}", path.display());
+    }
+    for path in &report.kept {
+        println!("Damaged copy kept as {}", path.display());
     }
     Ok(())
 }
 
+fn extract_options(matches: &clap::ArgMatches) -> Result<ExtractOptions> {
+    let mut options = ExtractOptions::new(matches.value_of("output").unwrap());
+    options.full_paths = !matches.is_present("flat");
+    options.overwrite = Overwrite::from_name(matches.value_of("overwrite").unwrap()).unwrap();
+    let patterns = |name| {
+        matches
+            .values_of(name)
+            .map_or(Vec::new(), |v| v.map(String::from).collect())
+    };
+    options.filter.include = patterns("include");
+    options.filter.exclude = patterns("exclude");
+    options.streams = StreamMode::from_name(matches.value_of("streams").unwrap()).unwrap();
+    options.owner.policy = OwnerPolicy::from_name(matches.value_of("owner").unwrap()).unwrap();
+    options.password = password(matches)?;
+    Ok(options)
+}
+
+// Extract the archive and return whether every file made it.
+fn run_extract(matches: &clap::ArgMatches, options: &ExtractOptions) -> Result<bool> {
+    let report = open_archive(matches)?.extract_all(options)?;
+    for entry in &report.entries {
+        match entry.outcome {
+            Extracted::Written(_) => println!("Extracting {:<45} OK", entry.name),
+            Extracted::Kept(_) => println!("Extracting {:<45} kept existing file", entry.name),
+            Extracted::Failed(ref why) => {
+                println!("Extracting {:<45} FAILED: {}", entry.name, why)
+            }
+            Extracted::Skipped(ref why) => {
+                println!("Extracting {:<45} skipped: {}", entry.name, why)
+            }
+        }
+    }
+    if let Some(ref error) = report.error {
+        println!("Could not read the whole archive: {}", error);
+    }
+    if report.passed() {
+        println!("All OK");
+    } else {
+        println!(
+            "{} of {} files failed",
+            report.failures(),
+            report.entries.len()
+        );
+    }
+    Ok(report.passed())
+}
+
+// Ask for the password on the terminal, without echoing it.
+fn read_password() -> Result<String> {
+    eprint!("Password: ");
+    let echo_off = set_echo(false);
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if echo_off {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).into())
+}
+
+// Turn echoing of the terminal on stdin on or off, and return whether it
+// could, which it can't when stdin isn't a terminal.
+#[cfg(unix)]
+fn set_echo(on: bool) -> bool {
+    unsafe {
+        let mut term: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+            return false;
+        }
+        if on {
+            term.c_lflag |= libc::ECHO;
+        } else {
+            term.c_lflag &= !libc::ECHO;
+        }
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) == 0
+    }
+}
+
+#[cfg(not(unix))]
+fn set_echo(_on: bool) -> bool {
+    false
+}
+
 //#[macro_use]
 //extern crate num_derive;
 //extern crate num_traits;
@@ -59,8 +566,7 @@ async fn run(filename: &str) -> Result<()> {
 //
 //use clap::{crate_authors, crate_description, crate_name, crate_version, App, Arg, ArgMatches};
 //use crc::Hasher16;
-//use error::{Error, Result};
-//use std::fs;
+////use std::fs;
 //use std::io;
 //use std::io::Read;
 //use tokio::prelude::*;
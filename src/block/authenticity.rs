@@ -0,0 +1,169 @@
+use super::cursor::BufferCursor;
+use crate::block::prefix::BlockHeaderCommon;
+use crate::dostime::DosDateTime;
+use crate::error::{Error, Result};
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+// Size of the fields following the common header in each block.
+const AV_FIELDS_SIZE: u16 = 7;
+const SIGN_FIELDS_SIZE: u16 = 8;
+
+// Authenticity verification block (HeadType::OldAuthenticityInformation).
+// The signature itself can only be checked by WinRAR, so it's kept as is.
+#[derive(Debug)]
+pub struct AvHeader {
+    pub prefix: BlockHeaderCommon,
+    pub unpack_version: u8,
+    pub method: u8,
+    pub av_version: u8,
+    pub av_info_crc: u32,
+    pub signature: Vec<u8>,
+}
+
+impl AvHeader {
+    pub async fn parse(prefix: BlockHeaderCommon, f: &mut impl AsyncFile) -> Result<AvHeader> {
+        let rest = fields_size(&prefix, AV_FIELDS_SIZE)?;
+        let buf = prefix.read_rest(f).await?;
+        let mut cursor = BufferCursor::new(&buf);
+
+        let unpack_version = cursor.read(1)?[0];
+        let method = cursor.read(1)?[0];
+        let av_version = cursor.read(1)?[0];
+        let av_info_crc = LittleEndian::read_u32(cursor.read(4)?);
+        let signature = cursor.read(usize::from(rest))?.to_vec();
+        Ok(AvHeader {
+            prefix,
+            unpack_version,
+            method,
+            av_version,
+            av_info_crc,
+            signature,
+        })
+    }
+}
+
+// Authenticity information block (HeadType::OldAuthenticityInformation2),
+// naming who created the archive and when.
+#[derive(Debug)]
+pub struct SignHeader {
+    pub prefix: BlockHeaderCommon,
+    pub creation_time: u32,
+    pub archive_name: String,
+    pub creator: String,
+}
+
+impl SignHeader {
+    pub async fn parse(prefix: BlockHeaderCommon, f: &mut impl AsyncFile) -> Result<SignHeader> {
+        let rest = fields_size(&prefix, SIGN_FIELDS_SIZE)?;
+        let buf = prefix.read_rest(f).await?;
+        let mut cursor = BufferCursor::new(&buf);
+
+        let creation_time = LittleEndian::read_u32(cursor.read(4)?);
+        let archive_name_size = LittleEndian::read_u16(cursor.read(2)?);
+        let creator_size = LittleEndian::read_u16(cursor.read(2)?);
+        if u32::from(archive_name_size) + u32::from(creator_size) > u32::from(rest) {
+            return Err(Error::bad_block(
+                "Authenticity names overrun the header".into(),
+            ));
+        }
+        let archive_name = cursor.read(usize::from(archive_name_size))?;
+        let creator = cursor.read(usize::from(creator_size))?;
+
+        Ok(SignHeader {
+            prefix,
+            creation_time,
+            archive_name: String::from_utf8_lossy(archive_name).into_owned(),
+            creator: String::from_utf8_lossy(creator).into_owned(),
+        })
+    }
+
+    pub fn created(&self) -> DosDateTime {
+        DosDateTime::from_u32(self.creation_time)
+    }
+}
+
+// Size of what follows `fields` bytes of fixed fields in the header.
+fn fields_size(prefix: &BlockHeaderCommon, fields: u16) -> Result<u16> {
+    prefix
+        .header_size()
+        .checked_sub(prefix.prefix_size() + fields)
+        .ok_or_else(|| {
+            Error::bad_block(format!(
+                "Header size {} is too small for {:?}",
+                prefix.header_size(),
+                prefix.header_type
+            ))
+        })
+}
+
+// What the authenticity blocks of an archive say, gathered from whichever of
+// them it has.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Authenticity {
+    pub av_version: Option<u8>,
+    pub creator: Option<String>,
+    pub archive_name: Option<String>,
+    pub created: Option<DosDateTime>,
+}
+
+impl Authenticity {
+    pub fn add_av(&mut self, av: &AvHeader) {
+        self.av_version = Some(av.av_version);
+    }
+
+    pub fn add_sign(&mut self, sign: &SignHeader) {
+        self.creator = Some(sign.creator.clone());
+        self.archive_name = Some(sign.archive_name.clone());
+        self.created = Some(sign.created());
+    }
+}
+
+impl fmt::Display for Authenticity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "created by {}",
+            self.creator.as_ref().map_or("unknown", |c| c.as_str())
+        )?;
+        if let Some(ref created) = self.created {
+            write!(f, " on {}", created)?;
+        }
+        if let Some(ref name) = self.archive_name {
+            write!(f, " as {}", name)?;
+        }
+        if let Some(version) = self.av_version {
+            write!(f, " (AV version {})", version)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_without_sign_block() {
+        let info = Authenticity {
+            av_version: Some(2),
+            ..Authenticity::default()
+        };
+        assert_eq!(info.to_string(), "created by unknown (AV version 2)");
+    }
+
+    #[test]
+    fn test_display_with_sign_block() {
+        let info = Authenticity {
+            av_version: None,
+            creator: Some("Erich".into()),
+            archive_name: Some("roar.rar".into()),
+            created: Some(DosDateTime::from_u32(0x3173_b82b)),
+        };
+        assert_eq!(
+            info.to_string(),
+            "created by Erich on 2004-11-19 23:01:22 as roar.rar"
+        );
+    }
+}
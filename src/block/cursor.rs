@@ -27,6 +27,17 @@ impl<'a> BufferCursor<'a> {
         Ok(ret)
     }
 
+    pub fn peek(&self, num: usize) -> Result<&'a [u8]> {
+        if self.pos + num > self.buf.len() {
+            return Err(Error::buffer_too_small(self.pos + num));
+        }
+        Ok(&self.buf[self.pos..self.pos + num])
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
     pub fn rest(self) -> &'a [u8] {
         &self.buf[self.pos..]
     }
@@ -65,6 +76,12 @@ impl<T: AsyncRead + Unpin> AsyncCRC16Cursor<T> {
         Ok(LittleEndian::read_u16(&buf))
     }
 
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        self.file.read_exact(buf).await?;
+        self.digest.write(buf);
+        Ok(())
+    }
+
     pub async fn read_u32(&mut self) -> Result<u32> {
         let mut buf = [0; 4];
         async_std::io::Read::read_exact(&mut self.file, &mut buf).await?;
@@ -0,0 +1,124 @@
+use super::cursor::BufferCursor;
+use crate::block::prefix::BlockHeaderCommon;
+use crate::error::Result;
+use crate::traits::AsyncFile;
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+
+bitflags! {
+    pub struct EndFlags: u16 {
+        // The archive continues in another volume.
+        const NEXT_VOLUME = 0x0001;
+        const DATA_CRC = 0x0002;
+        // Space reserved at the end of the header for a REV file's data.
+        const REV_SPACE = 0x0004;
+        const VOLUME_NUMBER = 0x0008;
+    }
+}
+
+// End of archive block (HeadType::Terminator), written by RAR 3.x and later.
+// Anything after it isn't part of the archive.
+#[derive(Debug)]
+pub struct EndHeader {
+    pub prefix: BlockHeaderCommon,
+    pub flags: EndFlags,
+    // CRC32 of the archive from the signature up to this block.
+    pub data_crc: Option<u32>,
+    // Number of this volume, counting from 0.
+    pub volume_number: Option<u16>,
+    // Whether `data_crc` matched the archive as it was read, filled in by
+    // `Archive::read_block`. `None` when it couldn't be checked.
+    pub data_crc_matches: Option<bool>,
+}
+
+impl EndHeader {
+    pub async fn parse(prefix: BlockHeaderCommon, f: &mut impl AsyncFile) -> Result<EndHeader> {
+        // The fields are optional and followed by the REV space, if any.
+        let buf = prefix.read_rest(f).await?;
+
+        let flags = EndFlags::from_bits_truncate(prefix.flags());
+        let mut fields = BufferCursor::new(&buf);
+        let data_crc = if flags.contains(EndFlags::DATA_CRC) {
+            Some(LittleEndian::read_u32(fields.read(4)?))
+        } else {
+            None
+        };
+        let volume_number = if flags.contains(EndFlags::VOLUME_NUMBER) {
+            Some(LittleEndian::read_u16(fields.read(2)?))
+        } else {
+            None
+        };
+        Ok(EndHeader {
+            prefix,
+            flags,
+            data_crc,
+            volume_number,
+            data_crc_matches: None,
+        })
+    }
+
+    pub fn has_next_volume(&self) -> bool {
+        self.flags.contains(EndFlags::NEXT_VOLUME)
+    }
+
+    pub fn has_rev_space(&self) -> bool {
+        self.flags.contains(EndFlags::REV_SPACE)
+    }
+
+    // Volumes keep the CRC of the part of the split file they hold instead
+    // of the archive data.
+    pub fn is_volume(&self) -> bool {
+        self.has_next_volume() || self.volume_number.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::with_header_crc;
+    use futures::executor::block_on;
+
+    fn parse(buf: &[u8]) -> Result<EndHeader> {
+        let mut f = buf;
+        let prefix = block_on(BlockHeaderCommon::read_from_file(&mut f)).unwrap();
+        let end = block_on(EndHeader::parse(prefix, &mut f));
+        assert!(f.is_empty());
+        end
+    }
+
+    #[test]
+    fn test_parses_plain_end() {
+        let end = parse(&[0xc4, 0x3d, 0x7b, 0x00, 0x40, 0x07, 0x00]).unwrap();
+        assert_eq!(end.data_crc, None);
+        assert_eq!(end.volume_number, None);
+        assert!(!end.has_next_volume());
+    }
+
+    #[test]
+    fn test_parses_all_fields() {
+        // Next volume, data CRC, REV space and volume number.
+        let mut buf = vec![0, 0, 0x7b, 0x0f, 0x40, 20, 0];
+        buf.extend(&0xdead_beef_u32.to_le_bytes());
+        buf.extend(&3u16.to_le_bytes());
+        buf.extend(&[0; 7]);
+        let end = parse(&with_header_crc(buf)).unwrap();
+        assert_eq!(end.data_crc, Some(0xdead_beef));
+        assert_eq!(end.volume_number, Some(3));
+        assert!(end.has_next_volume());
+        assert!(end.has_rev_space());
+    }
+
+    #[test]
+    fn test_rejects_truncated_fields() {
+        let buf = with_header_crc(vec![0, 0, 0x7b, 0x0a, 0x40, 11, 0, 1, 2, 3, 4]);
+        assert!(parse(&buf).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_header_crc() {
+        let mut buf = with_header_crc(vec![0, 0, 0x7b, 0x08, 0x40, 9, 0, 3, 0]);
+        assert_eq!(parse(&buf).unwrap().volume_number, Some(3));
+        buf[7] = 4;
+        assert!(parse(&buf).is_err());
+    }
+}
@@ -1,11 +1,30 @@
 use super::cursor::BufferCursor;
+use super::skip;
+use crate::block::prefix::{BlockHeaderCommon, HeadType};
+use crate::dostime::DosDateTime;
 use crate::error::{Error, Result};
+use crate::hash::{FileHash, VerifyReader};
+use crate::links::{Link, UNIX_SYMLINK};
+use crate::owner::{UnixOwner, OWNER_SERVICE};
+use crate::permissions::Permissions;
+use crate::recovery::RECOVERY_SERVICE;
+use crate::streams::{utf16_stream_name, NamedStream, STREAM_SERVICE};
+use crate::traits::AsyncFile;
+use crate::unpack::{EntryReader, Store};
 use bitflags::bitflags;
-use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use byteorder::{ByteOrder, LittleEndian};
 use num::FromPrimitive;
 
+// Size of the block prefix and of the fixed file header fields after it.
+const BLOCK_PREFIX_SIZE: usize = 7;
+const FILE_PREFIX_SIZE: usize = 25;
+
+// Largest stream or link target read into memory. The data of bigger ones is
+// skipped.
+const MAX_STORED_DATA: u64 = 16 << 20;
+
 #[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
-enum OperatingSystem {
+pub enum OperatingSystem {
     Dos = 0x0,
     OS2 = 0x1,
     Windows = 0x2,
@@ -15,13 +34,13 @@ enum OperatingSystem {
 }
 
 impl OperatingSystem {
-    fn from_u8(that: u8) -> Option<OperatingSystem> {
+    pub fn from_u8(that: u8) -> Option<OperatingSystem> {
         FromPrimitive::from_u8(that)
     }
 }
 
 #[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
-enum PackingMethod {
+pub enum PackingMethod {
     Store = 0x30,
     Fastest = 0x31,
     Fast = 0x32,
@@ -31,42 +50,49 @@ enum PackingMethod {
 }
 
 impl PackingMethod {
-    fn from_u8(that: u8) -> Option<PackingMethod> {
+    pub fn from_u8(that: u8) -> Option<PackingMethod> {
         FromPrimitive::from_u8(that)
     }
 }
 
 bitflags! {
-    struct FileFlags: u16 {
-        const ContinuedFromPreviousVolume = 0b0000_0000_0000_0001;
-        const ContinuedToNextVolume = 0b0000_0000_0000_0010;
-        const Encrypted = 0b0000_0000_0000_0100;
-        const CommentPresent = 0b0000_0000_0000_1000;
-        const Solid = 0b0000_0000_0001_0000;
-        const Dictionary1 = 0b0000_0000_0010_0000;
-        const Dictionary2 = 0b0000_0000_0100_0000;
-        const Dictionary3 = 0b0000_0000_1000_0000;
-        const HighFields = 0b0000_0001_0000_0000;
+    pub struct FileFlags: u16 {
+        const CONTINUED_FROM_PREVIOUS_VOLUME = 0b0000_0000_0000_0001;
+        const CONTINUED_TO_NEXT_VOLUME = 0b0000_0000_0000_0010;
+        const ENCRYPTED = 0b0000_0000_0000_0100;
+        const COMMENT_PRESENT = 0b0000_0000_0000_1000;
+        const SOLID = 0b0000_0000_0001_0000;
+        const DICTIONARY1 = 0b0000_0000_0010_0000;
+        const DICTIONARY2 = 0b0000_0000_0100_0000;
+        const DICTIONARY3 = 0b0000_0000_1000_0000;
+        const HIGH_FIELDS = 0b0000_0001_0000_0000;
 
         // FILE_NAME contains both usual and encoded Unicode name separated by
         // zero. In this case NAME_SIZE field is equal to the length of usual
         // name plus encoded Unicode name plus 1. If this flag is present,
         // but FILE_NAME does not contain zero bytes, it means that file name
         // is encoded using UTF-8.
-        const UnicodeFilename = 0b0000_0010_0000_0000;
+        const UNICODE_FILENAME = 0b0000_0010_0000_0000;
 
-        const Salted = 0b0000_0100_0000_0000;
-        const Versioned = 0b0000_1000_0000_0000;
-        const ExtTime = 0b0001_0000_0000_0000;
+        const SALTED = 0b0000_0100_0000_0000;
+        const VERSIONED = 0b0000_1000_0000_0000;
+        const EXT_TIME = 0b0001_0000_0000_0000;
 
         // This flag should always be set
-        const Always = 0b1000_0000_0000_0000;
+        const ALWAYS = 0b1000_0000_0000_0000;
+
+        // All three dictionary bits set mark a directory.
+        const DIRECTORY = 0b0000_0000_1110_0000;
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct FilePrefix<'a> {
-    block_prefix: BlockPrefix<'a>,
+    // HEAD_CRC        2
+    // HEAD_TYPE       1
+    // HEAD_FLAGS      2
+    // HEAD_SIZE       2
+    head: &'a [u8],
 
     // PACK_SIZE       4                Compressed file size
     // UNP_SIZE        4                Uncompressed file size
@@ -85,34 +111,26 @@ impl<'a> FilePrefix<'a> {
         let mut cursor = BufferCursor::new(buf);
         let fp = FilePrefix::from_cursor(&mut cursor)?;
         Ok((fp, cursor.rest()))
-        // let (prefix, prefix_rest) = BlockPrefix::from_buf(buf)?;
-        // if prefix_rest.len() < 25 {
-        //     return Err(Error::buffer_too_small(buf.len() - prefix_rest.len() + 25));
-        // }
-        // Ok((
-        //     FilePrefix {
-        //         block_prefix: prefix,
-        //         buf: &prefix_rest[0..25],
-        //     },
-        //     &prefix_rest[25..],
-        // ))
     }
 
     pub fn from_cursor(cursor: &mut BufferCursor<'a>) -> Result<FilePrefix<'a>> {
-        let prefix = BlockPrefix::from_cursor(cursor)?;
-        let buf = cursor.read(25)?;
-        Ok(FilePrefix {
-            block_prefix: prefix,
-            buf: buf,
-        })
+        let head = cursor.read(BLOCK_PREFIX_SIZE)?;
+        let buf = cursor.read(FILE_PREFIX_SIZE)?;
+        Ok(FilePrefix { head, buf })
     }
 
-    fn prefix(&self) -> BlockPrefix {
-        self.block_prefix
+    pub fn flags(&self) -> FileFlags {
+        FileFlags::from_bits_truncate(LittleEndian::read_u16(&self.head[3..5]))
     }
 
-    fn flags(&self) -> FileFlags {
-        FileFlags::from_bits_truncate(self.prefix().flags())
+    // Service headers share the layout of file headers, with the service
+    // type, e.g. "STM" for NTFS streams, in place of the file name.
+    pub fn is_service(&self) -> bool {
+        self.head[2] == HeadType::SubBlock as u8
+    }
+
+    pub fn header_size(&self) -> u16 {
+        LittleEndian::read_u16(&self.head[5..7])
     }
 
     fn low_compress_size(&self) -> u32 {
@@ -123,32 +141,36 @@ impl<'a> FilePrefix<'a> {
         LittleEndian::read_u32(&self.buf[4..8])
     }
 
-    fn creation_os(&self) -> Option<OperatingSystem> {
+    pub fn creation_os(&self) -> Option<OperatingSystem> {
         OperatingSystem::from_u8(self.buf[8])
     }
 
-    fn file_crc32(&self) -> u32 {
+    pub fn file_crc32(&self) -> u32 {
         LittleEndian::read_u32(&self.buf[9..9 + 4])
     }
 
-    fn ftime_raw(&self) -> u32 {
+    pub fn ftime_raw(&self) -> u32 {
         LittleEndian::read_u32(&self.buf[13..13 + 4])
     }
 
     // This might need an enum later as well...
-    fn unpack_version(&self) -> u8 {
+    pub fn unpack_version(&self) -> u8 {
         self.buf[17]
     }
 
-    fn packing_method(&self) -> Option<PackingMethod> {
-        PackingMethod::from_u8(self.buf[18])
+    pub fn raw_packing_method(&self) -> u8 {
+        self.buf[18]
+    }
+
+    pub fn packing_method(&self) -> Option<PackingMethod> {
+        PackingMethod::from_u8(self.raw_packing_method())
     }
 
     fn name_size(&self) -> u16 {
         LittleEndian::read_u16(&self.buf[19..21])
     }
 
-    fn file_attrs(&self) -> u32 {
+    pub fn file_attrs(&self) -> u32 {
         LittleEndian::read_u32(&self.buf[21..])
     }
 }
@@ -157,7 +179,7 @@ fn parse_header_highsize<'a>(
     cursor: &mut BufferCursor<'a>,
     flags: &FileFlags,
 ) -> Result<Option<&'a [u8]>> {
-    if flags.contains(FileFlags::HighFields) {
+    if flags.contains(FileFlags::HIGH_FIELDS) {
         Ok(Some(cursor.read(8)?))
     } else {
         Ok(None)
@@ -168,13 +190,120 @@ fn parse_header_salt<'a>(
     cursor: &mut BufferCursor<'a>,
     flags: &FileFlags,
 ) -> Result<Option<&'a [u8]>> {
-    if flags.contains(FileFlags::Salted) {
+    if flags.contains(FileFlags::SALTED) {
         Ok(Some(cursor.read(8)?))
     } else {
         Ok(None)
     }
 }
 
+// EXT_TIME is a 16 bit flags field followed by, for each of mtime, ctime,
+// atime and arctime, an optional DOS time (except for mtime, which is in
+// FTIME) and up to 3 bytes of extra precision.
+fn parse_header_ext_time<'a>(
+    cursor: &mut BufferCursor<'a>,
+    flags: &FileFlags,
+) -> Result<Option<&'a [u8]>> {
+    if !flags.contains(FileFlags::EXT_TIME) {
+        return Ok(None);
+    }
+    let time_flags = LittleEndian::read_u16(cursor.peek(2)?);
+    let mut size = 2;
+    for i in 0..4 {
+        let mode = time_flags >> ((3 - i) * 4);
+        if mode & 8 == 0 {
+            continue;
+        }
+        if i != 0 {
+            size += 4;
+        }
+        size += usize::from(mode & 3);
+    }
+    Ok(Some(cursor.read(size)?))
+}
+
+// Decode a file name stored with FileFlags::UNICODE_FILENAME: either UTF-8, or
+// a plain name and, after a zero byte, RAR's compact encoding of the UTF-16
+// name relative to it.
+fn decode_unicode_name(raw: &[u8]) -> String {
+    let zero = match raw.iter().position(|&b| b == 0) {
+        Some(zero) => zero,
+        None => return String::from_utf8_lossy(raw).into_owned(),
+    };
+    let (name, enc) = (&raw[..zero], &raw[zero + 1..]);
+
+    let mut out: Vec<u16> = Vec::with_capacity(name.len());
+    let mut pos = 0;
+    let high_byte = match enc.get(pos) {
+        Some(&b) => u16::from(b),
+        None => return String::from_utf8_lossy(name).into_owned(),
+    };
+    pos += 1;
+    let mut flags = 0u8;
+    let mut flag_bits = 0;
+    while pos < enc.len() {
+        if flag_bits == 0 {
+            flags = enc[pos];
+            pos += 1;
+            flag_bits = 8;
+        }
+        match flags >> 6 {
+            0 if pos < enc.len() => {
+                out.push(u16::from(enc[pos]));
+                pos += 1;
+            }
+            1 if pos < enc.len() => {
+                out.push(u16::from(enc[pos]) | (high_byte << 8));
+                pos += 1;
+            }
+            2 if pos + 1 < enc.len() => {
+                out.push(LittleEndian::read_u16(&enc[pos..pos + 2]));
+                pos += 2;
+            }
+            3 if pos < enc.len() => {
+                let length = enc[pos];
+                pos += 1;
+                if length & 0x80 != 0 {
+                    let correction = match enc.get(pos) {
+                        Some(&b) => b,
+                        None => break,
+                    };
+                    pos += 1;
+                    for _ in 0..(length & 0x7f) + 2 {
+                        match name.get(out.len()) {
+                            Some(&b) => {
+                                out.push(u16::from(b.wrapping_add(correction)) | (high_byte << 8))
+                            }
+                            None => break,
+                        }
+                    }
+                } else {
+                    for _ in 0..length + 2 {
+                        match name.get(out.len()) {
+                            Some(&b) => out.push(u16::from(b)),
+                            None => break,
+                        }
+                    }
+                }
+            }
+            _ => break,
+        }
+        flags <<= 2;
+        flag_bits -= 2;
+    }
+    String::from_utf16_lossy(&out)
+}
+
+// Split `name;N` into the name and the version number.
+fn split_version(name: &str) -> (&str, Option<u32>) {
+    if let Some(pos) = name.rfind(';') {
+        if let Ok(version) = name[pos + 1..].parse() {
+            return (&name[..pos], Some(version));
+        }
+    }
+    (name, None)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct FileHeader<'a> {
     prefix: FilePrefix<'a>,
@@ -187,6 +316,10 @@ pub struct FileHeader<'a> {
     // holds file_name
     file_name: &'a [u8],
 
+    // Service headers keep data specific to their type after the name, like
+    // the name of an NTFS stream.
+    sub_data: &'a [u8],
+
     // SALT 8
     // holds salt
     salt: Option<&'a [u8]>,
@@ -203,27 +336,289 @@ impl<'a> FileHeader<'a> {
     }
 
     pub fn from_cursor(cursor: &mut BufferCursor<'a>) -> Result<FileHeader<'a>> {
+        let start = cursor.remaining();
         let prefix = FilePrefix::from_cursor(cursor)?;
         let flags = prefix.flags();
         let high_size = parse_header_highsize(cursor, &flags)?;
         let name = cursor.read(usize::from(prefix.name_size()))?;
-        let salt = parse_header_salt(cursor, &flags)?;
 
-        // TODO: ext_time
+        // Service headers keep whatever lies between the name and the salt
+        // as sub data.
+        let sub_data = if prefix.is_service() {
+            let mut known = start - cursor.remaining();
+            if flags.contains(FileFlags::SALTED) {
+                known += 8;
+            }
+            cursor.read(usize::from(prefix.header_size()).saturating_sub(known))?
+        } else {
+            &[]
+        };
+
+        let salt = parse_header_salt(cursor, &flags)?;
+        let ext_time = parse_header_ext_time(cursor, &flags)?;
 
         Ok(FileHeader {
-            prefix: prefix,
-            high_size: high_size,
+            prefix,
+            high_size,
             file_name: name,
-            salt: salt,
-            ext_time: None,
+            sub_data,
+            salt,
+            ext_time,
+        })
+    }
+
+    pub fn prefix(&self) -> &FilePrefix<'a> {
+        &self.prefix
+    }
+
+    pub fn flags(&self) -> FileFlags {
+        self.prefix.flags()
+    }
+
+    pub fn packed_size(&self) -> u64 {
+        let high = self
+            .high_size
+            .map_or(0, |h| LittleEndian::read_u32(&h[0..4]));
+        u64::from(high) << 32 | u64::from(self.prefix.low_compress_size())
+    }
+
+    pub fn unpacked_size(&self) -> u64 {
+        let high = self
+            .high_size
+            .map_or(0, |h| LittleEndian::read_u32(&h[4..8]));
+        u64::from(high) << 32 | u64::from(self.prefix.low_uncompress_size())
+    }
+
+    pub fn raw_name(&self) -> &'a [u8] {
+        self.file_name
+    }
+
+    // The name as stored, including the version suffix of old versions.
+    pub fn full_name(&self) -> String {
+        if self.flags().contains(FileFlags::UNICODE_FILENAME) {
+            decode_unicode_name(self.file_name)
+        } else {
+            String::from_utf8_lossy(self.file_name).into_owned()
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let name = self.full_name();
+        match split_version(&name) {
+            (base, Some(_)) if self.flags().contains(FileFlags::VERSIONED) => base.to_string(),
+            _ => name,
+        }
+    }
+
+    // Old versions of a file kept with FileFlags::VERSIONED are named
+    // `name;N`. The current version has no number.
+    pub fn version(&self) -> Option<u32> {
+        if !self.flags().contains(FileFlags::VERSIONED) {
+            return None;
+        }
+        split_version(&self.full_name()).1
+    }
+
+    pub fn sub_data(&self) -> &'a [u8] {
+        self.sub_data
+    }
+
+    pub fn salt(&self) -> Option<&'a [u8]> {
+        self.salt
+    }
+
+    pub fn ext_time(&self) -> Option<&'a [u8]> {
+        self.ext_time
+    }
+
+    pub fn mtime(&self) -> DosDateTime {
+        DosDateTime::from_u32(self.prefix.ftime_raw())
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags().contains(FileFlags::DIRECTORY)
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags().contains(FileFlags::ENCRYPTED)
+    }
+
+    // Whether unpacking depends on the files before this one.
+    pub fn is_solid(&self) -> bool {
+        self.flags().contains(FileFlags::SOLID)
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.prefix.packing_method() == Some(PackingMethod::Store)
+    }
+
+    // Size of the dictionary the file was packed with, 64 KB to 4 MB.
+    // Directories don't have one.
+    pub fn dictionary_size(&self) -> Option<u64> {
+        if self.is_directory() {
+            return None;
+        }
+        let bits = (self.flags() & FileFlags::DIRECTORY).bits() >> 5;
+        Some(0x1_0000 << bits)
+    }
+
+    pub fn hash(&self) -> FileHash {
+        FileHash::Crc32(self.prefix.file_crc32())
+    }
+
+    // Unix and BeOS store the whole mode in the attributes, DOS, OS/2 and
+    // Windows the DOS attribute bits. Like unrar, MacOS counts as unknown.
+    fn has_unix_attrs(&self) -> Option<bool> {
+        match self.prefix.creation_os()? {
+            OperatingSystem::Unix | OperatingSystem::BeOS => Some(true),
+            OperatingSystem::Dos | OperatingSystem::OS2 | OperatingSystem::Windows => Some(false),
+            OperatingSystem::MacOS => None,
+        }
+    }
+
+    pub fn unix_mode(&self) -> Option<u32> {
+        match self.has_unix_attrs() {
+            Some(true) => Some(self.prefix.file_attrs()),
+            _ => None,
+        }
+    }
+
+    // Unix symlinks are files with the symlink type in their mode, whose data
+    // is the target.
+    pub fn is_symlink(&self) -> bool {
+        matches!(self.unix_mode(), Some(mode) if mode & 0o170_000 == UNIX_SYMLINK)
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        match self.has_unix_attrs() {
+            Some(true) => Permissions::from_unix(self.prefix.file_attrs()),
+            Some(false) => Permissions::from_dos(self.prefix.file_attrs()),
+            None => Permissions::unknown(self.is_directory()),
+        }
+    }
+}
+
+// A file or service block, holding its whole header so that `FileHeader`
+// can borrow from it.
+#[derive(Debug)]
+pub struct FileBlock {
+    pub prefix: BlockHeaderCommon,
+    buf: Vec<u8>,
+}
+
+impl FileBlock {
+    pub async fn parse(prefix: BlockHeaderCommon, f: &mut impl AsyncFile) -> Result<FileBlock> {
+        let rest = prefix.read_rest(f).await?;
+        let mut buf = prefix.to_bytes();
+        buf.extend_from_slice(&rest);
+        FileHeader::from_buf(&buf)?;
+        Ok(FileBlock { prefix, buf })
+    }
+
+    pub fn header(&self) -> FileHeader<'_> {
+        let (header, _) = FileHeader::from_buf(&self.buf).expect("header was checked in parse");
+        header
+    }
+
+    // Whether this is a service block holding an NTFS stream.
+    pub fn is_stream(&self) -> bool {
+        let header = self.header();
+        header.prefix().is_service() && header.raw_name() == STREAM_SERVICE
+    }
+
+    // Whether this is a service block holding the Unix owner of a file.
+    pub fn is_owner(&self) -> bool {
+        let header = self.header();
+        header.prefix().is_service() && header.raw_name() == OWNER_SERVICE
+    }
+
+    // Whether this is the RR service block holding the recovery record.
+    pub fn is_recovery_record(&self) -> bool {
+        let header = self.header();
+        header.prefix().is_service() && header.raw_name() == RECOVERY_SERVICE
+    }
+
+    pub fn read_owner(&self) -> Result<UnixOwner> {
+        UnixOwner::parse_uow(self.header().sub_data())
+    }
+
+    // Read the target of a symlink from `f`, positioned at its data. Only
+    // stored targets can be read, others are skipped.
+    pub async fn read_link(&self, f: &mut impl AsyncFile) -> Result<Option<Link>> {
+        match self.read_stored_data(f).await? {
+            Some(target) => Ok(Some(Link::unix_symlink(&target))),
+            None => Ok(None),
+        }
+    }
+
+    // Read the data of a stored, unencrypted block of up to MAX_STORED_DATA
+    // bytes, checking its CRC. Other data is skipped.
+    async fn read_stored_data(&self, f: &mut impl AsyncFile) -> Result<Option<Vec<u8>>> {
+        let header = self.header();
+        let too_big = header.packed_size() > MAX_STORED_DATA;
+        if !header.is_stored() || header.is_encrypted() || too_big {
+            self.skip_data(f).await?;
+            return Ok(None);
+        }
+        let data = super::read_data(f, header.packed_size()).await?;
+        if crc::crc32::checksum_ieee(&data) != header.prefix().file_crc32() {
+            return Err(Error::bad_data(format!(
+                "CRC mismatch in {}",
+                header.name()
+            )));
+        }
+        Ok(Some(data))
+    }
+
+    // Read the NTFS stream of an STM service block from `f`, positioned at
+    // its data. Only stored streams keep their contents.
+    pub async fn read_stream(&self, f: &mut impl AsyncFile) -> Result<NamedStream> {
+        let header = self.header();
+        let name = utf16_stream_name(header.sub_data())?;
+        let data = self.read_stored_data(f).await?;
+        Ok(NamedStream {
+            name,
+            size: data
+                .as_ref()
+                .map_or(header.unpacked_size(), |d| d.len() as u64),
+            data,
         })
     }
+
+    // Stream the contents of this file from `f`, positioned at its data.
+    // Only stored files can be read; RAR 1.5-4.x compression and encryption
+    // aren't implemented.
+    pub fn reader<T: AsyncFile>(&self, f: T) -> Result<VerifyReader<EntryReader<T, Store>>> {
+        let header = self.header();
+        if header.is_encrypted() {
+            return Err(Error::unsupported("RAR 1.5-4.x encryption".into()));
+        }
+        if header.flags().intersects(
+            FileFlags::CONTINUED_FROM_PREVIOUS_VOLUME | FileFlags::CONTINUED_TO_NEXT_VOLUME,
+        ) {
+            return Err(Error::unsupported("Files split across volumes".into()));
+        }
+        if !header.is_stored() {
+            return Err(Error::unsupported(format!(
+                "RAR 1.5-4.x compression method {:#x}",
+                header.prefix().raw_packing_method()
+            )));
+        }
+        let entry = EntryReader::new(f, Store::new(), header.packed_size());
+        Ok(VerifyReader::new(entry, header.hash(), None))
+    }
+
+    // Move `f` past the packed data of this block.
+    pub async fn skip_data(&self, f: &mut impl AsyncFile) -> Result<()> {
+        skip(f, self.header().packed_size()).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::with_header_crc;
+    use futures::executor::block_on;
 
     fn prefix_buf() -> Vec<u8> {
         vec![
@@ -299,15 +694,87 @@ mod tests {
     fn test_gets_flags() {
         let buf = prefix_buf();
         let (prefix, _) = FilePrefix::from_buf(&buf).unwrap();
-        let expected = FileFlags::Dictionary3 | FileFlags::ExtTime | FileFlags::Always;
-        assert_eq!(prefix.flags(), FileFlags::Dictionary3 | expected);
+        let expected = FileFlags::DICTIONARY3 | FileFlags::EXT_TIME | FileFlags::ALWAYS;
+        assert_eq!(prefix.flags(), FileFlags::DICTIONARY3 | expected);
+    }
+
+    fn header_buf(head_type: u8, flags: u16, name: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0, 0, head_type];
+        buf.extend(&flags.to_le_bytes());
+        buf.extend(&((32 + name.len() + extra.len()) as u16).to_le_bytes());
+        buf.extend(&prefix_buf()[7..32]);
+        LittleEndian::write_u16(&mut buf[26..28], name.len() as u16);
+        buf.extend(name);
+        buf.extend(extra);
+        buf
+    }
+
+    #[test]
+    fn test_file_header_reads_name_and_sizes() {
+        let buf = header_buf(0x74, 0x8000, b"roar.txt", &[]);
+        let (header, rest) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.name(), "roar.txt");
+        assert_eq!(header.packed_size(), 374426);
+        assert!(header.sub_data().is_empty());
+        assert!(!header.is_directory());
+        assert_eq!(header.permissions(), Permissions::from_dos(0x20));
+        assert_eq!(header.dictionary_size(), Some(0x1_0000));
+        assert_eq!(rest.len(), 0);
+    }
+
+    #[test]
+    fn test_file_header_dictionary_size() {
+        let buf = header_buf(0x74, 0x80c0, b"roar.txt", &[]);
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.dictionary_size(), Some(0x40_0000));
+        let buf = header_buf(0x74, 0x80e0, b"dir", &[]);
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.dictionary_size(), None);
+    }
+
+    #[test]
+    fn test_file_header_reads_high_sizes() {
+        let mut buf = header_buf(0x74, 0x8100, b"", &[1, 0, 0, 0, 2, 0, 0, 0]);
+        buf.extend(b"name");
+        LittleEndian::write_u16(&mut buf[26..28], 4);
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.packed_size(), 0x1_0005_b69a);
+        assert_eq!(header.unpacked_size(), 0x2_0005_b69a);
+        assert_eq!(header.name(), "name");
+    }
+
+    #[test]
+    fn test_service_header_keeps_sub_data() {
+        let buf = header_buf(0x7a, 0x8000, b"STM", b":\0s\0");
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.raw_name(), b"STM");
+        assert_eq!(header.sub_data(), b":\0s\0");
+    }
+
+    #[test]
+    fn test_file_header_skips_ext_time() {
+        // mtime with one extra byte, atime with a DOS time and no extra.
+        let buf = header_buf(0x74, 0x9000, b"a", &[0x00, 0x89, 7, 1, 2, 3, 4]);
+        let (header, rest) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.ext_time().unwrap().len(), 7);
+        assert_eq!(rest.len(), 0);
+    }
+
+    #[test]
+    fn test_decodes_utf8_and_encoded_unicode_names() {
+        assert_eq!(decode_unicode_name("żółw".as_bytes()), "żółw");
+        // "ab" with the second character replaced by U+0142 through a high
+        // byte of 0x01: flags 0b00_01_0000.
+        assert_eq!(decode_unicode_name(b"ab\0\x01\x10a\x42"), "a\u{142}");
+        // A run copied from the plain name.
+        assert_eq!(decode_unicode_name(b"abcd\0\x00\xc0\x02"), "abcd");
     }
 
     #[test]
     fn test_parse_header_highsize_returns_nothing_when_unflagged() {
         let buf = vec![];
         let mut cursor = BufferCursor::new(&buf);
-        let flags = FileFlags::Always;
+        let flags = FileFlags::ALWAYS;
         assert!(parse_header_highsize(&mut cursor, &flags)
             .unwrap()
             .is_none());
@@ -317,7 +784,7 @@ mod tests {
     fn test_parse_header_highsize_returns_8_bytes_when_flagged() {
         let buf = vec![1, 2, 3, 4, 5, 6, 7, 8];
         let mut cursor = BufferCursor::new(&buf);
-        let flags = FileFlags::Always | FileFlags::HighFields;
+        let flags = FileFlags::ALWAYS | FileFlags::HIGH_FIELDS;
         assert_eq!(
             parse_header_highsize(&mut cursor, &flags)
                 .unwrap()
@@ -331,7 +798,7 @@ mod tests {
     fn test_parse_header_salt_returns_nothing_when_unflagged() {
         let buf = vec![];
         let mut cursor = BufferCursor::new(&buf);
-        let flags = FileFlags::Always;
+        let flags = FileFlags::ALWAYS;
         assert!(parse_header_salt(&mut cursor, &flags).unwrap().is_none());
     }
 
@@ -339,7 +806,7 @@ mod tests {
     fn test_parse_header_salt_returns_8_bytes_when_flagged() {
         let buf = vec![1, 2, 3, 4, 5, 6, 7, 8];
         let mut cursor = BufferCursor::new(&buf);
-        let flags = FileFlags::Always | FileFlags::Salted;
+        let flags = FileFlags::ALWAYS | FileFlags::SALTED;
         assert_eq!(
             parse_header_salt(&mut cursor, &flags)
                 .unwrap()
@@ -348,4 +815,108 @@ mod tests {
             8
         );
     }
+
+    #[test]
+    fn test_reads_stored_stream() {
+        let name: Vec<u8> = ":extra:$DATA"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let mut buf = header_buf(0x7a, 0x8000, b"STM", &name);
+        LittleEndian::write_u32(&mut buf[7..11], 4);
+        LittleEndian::write_u32(&mut buf[16..20], crc::crc32::checksum_ieee(b"roar"));
+        buf.extend(b"roar");
+
+        let buf = with_header_crc(buf);
+        let mut f = &buf[..];
+        let prefix = block_on(BlockHeaderCommon::read_from_file(&mut f)).unwrap();
+        let block = block_on(FileBlock::parse(prefix, &mut f)).unwrap();
+        assert!(block.is_stream());
+        let stream = block_on(block.read_stream(&mut f)).unwrap();
+        assert_eq!(stream.name, "extra");
+        assert_eq!(stream.data, Some(b"roar".to_vec()));
+        assert!(f.is_empty());
+    }
+
+    #[test]
+    fn test_parse_checks_header_crc() {
+        let mut buf = with_header_crc(header_buf(0x74, 0x8000, b"roar.txt", &[]));
+        buf[20] ^= 1;
+        let mut f = &buf[..];
+        let prefix = block_on(BlockHeaderCommon::read_from_file(&mut f)).unwrap();
+        assert!(block_on(FileBlock::parse(prefix, &mut f)).is_err());
+    }
+
+    #[test]
+    fn test_reads_owner_and_unix_mode() {
+        let mut buf = header_buf(0x7a, 0x8000, b"UOW", b"erich\0staff");
+        LittleEndian::write_u32(&mut buf[7..11], 0);
+        buf[15] = OperatingSystem::Unix as u8;
+        LittleEndian::write_u32(&mut buf[28..32], 0o100_755);
+
+        let buf = with_header_crc(buf);
+        let mut f = &buf[..];
+        let prefix = block_on(BlockHeaderCommon::read_from_file(&mut f)).unwrap();
+        let block = block_on(FileBlock::parse(prefix, &mut f)).unwrap();
+        assert!(block.is_owner());
+        assert!(!block.is_stream());
+        assert_eq!(block.read_owner().unwrap().to_string(), "erich:staff");
+        assert_eq!(block.header().unix_mode(), Some(0o100_755));
+        assert!(block.header().permissions().executable);
+    }
+
+    #[test]
+    fn test_reads_stored_symlink_target() {
+        let mut buf = header_buf(0x74, 0x8000, b"link", b"");
+        LittleEndian::write_u32(&mut buf[7..11], 6);
+        buf[15] = OperatingSystem::Unix as u8;
+        LittleEndian::write_u32(&mut buf[16..20], crc::crc32::checksum_ieee(b"target"));
+        LittleEndian::write_u32(&mut buf[28..32], 0o120_777);
+        buf.extend(b"target");
+
+        let buf = with_header_crc(buf);
+        let mut f = &buf[..];
+        let prefix = block_on(BlockHeaderCommon::read_from_file(&mut f)).unwrap();
+        let block = block_on(FileBlock::parse(prefix, &mut f)).unwrap();
+        assert!(block.header().is_symlink());
+        let link = block_on(block.read_link(&mut f)).unwrap().unwrap();
+        assert_eq!(link.target, "target");
+    }
+
+    #[test]
+    fn test_host_os_decides_what_attributes_mean() {
+        fn header(buf: &[u8]) -> FileHeader<'_> {
+            FileHeader::from_buf(buf).unwrap().0
+        }
+        let mut buf = header_buf(0x74, 0x8000, b"link", b"");
+        LittleEndian::write_u32(&mut buf[28..32], 0o120_777);
+
+        buf[15] = OperatingSystem::BeOS as u8;
+        assert_eq!(header(&buf).unix_mode(), Some(0o120_777));
+        assert!(header(&buf).is_symlink());
+        assert_eq!(header(&buf).permissions().unix_mode, 0o777);
+
+        // MacOS attributes are unknown, like any other system's.
+        for &os in &[OperatingSystem::MacOS as u8, 9] {
+            buf[15] = os;
+            assert_eq!(header(&buf).unix_mode(), None);
+            assert!(!header(&buf).is_symlink());
+            assert_eq!(header(&buf).permissions(), Permissions::from_dos(0));
+        }
+    }
+
+    #[test]
+    fn test_splits_version_from_name() {
+        let buf = header_buf(0x74, 0x8800, b"dir/roar.txt;12", &[]);
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.name(), "dir/roar.txt");
+        assert_eq!(header.full_name(), "dir/roar.txt;12");
+        assert_eq!(header.version(), Some(12));
+
+        // Without the flag a semicolon is part of the name.
+        let buf = header_buf(0x74, 0x8000, b"roar.txt;12", &[]);
+        let (header, _) = FileHeader::from_buf(&buf).unwrap();
+        assert_eq!(header.name(), "roar.txt;12");
+        assert_eq!(header.version(), None);
+    }
 }
@@ -1,12 +1,21 @@
 mod archive;
+mod authenticity;
 mod cursor;
-//mod file;
+mod end;
+mod file;
 mod prefix;
+mod protect;
+mod view;
 
 pub use archive::ArchiveHeader;
-//pub use file::FileHeader;
-pub use prefix::BlockHeaderCommon;
+pub use authenticity::{Authenticity, AvHeader, SignHeader};
+pub(crate) use cursor::BufferCursor;
+pub use end::{EndFlags, EndHeader};
+pub use file::{FileBlock, FileFlags, FileHeader, OperatingSystem, PackingMethod};
+pub use prefix::{BlockHeaderCommon, BlockPrefix};
 pub use prefix::HeadType;
+pub use protect::ProtectHeader;
+pub use view::{BlockView, BlockViews};
 
 use crate::error::{Error, Result};
 use crate::traits::AsyncFile;
@@ -16,6 +25,30 @@ use futures::io::{AsyncRead, AsyncReadExt};
 pub enum Block {
     Marker,
     Archive(ArchiveHeader),
+    Protect(ProtectHeader),
+    Av(AvHeader),
+    Sign(SignHeader),
+    File(FileBlock),
+    Service(FileBlock),
+    End(EndHeader),
+}
+
+impl Block {
+    pub fn prefix(&self) -> Option<&BlockHeaderCommon> {
+        match *self {
+            Block::Marker => None,
+            Block::Archive(ref x) => Some(&x.prefix),
+            Block::Protect(ref x) => Some(&x.prefix),
+            Block::Av(ref x) => Some(&x.prefix),
+            Block::Sign(ref x) => Some(&x.prefix),
+            Block::File(ref x) | Block::Service(ref x) => Some(&x.prefix),
+            Block::End(ref x) => Some(&x.prefix),
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.prefix().is_some_and(BlockHeaderCommon::is_deleted)
+    }
 }
 
 pub async fn read_block<T: AsyncFile>(f: &mut T) -> Result<Block> {
@@ -29,6 +62,12 @@ pub async fn read_block<T: AsyncFile>(f: &mut T) -> Result<Block> {
     Ok(match block.header_type {
         HeadType::MarkerBlock => Block::Marker,
         HeadType::ArchiveHeader => Block::Archive(ArchiveHeader::parse(block, f).await?),
+        HeadType::OldRecoveryRecord => Block::Protect(ProtectHeader::parse(block, f).await?),
+        HeadType::OldAuthenticityInformation => Block::Av(AvHeader::parse(block, f).await?),
+        HeadType::OldAuthenticityInformation2 => Block::Sign(SignHeader::parse(block, f).await?),
+        HeadType::FileHeader => Block::File(FileBlock::parse(block, f).await?),
+        HeadType::SubBlock => Block::Service(FileBlock::parse(block, f).await?),
+        HeadType::Terminator => Block::End(EndHeader::parse(block, f).await?),
         _ => {
             return Err(Error::bad_block(format!(
                 "Unknown block marker: {:?}",
@@ -37,3 +76,28 @@ pub async fn read_block<T: AsyncFile>(f: &mut T) -> Result<Block> {
         }
     })
 }
+
+// Move `f` forward by `n` bytes, e.g. past the data of a block.
+pub(crate) async fn skip<T: AsyncFile>(f: &mut T, mut n: u64) -> Result<()> {
+    let mut buf = [0u8; 0x1000];
+    while n > 0 {
+        let want = n.min(buf.len() as u64) as usize;
+        f.read_exact(&mut buf[..want]).await?;
+        n -= want as u64;
+    }
+    Ok(())
+}
+
+// Read `n` bytes of block data from `f`. The buffer grows as the data comes
+// in, so a forged size fails at the end of the file instead of allocating
+// all of it up front.
+pub(crate) async fn read_data<T: AsyncFile>(f: &mut T, n: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 0x1000];
+    while (data.len() as u64) < n {
+        let want = (n - data.len() as u64).min(buf.len() as u64) as usize;
+        f.read_exact(&mut buf[..want]).await?;
+        data.extend_from_slice(&buf[..want]);
+    }
+    Ok(data)
+}
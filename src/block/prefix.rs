@@ -4,6 +4,7 @@ use crate::error::{Error, Result};
 use bitflags::bitflags;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use crc::crc16;
+use crc::crc32;
 use crc::crc16::Hasher16;
 use futures::{AsyncRead, AsyncReadExt};
 use num::FromPrimitive;
@@ -89,6 +90,8 @@ pub struct BlockHeaderCommon {
     expected_header_crc: u16,
     pub header_type: HeadType,
     header_flags: PrefixFlags,
+    // The flags as stored, including the block type specific ones.
+    raw_flags: u16,
     header_size: u16,
     additional_size: u32,
     pub digest: crc::crc16::Digest,
@@ -112,30 +115,99 @@ impl BlockHeaderCommon {
     pub async fn read_from_file<T: AsyncRead + Unpin>(f: T) -> Result<BlockHeaderCommon> {
         // This seed is incorrect.
         let mut cursor = AsyncCRC16Cursor::new(f, 0);
-        let header_crc = cursor.read_u16().await?;
-
-        let header_type = HeadType::from_u8(cursor.read_u8().await?)
-            .ok_or(Error::bad_block("Unknown block type".into()))?;
-
-        let header_flags = PrefixFlags::from_bits_truncate(cursor.read_u16().await?);
-        let header_size = cursor.read_u16().await?;
-
-        let additional_size = if header_flags.contains(PrefixFlags::HAS_ADD_SIZE) {
-            cursor.read_u32().await?
+        let mut buf = [0u8; 11];
+        cursor.read_exact(&mut buf[..7]).await?;
+        let len = if LittleEndian::read_u16(&buf[3..5]) & PrefixFlags::HAS_ADD_SIZE.bits() != 0 {
+            cursor.read_exact(&mut buf[7..]).await?;
+            11
         } else {
-            0
+            7
         };
+        let (prefix, _) = BlockPrefix::from_buf(&buf[..len])?;
+        BlockHeaderCommon::from_prefix(&prefix, cursor.digest)
+    }
 
+    // Build the owned form of a borrowed prefix. `digest` is the CRC of the
+    // header read so far.
+    pub fn from_prefix(prefix: &BlockPrefix, digest: crc16::Digest) -> Result<BlockHeaderCommon> {
+        let header_type = prefix
+            .block_type()
+            .ok_or_else(|| Error::bad_block("Unknown block type".into()))?;
         Ok(BlockHeaderCommon {
-            expected_header_crc: header_crc,
+            expected_header_crc: prefix.crc(),
             header_type,
-            header_flags,
-            header_size,
-            additional_size,
-            digest: cursor.digest,
+            header_flags: PrefixFlags::from_bits_truncate(prefix.flags()),
+            raw_flags: prefix.flags(),
+            header_size: prefix.size(),
+            additional_size: prefix.add_size(),
+            digest,
         })
     }
 
+    pub fn flags(&self) -> u16 {
+        self.raw_flags
+    }
+
+    // Whether the block was deleted from the archive but left in place, so
+    // that it could still be recovered.
+    pub fn is_deleted(&self) -> bool {
+        self.header_flags.contains(PrefixFlags::IS_DELETED)
+    }
+
+    // Size of the header, including this common part.
+    pub fn header_size(&self) -> u16 {
+        self.header_size
+    }
+
+    // Size of this common part of the header.
+    pub fn prefix_size(&self) -> u16 {
+        if self.header_flags.contains(PrefixFlags::HAS_ADD_SIZE) {
+            11
+        } else {
+            7
+        }
+    }
+
+    // The common part of the header as stored, for parsers that work on
+    // whole headers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; usize::from(self.prefix_size())];
+        LittleEndian::write_u16(&mut buf[0..2], self.expected_header_crc);
+        buf[2] = self.header_type as u8;
+        LittleEndian::write_u16(&mut buf[3..5], self.raw_flags);
+        LittleEndian::write_u16(&mut buf[5..7], self.header_size);
+        if buf.len() > 7 {
+            LittleEndian::write_u32(&mut buf[7..11], self.additional_size);
+        }
+        buf
+    }
+
+    // Read the rest of the header, which follows this common part, and check
+    // it against the stored CRC: the low 16 bits of the CRC32 of the header
+    // from its type byte on.
+    pub async fn read_rest<T: AsyncRead + Unpin>(&self, mut f: T) -> Result<Vec<u8>> {
+        let size = self
+            .header_size
+            .checked_sub(self.prefix_size())
+            .ok_or_else(|| {
+                Error::bad_block(format!("Header size {} is too small", self.header_size))
+            })?;
+        let mut rest = vec![0u8; usize::from(size)];
+        f.read_exact(&mut rest).await?;
+
+        let mut header = self.to_bytes();
+        header.extend_from_slice(&rest);
+        if crc32::checksum_ieee(&header[2..]) as u16 != self.expected_header_crc {
+            return Err(Error::bad_block(format!("Bad header CRC in {:?}", self.header_type)));
+        }
+        Ok(rest)
+    }
+
+    // Size of the data following the header, such as packed file data.
+    pub fn data_size(&self) -> u32 {
+        self.additional_size
+    }
+
     pub fn block_size(&self) -> u32 {
         self.additional_size
             .checked_add(self.header_size as u32)
@@ -143,148 +215,100 @@ impl BlockHeaderCommon {
     }
 }
 
-//#[derive(Debug, Clone, Copy, PartialEq, Default)]
-//pub struct BlockPrefix<'a> {
-//    // FIELD BYTES
-//    // HEAD_CRC 2
-//    // HEAD_TYPE 1
-//    // HEAD_FLAGS 2
-//    // HEAD_SIZE 2
-//    // ADD_SIZE 4 (optional)
-//    main: &'a [u8],
-//    // add_size: Option<&'a [u8]>,
-//}
-//
-//impl<'a> BlockPrefix<'a> {
-//    pub fn crc(&self) -> u16 {
-//        LittleEndian::read_u16(&self.main[0..2])
-//    }
-//
-//    pub fn crc_digest(&self, seed: u16) -> crc16::Digest {
-//        // panic!("this method is broken still.");
-//        let mut digest = crc::crc16::Digest::new(seed);
-//        digest.write(&self.main[2..]);
-//        // if let Some(ref x) = self.add_size {
-//        //     digest.write(x);
-//        // }
-//        return digest;
-//    }
-//
-//    pub fn raw_block_type(&self) -> u8 {
-//        self.main[2]
-//    }
-//
-//    pub fn block_type(&self) -> Option<HeadType> {
-//        HeadType::from_u8(self.raw_block_type())
-//    }
-//
-//    pub fn flags(&self) -> u16 {
-//        LittleEndian::read_u16(&self.main[3..5])
-//    }
-//
-//    pub fn size(&self) -> u64 {
-//        // let add_size = self
-//        //     .add_size
-//        //     .map(|x| LittleEndian::read_u32(x))
-//        //     .unwrap_or(0);
-//        // let size = LittleEndian::read_u16(&self.main[5..7]);
-//        // u64::from(size) + u64::from(add_size)
-//        u64::from(LittleEndian::read_u16(&self.main[5..7]))
-//    }
-//
-//    pub fn from_buf(buf: &'a [u8]) -> Result<(BlockPrefix<'a>, &'a [u8])> {
-//        let mut cursor = BufferCursor::new(buf);
-//        let bp = BlockPrefix::from_cursor(&mut cursor)?;
-//        let rest = cursor.rest();
-//        Ok((bp, rest))
-//        // if buf.len() < 7 {
-//        //     return Err(Error::buffer_too_small(7));
-//        // }
-//        // // let flags = LittleEndian::read_u16(&buf[3..5]);
-//
-//        // // let has_add_size = flags & PrefixFlags::HAS_ADD_SIZE.bits() > 0;
-//        // // if has_add_size && buf.len() < 7 + 4 {
-//        // //     return Err(Error::buffer_too_small(7 + 4));
-//        // // }
-//        // // let rest = if has_add_size {
-//        // //     &buf[(7 + 4)..]
-//        // // } else {
-//        // //     &buf[7..]
-//        // // };
-//        // // let add_size = if has_add_size {
-//        // //     Some(&buf[7..(7 + 4)])
-//        // // } else {
-//        // //     None
-//        // // };
-//
-//        // Ok((
-//        //     BlockPrefix {
-//        //         main: &buf[0..7],
-//        //         // add_size: add_size,
-//        //     },
-//        //     &buf[7..],
-//        // ))
-//    }
-//
-//    pub fn from_cursor(cursor: &mut BufferCursor<'a>) -> Result<BlockPrefix<'a>> {
-//        Ok(BlockPrefix {
-//            main: cursor.read(7)?,
-//        })
-//    }
-//
-//    pub fn as_owned(&self) -> Result<OwnedBlockPrefix> {
-//        Ok(OwnedBlockPrefix {
-//            expected_crc: self.crc(),
-//            block_type: self.block_type().ok_or_else(|| {
-//                Error::bad_block(format!("Unknown block type: {}", self.raw_block_type()))
-//            })?,
-//            flags: self.flags(),
-//            size: self.size(),
-//        })
-//    }
-//}
-//
-//#[derive(Clone)]
-//pub struct OwnedBlockPrefix {
-//    pub expected_crc: u16,
-//    pub block_type: HeadType,
-//    pub flags: u16,
-//    pub size: u64,
-//}
-//
-//impl ::std::fmt::Debug for OwnedBlockPrefix {
-//    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-//        write!(
-//            f,
-//            "OwnedBlockPrefix<expected_crc: {:?}, block_type: {:?}, flags: {:?}, size: {:?}>",
-//            self.expected_crc, self.block_type, self.flags, self.size
-//        )
-//    }
-//}
+// The common part of a block header, borrowed from a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BlockPrefix<'a> {
+    // FIELD BYTES
+    // HEAD_CRC 2
+    // HEAD_TYPE 1
+    // HEAD_FLAGS 2
+    // HEAD_SIZE 2
+    main: &'a [u8],
+    // ADD_SIZE 4 (optional)
+    add_size: Option<&'a [u8]>,
+}
+
+impl<'a> BlockPrefix<'a> {
+    pub fn crc(&self) -> u16 {
+        LittleEndian::read_u16(&self.main[0..2])
+    }
+
+    pub fn raw_block_type(&self) -> u8 {
+        self.main[2]
+    }
+
+    pub fn block_type(&self) -> Option<HeadType> {
+        HeadType::from_u8(self.raw_block_type())
+    }
+
+    pub fn flags(&self) -> u16 {
+        LittleEndian::read_u16(&self.main[3..5])
+    }
+
+    // Size of the header, including this common part.
+    pub fn size(&self) -> u16 {
+        LittleEndian::read_u16(&self.main[5..7])
+    }
+
+    // Size of the data following the header.
+    pub fn add_size(&self) -> u32 {
+        self.add_size.map_or(0, LittleEndian::read_u32)
+    }
+
+    // Size of this common part of the header.
+    pub fn prefix_size(&self) -> u16 {
+        if self.add_size.is_some() {
+            11
+        } else {
+            7
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.flags() & PrefixFlags::IS_DELETED.bits() != 0
+    }
+
+    pub fn from_buf(buf: &'a [u8]) -> Result<(BlockPrefix<'a>, &'a [u8])> {
+        let mut cursor = BufferCursor::new(buf);
+        let bp = BlockPrefix::from_cursor(&mut cursor)?;
+        let rest = cursor.rest();
+        Ok((bp, rest))
+    }
+
+    pub fn from_cursor(cursor: &mut BufferCursor<'a>) -> Result<BlockPrefix<'a>> {
+        let main = cursor.read(7)?;
+        let add_size = if LittleEndian::read_u16(&main[3..5]) & PrefixFlags::HAS_ADD_SIZE.bits() != 0 {
+            Some(cursor.read(4)?)
+        } else {
+            None
+        };
+        Ok(BlockPrefix { main, add_size })
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use crate::error::ErrorKind;
+    use futures::executor::block_on;
 
     fn magic_block_prefix() -> Vec<u8> {
         vec![0x52, 0x61, 0x72, 0x21, 0x1A, 0x07, 0x00]
     }
 
-//    #[test]
-//    fn test_block_prefix_read_errors_with_not_enough_data() {
-//        BlockHeaderCommon::read_from_file(Cursor::new(magic_block_prefix()))
-//        let res = BlockPrefix::from_buf(&[0]);
-//        assert!(res.is_err());
-//    }
+    #[test]
+    fn test_block_prefix_read_errors_with_not_enough_data() {
+        let res = BlockPrefix::from_buf(&[0]);
+        assert!(res.is_err());
+    }
 
-    // #[test]
-    // fn test_block_prefix_read_errors_with_not_enough_data_from_add_data() {
-    //     let mut buf = magic_block_prefix();
-    //     buf[4] = 0x80;
-    //     let res = BlockPrefix::from_buf(&buf);
-    //     assert!(res.is_err());
-    // }
+    #[test]
+    fn test_block_prefix_read_errors_with_not_enough_data_from_add_data() {
+        let mut buf = magic_block_prefix();
+        buf[4] = 0x80;
+        let res = BlockPrefix::from_buf(&buf);
+        assert!(res.is_err());
+    }
 
     #[test]
     fn test_block_prefix_read_reads_magic() {
@@ -301,6 +325,30 @@ mod tests {
         assert_eq!(rest.len(), 0);
     }
 
+    #[test]
+    fn test_block_prefix_reads_add_size() {
+        let buf = vec![0, 0, 0x74, 0, 0x80, 32, 0, 5, 0, 0, 0, 1];
+        let (bh, rest) = BlockPrefix::from_buf(&buf).unwrap();
+        assert_eq!(bh.block_type(), Some(HeadType::FileHeader));
+        assert_eq!(bh.prefix_size(), 11);
+        assert_eq!(bh.add_size(), 5);
+        assert_eq!(rest, &[1]);
+
+        match *BlockPrefix::from_buf(&buf[..9]).unwrap_err().kind() {
+            ErrorKind::BufferTooSmall(11) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn test_read_from_file_matches_block_prefix() {
+        let buf = vec![0, 0, 0x74, 0, 0x80, 32, 0, 5, 0, 0, 0];
+        let bh = block_on(BlockHeaderCommon::read_from_file(&buf[..])).unwrap();
+        assert_eq!(bh.header_type, HeadType::FileHeader);
+        assert_eq!(bh.to_bytes(), buf);
+        assert_eq!(bh.data_size(), 5);
+    }
+
     // #[test]
     // fn test_read_old_block_head_reads_block_head() {
     //     let block_result = BlockHead::read(&mut Cursor::new(magic_blockhead()));
@@ -0,0 +1,50 @@
+use super::cursor::BufferCursor;
+use crate::block::prefix::BlockHeaderCommon;
+use crate::error::{Error, Result};
+use crate::recovery::{RecoveryRecord, PROTECT_MARK};
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+
+// Old style recovery record block (HeadType::OldRecoveryRecord), written by
+// RAR 2.x. Its data area holds the recovery record itself.
+#[derive(Debug)]
+pub struct ProtectHeader {
+    pub prefix: BlockHeaderCommon,
+    pub version: u8,
+    pub recovery_sectors: u16,
+    pub total_blocks: u32,
+    pub mark: [u8; 8],
+}
+
+impl ProtectHeader {
+    pub async fn parse(prefix: BlockHeaderCommon, f: &mut impl AsyncFile) -> Result<ProtectHeader> {
+        let buf = prefix.read_rest(f).await?;
+        let mut cursor = BufferCursor::new(&buf);
+
+        let version = cursor.read(1)?[0];
+        let recovery_sectors = LittleEndian::read_u16(cursor.read(2)?);
+        let total_blocks = LittleEndian::read_u32(cursor.read(4)?);
+        let mut mark = [0u8; 8];
+        mark.copy_from_slice(cursor.read(8)?);
+        if mark != PROTECT_MARK {
+            return Err(Error::bad_block("Missing recovery record mark".into()));
+        }
+        Ok(ProtectHeader {
+            prefix,
+            version,
+            recovery_sectors,
+            total_blocks,
+            mark,
+        })
+    }
+
+    // Read the recovery record from the data area, which must follow.
+    pub async fn read_record(&self, f: &mut impl AsyncFile) -> Result<RecoveryRecord> {
+        let data = super::read_data(f, u64::from(self.prefix.data_size())).await?;
+        RecoveryRecord::parse(
+            u32::from(self.recovery_sectors),
+            u64::from(self.total_blocks),
+            &data,
+        )
+    }
+}
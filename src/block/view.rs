@@ -0,0 +1,198 @@
+use super::cursor::BufferCursor;
+use super::file::FileHeader;
+use super::prefix::{BlockPrefix, HeadType};
+use crate::error::{Error, Result};
+
+// A block of a RAR 1.5-4.x archive borrowed from a buffer holding the
+// archive, e.g. a memory mapped file. Nothing is copied, and running out of
+// data is reported as ErrorKind::BufferTooSmall with the number of bytes
+// needed from the start of the buffer, so more can be read and the parse
+// retried.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockView<'a> {
+    pub prefix: BlockPrefix<'a>,
+    // The whole header, including the prefix.
+    header: &'a [u8],
+    // Data following the header, like packed file data.
+    data: &'a [u8],
+}
+
+impl<'a> BlockView<'a> {
+    pub fn from_buf(buf: &'a [u8]) -> Result<(BlockView<'a>, &'a [u8])> {
+        let mut cursor = BufferCursor::new(buf);
+        let view = BlockView::from_cursor(&mut cursor)?;
+        Ok((view, cursor.rest()))
+    }
+
+    pub fn from_cursor(cursor: &mut BufferCursor<'a>) -> Result<BlockView<'a>> {
+        let (prefix, _) = BlockPrefix::from_buf(cursor.peek(cursor.remaining())?)?;
+        if prefix.size() < prefix.prefix_size() {
+            return Err(Error::bad_block(format!(
+                "Header size {} is too small",
+                prefix.size()
+            )));
+        }
+        let header = cursor.read(usize::from(prefix.size()))?;
+        // File and service blocks may keep the high half of the data size in
+        // HIGH_PACK_SIZE.
+        let data_size = match prefix.block_type() {
+            Some(HeadType::FileHeader) | Some(HeadType::SubBlock) => {
+                let (file, _) = FileHeader::from_buf(header)
+                    .map_err(|e| Error::bad_block(format!("Truncated file header: {}", e)))?;
+                file.packed_size()
+            }
+            _ => u64::from(prefix.add_size()),
+        };
+        let data = cursor.read(data_size as usize)?;
+        Ok(BlockView {
+            prefix,
+            header,
+            data,
+        })
+    }
+
+    pub fn block_type(&self) -> Option<HeadType> {
+        self.prefix.block_type()
+    }
+
+    pub fn header(&self) -> &'a [u8] {
+        self.header
+    }
+
+    // The header after the prefix.
+    pub fn fields(&self) -> &'a [u8] {
+        &self.header[usize::from(self.prefix.prefix_size())..]
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    // Total size of the block, header and data.
+    pub fn size(&self) -> usize {
+        self.header.len() + self.data.len()
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.prefix.is_deleted()
+    }
+
+    // The header of a file or service block.
+    pub fn file_header(&self) -> Option<Result<FileHeader<'a>>> {
+        match self.block_type() {
+            Some(HeadType::FileHeader) | Some(HeadType::SubBlock) => {
+                Some(FileHeader::from_buf(self.header).map(|(header, _)| header))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Iterates over the blocks of a buffer starting right after the signature.
+// Stops at the end of the buffer; a block cut off by the end is returned as
+// ErrorKind::BufferTooSmall, counted from the start of the buffer.
+pub struct BlockViews<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BlockViews<'a> {
+    pub fn new(buf: &'a [u8]) -> BlockViews<'a> {
+        BlockViews { buf, pos: 0 }
+    }
+
+    // How much of the buffer the blocks returned so far cover.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl<'a> Iterator for BlockViews<'a> {
+    type Item = Result<BlockView<'a>>;
+
+    fn next(&mut self) -> Option<Result<BlockView<'a>>> {
+        if self.pos >= self.buf.len() {
+            return None;
+        }
+        match BlockView::from_buf(&self.buf[self.pos..]) {
+            Ok((view, _)) => {
+                self.pos += view.size();
+                Some(Ok(view))
+            }
+            Err(e) => {
+                let pos = self.pos;
+                // Nothing more can be parsed from this buffer.
+                self.pos = self.buf.len();
+                Some(Err(e.offset_required(pos)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::file_block;
+
+    fn archive_header() -> Vec<u8> {
+        vec![0, 0, 0x73, 0, 0, 13, 0, 0, 0, 0, 0, 0, 0]
+    }
+
+    #[test]
+    fn test_iterates_blocks_without_copying() {
+        let mut buf = archive_header();
+        buf.extend(file_block(b"a.txt", b"roar"));
+        let blocks: Vec<_> = BlockViews::new(&buf).map(|b| b.unwrap()).collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].block_type(), Some(HeadType::ArchiveHeader));
+        assert_eq!(blocks[0].fields().len(), 6);
+        assert!(blocks[0].file_header().is_none());
+
+        let file = blocks[1].file_header().unwrap().unwrap();
+        assert_eq!(file.name(), "a.txt");
+        assert_eq!(blocks[1].data(), b"roar");
+        assert_eq!(blocks[1].data().as_ptr(), buf[buf.len() - 4..].as_ptr());
+    }
+
+    #[test]
+    fn test_data_size_includes_high_pack_size() {
+        let mut buf = archive_header();
+        let mut block = vec![0, 0, 0x74, 0, 0x81, 45, 0];
+        block.extend(&4u32.to_le_bytes());
+        block.extend(&4u32.to_le_bytes());
+        block.extend(&[2, 0, 0, 0, 0, 0, 0, 0, 0, 29, 0x30, 5, 0, 0x20, 0, 0, 0]);
+        block.extend(&1u32.to_le_bytes()); // high pack size
+        block.extend(&0u32.to_le_bytes()); // high unpacked size
+        block.extend(b"a.txt");
+        block.extend(b"roar");
+        buf.extend(&block);
+
+        let mut views = BlockViews::new(&buf);
+        assert!(views.next().unwrap().is_ok());
+        match *views.next().unwrap().unwrap_err().kind() {
+            ErrorKind::BufferTooSmall(required) => {
+                assert_eq!(required as u64, 13 + 45 + (1 << 32) + 4)
+            }
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn test_reports_bytes_needed_for_cut_off_block() {
+        let mut buf = archive_header();
+        buf.extend(file_block(b"a.txt", b"roar"));
+        let full = buf.len();
+
+        for &cut in &[full - 1, 15, 22] {
+            let mut views = BlockViews::new(&buf[..cut]);
+            assert!(views.next().unwrap().is_ok());
+            let required = match *views.next().unwrap().unwrap_err().kind() {
+                ErrorKind::BufferTooSmall(required) => required,
+                ref kind => panic!("unexpected error: {}", kind),
+            };
+            assert!(required > cut && required <= full);
+            assert!(views.next().is_none());
+        }
+    }
+}
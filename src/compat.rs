@@ -0,0 +1,143 @@
+// Tokio compatibility, behind the `tokio` feature. Tokio has its own copies
+// of the async IO traits; `Compat` implements each side's traits for a type
+// implementing the other's, so archives can be opened straight from Tokio
+// files and entry readers handed back to Tokio code.
+
+use crate::archive::Archive;
+use crate::error::Result;
+use futures::io::{AsyncRead, AsyncSeek};
+use futures::task::{Context, Poll};
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+
+pub struct Compat<T> {
+    inner: T,
+    // A seek started through one side's traits and not yet completed.
+    seek: Option<SeekFrom>,
+}
+
+impl<T> Compat<T> {
+    pub fn new(inner: T) -> Compat<T> {
+        Compat { inner, seek: None }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+// Open an archive from a Tokio reader.
+pub async fn open<T: tokio::io::AsyncRead + Unpin>(inner: T) -> Result<Archive<Compat<T>>> {
+    Archive::open(Compat::new(inner)).await
+}
+
+impl<T: tokio::io::AsyncRead + Unpin> AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        tokio::io::AsyncRead::poll_read(Pin::new(&mut self.inner), cx, buf)
+    }
+}
+
+impl<T: tokio::io::AsyncSeek + Unpin> AsyncSeek for Compat<T> {
+    fn poll_seek(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = &mut *self;
+        // Tokio seeks in two steps, which both need to be retried on Pending.
+        if this.seek.is_none() {
+            match tokio::io::AsyncSeek::start_seek(Pin::new(&mut this.inner), cx, pos) {
+                Poll::Ready(Ok(())) => this.seek = Some(pos),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let result = tokio::io::AsyncSeek::poll_complete(Pin::new(&mut this.inner), cx);
+        if result.is_ready() {
+            this.seek = None;
+        }
+        result
+    }
+}
+
+impl<T: AsyncRead + Unpin> tokio::io::AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(Pin::new(&mut self.inner), cx, buf)
+    }
+}
+
+impl<T: AsyncSeek + Unpin> tokio::io::AsyncSeek for Compat<T> {
+    fn start_seek(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: SeekFrom,
+    ) -> Poll<io::Result<()>> {
+        self.seek = Some(pos);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = &mut *self;
+        // Without a pending seek this reports the current position.
+        let pos = this.seek.unwrap_or(SeekFrom::Current(0));
+        let result = AsyncSeek::poll_seek(Pin::new(&mut this.inner), cx, pos);
+        if result.is_ready() {
+            this.seek = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{Format, RAR15_SIGNATURE};
+    use futures::executor::block_on;
+    use futures::io::{AsyncReadExt, AsyncSeekExt};
+
+    #[test]
+    fn test_opens_tokio_reader() {
+        let mut data = b"stub".to_vec();
+        data.extend(&RAR15_SIGNATURE);
+        data.extend(b"rest");
+        let mut archive = block_on(open(io::Cursor::new(data))).unwrap();
+        assert_eq!(archive.format(), Format::Rar15);
+        assert_eq!(archive.sfx_size(), 4);
+        let mut rest = Vec::new();
+        block_on(AsyncReadExt::read_to_end(&mut archive, &mut rest)).unwrap();
+        assert_eq!(rest, b"rest");
+    }
+
+    #[test]
+    fn test_seeks_both_ways() {
+        let mut f = Compat::new(io::Cursor::new(b"0123456789".to_vec()));
+        assert_eq!(block_on(f.seek(SeekFrom::Start(6))).unwrap(), 6);
+
+        // And back to Tokio's traits.
+        let mut f = Compat::new(f);
+        let mut buf = [0u8; 2];
+        block_on(tokio::io::AsyncReadExt::read_exact(&mut f, &mut buf)).unwrap();
+        assert_eq!(&buf, b"67");
+        let pos = block_on(tokio::io::AsyncSeekExt::seek(&mut f, SeekFrom::Current(-4)));
+        assert_eq!(pos.unwrap(), 4);
+        let mut rest = Vec::new();
+        block_on(tokio::io::AsyncReadExt::read_to_end(&mut f, &mut rest)).unwrap();
+        assert_eq!(rest, b"456789");
+    }
+}
@@ -0,0 +1,206 @@
+mod rar5;
+
+pub use rar5::{
+    PasswordCheck, Rar5Key, MAX_KDF_LG2_COUNT, PASSWORD_CHECK_CSUM_SIZE, PASSWORD_CHECK_SIZE,
+    SALT_SIZE,
+};
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockCipher, NewBlockCipher};
+use aes::Aes256;
+use futures::io::AsyncRead;
+use futures::task::{Context, Poll};
+use std::io;
+use std::pin::Pin;
+
+pub const AES_BLOCK_SIZE: usize = 16;
+
+// AES-256 in CBC mode, decrypting any number of whole blocks at a time.
+pub struct CbcDecryptor {
+    cipher: Aes256,
+    prev: [u8; AES_BLOCK_SIZE],
+}
+
+impl CbcDecryptor {
+    pub fn new(key: &[u8; 32], iv: &[u8; AES_BLOCK_SIZE]) -> CbcDecryptor {
+        CbcDecryptor {
+            cipher: Aes256::new(GenericArray::from_slice(key)),
+            prev: *iv,
+        }
+    }
+
+    // Decrypt `data` in place. Its length must be a multiple of the block
+    // size.
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        debug_assert_eq!(data.len() % AES_BLOCK_SIZE, 0);
+        for chunk in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+            let mut saved = [0u8; AES_BLOCK_SIZE];
+            saved.copy_from_slice(chunk);
+            self.cipher
+                .decrypt_block(GenericArray::from_mut_slice(chunk));
+            for (b, p) in chunk.iter_mut().zip(self.prev.iter()) {
+                *b ^= p;
+            }
+            self.prev = saved;
+        }
+    }
+}
+
+// Decrypts `packed_size` bytes of AES-CBC encrypted data read from `inner`.
+// Encrypted entries are padded to the block size, so `packed_size` is always
+// a multiple of it.
+pub struct DecryptReader<T> {
+    inner: T,
+    decryptor: CbcDecryptor,
+    remaining: u64,
+    buf: Vec<u8>,
+    // Decrypted bytes are buf[pos..decrypted], anything after is ciphertext
+    // waiting for the rest of its block.
+    pos: usize,
+    decrypted: usize,
+}
+
+impl<T: AsyncRead + Unpin> DecryptReader<T> {
+    pub fn new(inner: T, decryptor: CbcDecryptor, packed_size: u64) -> DecryptReader<T> {
+        DecryptReader {
+            inner,
+            decryptor,
+            remaining: packed_size,
+            buf: Vec::new(),
+            pos: 0,
+            decrypted: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for DecryptReader<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        while this.pos == this.decrypted {
+            if this.remaining == 0 {
+                if this.buf.len() > this.decrypted {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "encrypted data is not a whole number of blocks",
+                    )));
+                }
+                return Poll::Ready(Ok(0));
+            }
+
+            // Keep the partial block, if any, and read more after it.
+            this.buf.drain(..this.decrypted);
+            this.pos = 0;
+            this.decrypted = 0;
+
+            let have = this.buf.len();
+            let want = this.remaining.min(0x8000) as usize;
+            this.buf.resize(have + want, 0);
+            let n = match Pin::new(&mut this.inner).poll_read(cx, &mut this.buf[have..]) {
+                Poll::Pending => {
+                    this.buf.truncate(have);
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(e)) => {
+                    this.buf.truncate(have);
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Ready(Ok(n)) => n,
+            };
+            this.buf.truncate(have + n);
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.remaining -= n as u64;
+
+            let whole = this.buf.len() - this.buf.len() % AES_BLOCK_SIZE;
+            this.decryptor.decrypt(&mut this.buf[..whole]);
+            this.decrypted = whole;
+        }
+
+        let n = (this.decrypted - this.pos).min(out.len());
+        out[..n].copy_from_slice(&this.buf[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+
+    fn fips197_key() -> [u8; 32] {
+        let mut key = [0u8; 32];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        key
+    }
+
+    fn fips197_ciphertext() -> Vec<u8> {
+        vec![
+            0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49,
+            0x60, 0x89,
+        ]
+    }
+
+    fn fips197_plaintext() -> Vec<u8> {
+        vec![
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ]
+    }
+
+    #[test]
+    fn test_cbc_decrypts_fips197_block() {
+        let mut data = fips197_ciphertext();
+        CbcDecryptor::new(&fips197_key(), &[0; 16]).decrypt(&mut data);
+        assert_eq!(data, fips197_plaintext());
+    }
+
+    #[test]
+    fn test_cbc_chains_blocks() {
+        // The second block decrypts to the same AES output, xored with the
+        // first ciphertext block.
+        let mut data = fips197_ciphertext();
+        data.extend(fips197_ciphertext());
+        CbcDecryptor::new(&fips197_key(), &[0; 16]).decrypt(&mut data);
+
+        let expected: Vec<u8> = fips197_plaintext()
+            .iter()
+            .zip(fips197_ciphertext().iter())
+            .map(|(p, c)| p ^ c)
+            .collect();
+        assert_eq!(&data[..16], &fips197_plaintext()[..]);
+        assert_eq!(&data[16..], &expected[..]);
+    }
+
+    #[test]
+    fn test_decrypt_reader_stops_at_packed_size() {
+        let mut file = fips197_ciphertext();
+        file.extend(vec![0xaa; 16]);
+        let decryptor = CbcDecryptor::new(&fips197_key(), &[0; 16]);
+        let mut reader = DecryptReader::new(&file[..], decryptor, 16);
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, fips197_plaintext());
+    }
+
+    #[test]
+    fn test_decrypt_reader_rejects_partial_block() {
+        let file = fips197_ciphertext();
+        let decryptor = CbcDecryptor::new(&fips197_key(), &[0; 16]);
+        let mut reader = DecryptReader::new(&file[..12], decryptor, 12);
+        let mut out = Vec::new();
+        assert!(block_on(reader.read_to_end(&mut out)).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use crate::error::{Error, Result};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SALT_SIZE: usize = 16;
+pub const PASSWORD_CHECK_SIZE: usize = 8;
+pub const PASSWORD_CHECK_CSUM_SIZE: usize = 4;
+
+// RAR refuses KDF iteration counts above 2^24.
+pub const MAX_KDF_LG2_COUNT: u8 = 24;
+
+// The password check value stored in RAR5 encryption records, along with the
+// checksum that protects it against corruption.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PasswordCheck {
+    pub value: [u8; PASSWORD_CHECK_SIZE],
+    pub csum: [u8; PASSWORD_CHECK_CSUM_SIZE],
+}
+
+impl PasswordCheck {
+    pub fn from_bytes(buf: &[u8]) -> PasswordCheck {
+        let mut value = [0u8; PASSWORD_CHECK_SIZE];
+        let mut csum = [0u8; PASSWORD_CHECK_CSUM_SIZE];
+        value.copy_from_slice(&buf[..PASSWORD_CHECK_SIZE]);
+        csum.copy_from_slice(&buf[PASSWORD_CHECK_SIZE..PASSWORD_CHECK_SIZE + PASSWORD_CHECK_CSUM_SIZE]);
+        PasswordCheck { value, csum }
+    }
+
+    // A check value whose checksum doesn't match was damaged and can't be
+    // used to reject passwords.
+    pub fn is_intact(&self) -> bool {
+        Sha256::digest(&self.value)[..PASSWORD_CHECK_CSUM_SIZE] == self.csum
+    }
+}
+
+// Keys derived from a password for one salt and iteration count.
+#[derive(Clone)]
+pub struct Rar5Key {
+    key: [u8; 32],
+    hash_key: [u8; 32],
+    password_check: [u8; PASSWORD_CHECK_SIZE],
+}
+
+impl ::std::fmt::Debug for Rar5Key {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Rar5Key{{ .. }}")
+    }
+}
+
+impl Rar5Key {
+    // PBKDF2-HMAC-SHA256 over 2^lg2_count iterations gives the AES key;
+    // continuing the same chain for 16 and 32 more iterations gives the key
+    // used to tweak checksums and the password check value.
+    pub fn derive(password: &str, salt: &[u8], lg2_count: u8) -> Result<Rar5Key> {
+        if lg2_count > MAX_KDF_LG2_COUNT {
+            return Err(Error::bad_block(format!(
+                "KDF iteration count 2^{} too large",
+                lg2_count
+            )));
+        }
+
+        let mac = HmacSha256::new_varkey(password.as_bytes()).expect("HMAC takes any key size");
+
+        let mut first = mac.clone();
+        first.update(salt);
+        first.update(&[0, 0, 0, 1]);
+        let mut u = [0u8; 32];
+        u.copy_from_slice(&first.finalize().into_bytes());
+        let mut acc = u;
+
+        let mut outputs = [[0u8; 32]; 3];
+        let counts = [(1u32 << lg2_count) - 1, 16, 16];
+        for (output, &count) in outputs.iter_mut().zip(counts.iter()) {
+            for _ in 0..count {
+                let mut next = mac.clone();
+                next.update(&u);
+                u.copy_from_slice(&next.finalize().into_bytes());
+                for (a, b) in acc.iter_mut().zip(u.iter()) {
+                    *a ^= b;
+                }
+            }
+            *output = acc;
+        }
+
+        let mut password_check = [0u8; PASSWORD_CHECK_SIZE];
+        for (i, b) in outputs[2].iter().enumerate() {
+            password_check[i % PASSWORD_CHECK_SIZE] ^= b;
+        }
+
+        Ok(Rar5Key {
+            key: outputs[0],
+            hash_key: outputs[1],
+            password_check,
+        })
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    pub fn hash_key(&self) -> &[u8; 32] {
+        &self.hash_key
+    }
+
+    pub fn password_check(&self) -> &[u8; PASSWORD_CHECK_SIZE] {
+        &self.password_check
+    }
+
+    // Fail with `WrongPassword` if an intact stored check value doesn't
+    // match this key.
+    pub fn verify(&self, check: &PasswordCheck) -> Result<()> {
+        if check.is_intact() && check.value != self.password_check {
+            return Err(Error::wrong_password());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn test_derive_matches_pbkdf2_sha256_one_iteration() {
+        let key = Rar5Key::derive("password", b"salt", 0).unwrap();
+        assert_eq!(
+            key.key(),
+            &[
+                0x12, 0x0f, 0xb6, 0xcf, 0xfc, 0xf8, 0xb3, 0x2c, 0x43, 0xe7, 0x22, 0x52, 0x56, 0xc4,
+                0xf8, 0x37, 0xa8, 0x65, 0x48, 0xc9, 0x2c, 0xcc, 0x35, 0x48, 0x08, 0x05, 0x98, 0x7c,
+                0xb7, 0x0b, 0xe1, 0x7b
+            ]
+        );
+    }
+
+    #[test]
+    fn test_derive_matches_pbkdf2_sha256_4096_iterations() {
+        let key = Rar5Key::derive("password", b"salt", 12).unwrap();
+        assert_eq!(
+            key.key(),
+            &[
+                0xc5, 0xe4, 0x78, 0xd5, 0x92, 0x88, 0xc8, 0x41, 0xaa, 0x53, 0x0d, 0xb6, 0x84, 0x5c,
+                0x4c, 0x8d, 0x96, 0x28, 0x93, 0xa0, 0x01, 0xce, 0x4e, 0x11, 0xa4, 0x96, 0x38, 0x73,
+                0xaa, 0x98, 0x13, 0x4a
+            ]
+        );
+    }
+
+    #[test]
+    fn test_derived_values_differ() {
+        let key = Rar5Key::derive("password", b"salt", 1).unwrap();
+        assert_ne!(key.key(), key.hash_key());
+    }
+
+    #[test]
+    fn test_derive_rejects_huge_iteration_count() {
+        assert!(Rar5Key::derive("password", b"salt", 25).is_err());
+    }
+
+    fn check_for(key: &Rar5Key) -> PasswordCheck {
+        let value = *key.password_check();
+        let mut csum = [0u8; PASSWORD_CHECK_CSUM_SIZE];
+        csum.copy_from_slice(&Sha256::digest(&value)[..PASSWORD_CHECK_CSUM_SIZE]);
+        PasswordCheck { value, csum }
+    }
+
+    #[test]
+    fn test_verify_accepts_right_password() {
+        let key = Rar5Key::derive("roar", b"0123456789abcdef", 2).unwrap();
+        assert!(key.verify(&check_for(&key)).is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_wrong_password() {
+        let key = Rar5Key::derive("roar", b"0123456789abcdef", 2).unwrap();
+        let other = Rar5Key::derive("meow", b"0123456789abcdef", 2).unwrap();
+        let err = other.verify(&check_for(&key)).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::WrongPassword);
+    }
+
+    #[test]
+    fn test_verify_ignores_damaged_check_value() {
+        let key = Rar5Key::derive("roar", b"0123456789abcdef", 2).unwrap();
+        let other = Rar5Key::derive("meow", b"0123456789abcdef", 2).unwrap();
+        let mut check = check_for(&key);
+        check.csum[0] ^= 1;
+        assert!(other.verify(&check).is_ok());
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+// A timestamp in the packed MS-DOS format used by RAR 1.x-4.x headers:
+// the date in the high 16 bits, the time in the low 16, with two second
+// resolution.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct DosDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DosDateTime {
+    pub fn from_u32(raw: u32) -> DosDateTime {
+        DosDateTime {
+            year: 1980 + (raw >> 25) as u16,
+            month: ((raw >> 21) & 0x0f) as u8,
+            day: ((raw >> 16) & 0x1f) as u8,
+            hour: ((raw >> 11) & 0x1f) as u8,
+            minute: ((raw >> 5) & 0x3f) as u8,
+            second: ((raw & 0x1f) * 2) as u8,
+        }
+    }
+
+    // The UTC date and time of a Unix timestamp, as stored by RAR5, in the
+    // same broken down form.
+    pub fn from_unix_time(secs: u64) -> DosDateTime {
+        // Days since 0000-03-01, so that leap days end the year.
+        let days = secs / 86400 + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        let time = secs % 86400;
+        DosDateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DosDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unpacks_fields() {
+        // 2004-11-19 23:01:22
+        let time = DosDateTime::from_u32(0x3173_b82b);
+        assert_eq!(time.to_string(), "2004-11-19 23:01:22");
+    }
+
+    #[test]
+    fn test_converts_unix_time() {
+        assert_eq!(
+            DosDateTime::from_unix_time(0).to_string(),
+            "1970-01-01 00:00:00"
+        );
+        assert_eq!(
+            DosDateTime::from_unix_time(1_101_164_483).to_string(),
+            "2004-11-22 23:01:23"
+        );
+        // A leap day.
+        assert_eq!(
+            DosDateTime::from_unix_time(951_827_696).to_string(),
+            "2000-02-29 12:34:56"
+        );
+    }
+}
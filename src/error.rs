@@ -26,9 +26,30 @@ impl Error {
         Error::from(ErrorKind::Aio(wrapped.to_string()))
     }
 
+    // Count the size required by a BufferTooSmall error from `offset` bytes
+    // earlier, for parsers working on part of a buffer.
+    pub fn offset_required(self, offset: usize) -> Error {
+        match *self.kind() {
+            ErrorKind::BufferTooSmall(required) => Error::buffer_too_small(offset + required),
+            _ => self,
+        }
+    }
+
     pub fn bad_block(reason: String) -> Error {
         Error::from(ErrorKind::BadBlock(reason))
     }
+
+    pub fn bad_data(reason: String) -> Error {
+        Error::from(ErrorKind::BadData(reason))
+    }
+
+    pub fn unsupported(what: String) -> Error {
+        Error::from(ErrorKind::Unsupported(what))
+    }
+
+    pub fn wrong_password() -> Error {
+        Error::from(ErrorKind::WrongPassword)
+    }
 }
 
 impl Fail for Error {
@@ -60,6 +81,15 @@ pub enum ErrorKind {
 
     // Invalid block (corrupt archive?)
     BadBlock(String),
+
+    // Invalid compressed data (corrupt entry?)
+    BadData(String),
+
+    // A valid archive using a feature this crate doesn't implement.
+    Unsupported(String),
+
+    // The supplied password does not decrypt the archive or entry.
+    WrongPassword,
 }
 
 impl fmt::Display for ErrorKind {
@@ -77,6 +107,13 @@ impl fmt::Display for ErrorKind {
                 "Block Decoding error: {} (perhaps the archive is corrupt)",
                 msg
             ),
+            ErrorKind::BadData(ref msg) => write!(
+                f,
+                "Decompression error: {} (perhaps the entry is corrupt)",
+                msg
+            ),
+            ErrorKind::Unsupported(ref msg) => write!(f, "Unsupported: {}", msg),
+            ErrorKind::WrongPassword => write!(f, "Wrong password"),
         }
     }
 }
@@ -98,3 +135,9 @@ impl From<::std::io::Error> for Error {
         Error::io(e)
     }
 }
+
+impl From<Error> for ::std::io::Error {
+    fn from(e: Error) -> ::std::io::Error {
+        ::std::io::Error::new(::std::io::ErrorKind::InvalidData, e.to_string())
+    }
+}
@@ -0,0 +1,788 @@
+// Extracting files to a directory, the way `unrar x` does. Files are
+// unpacked and checked the same way `Archive::test` does it; on top of that
+// this picks the files to take by name, where each one goes, and what
+// happens to files already there.
+
+use crate::archive::{Archive, Entry, Format};
+use crate::dostime::DosDateTime;
+use crate::error::{Error, ErrorKind, Result};
+use crate::integrity::discard;
+use crate::links::{normalize, Link};
+use crate::owner::{restore_mode, restore_owner, OwnerOptions, UnixOwner};
+use crate::rar14::{FileHeader14, MainHeader14};
+use crate::rar5::{Rar5Entry, Rar5Unpacking};
+use crate::streams::{write_stream, StreamMode};
+use crate::traits::AsyncFile;
+use futures::io::{AllowStdIo, AsyncSeek};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// What to do when a file to extract is already there.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Overwrite {
+    Always,
+    Never,
+    // Write to a new name instead, like `file(1).txt`.
+    Rename,
+    // Only replace files older than the archived one.
+    Newer,
+}
+
+impl Overwrite {
+    pub fn from_name(name: &str) -> Option<Overwrite> {
+        match name {
+            "always" => Some(Overwrite::Always),
+            "never" => Some(Overwrite::Never),
+            "rename" => Some(Overwrite::Rename),
+            "newer" => Some(Overwrite::Newer),
+            _ => None,
+        }
+    }
+}
+
+// Which files to extract, by name. With no include patterns every file is
+// included, and exclude patterns win over include ones.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, name)))
+            && !self.exclude.iter().any(|p| glob_match(p, name))
+    }
+}
+
+// Match `name` against a pattern where `*` stands for any characters within
+// a path component and `?` for any one character. Patterns without a path
+// separator only look at the last component of the name. Both `/` and `\`
+// work as separators.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.replace('\\', "/").chars().collect();
+    let name = name.replace('\\', "/");
+    let name = if pattern.contains(&'/') {
+        &name[..]
+    } else {
+        name.rsplit('/').next().unwrap_or("")
+    };
+    let name: Vec<char> = name.chars().collect();
+    glob_match_chars(&pattern, &name)
+}
+
+fn glob_match_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len())
+            .take_while(|&i| i == 0 || name[i - 1] != '/')
+            .any(|i| glob_match_chars(&pattern[1..], &name[i..])),
+        Some('?') => match name.first() {
+            Some(&c) if c != '/' => glob_match_chars(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(c) => name.first() == Some(c) && glob_match_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    pub output: PathBuf,
+    // Recreate the directories stored in the archive, or put every file
+    // straight into `output`.
+    pub full_paths: bool,
+    pub overwrite: Overwrite,
+    pub filter: Filter,
+    // For RAR5 archives with encrypted files or headers.
+    pub password: Option<String>,
+    // How to write the NTFS streams of RAR 1.5-4.x files.
+    pub streams: StreamMode,
+    pub owner: OwnerOptions,
+}
+
+impl ExtractOptions {
+    pub fn new<P: Into<PathBuf>>(output: P) -> ExtractOptions {
+        ExtractOptions {
+            output: output.into(),
+            full_paths: true,
+            overwrite: Overwrite::Never,
+            filter: Filter::default(),
+            password: None,
+            streams: StreamMode::Skip,
+            owner: OwnerOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Extracted {
+    // Written to this path, relative to the output directory.
+    Written(PathBuf),
+    // Already there, and kept by the overwrite policy.
+    Kept(PathBuf),
+    Failed(String),
+    // The file uses something this crate can't unpack, e.g. encryption
+    // without a password.
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractedEntry {
+    pub name: String,
+    pub outcome: Extracted,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtractReport {
+    // Every file the filter picked, in archive order.
+    pub entries: Vec<ExtractedEntry>,
+    // Why the archive couldn't be read to the end, if it couldn't.
+    pub error: Option<String>,
+}
+
+impl ExtractReport {
+    // Whether everything picked was extracted or kept. Skipped files don't
+    // count as failures.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.failures() == 0
+    }
+
+    pub fn failures(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.outcome, Extracted::Failed(_)))
+            .count()
+    }
+
+    fn add(&mut self, name: String, result: Result<Option<Extracted>>) {
+        let outcome = match result {
+            Ok(Some(outcome)) => outcome,
+            Ok(None) => return,
+            Err(e) => match *e.kind() {
+                ErrorKind::Unsupported(ref what) => Extracted::Skipped(what.clone()),
+                _ => Extracted::Failed(e.to_string()),
+            },
+        };
+        self.entries.push(ExtractedEntry { name, outcome });
+    }
+}
+
+// What to do with one file, decided before unpacking anything.
+enum Plan {
+    // Directories when extracting without paths.
+    Nothing,
+    Directory(PathBuf),
+    Write(PathBuf),
+    Keep(PathBuf),
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Extract the files picked by `options.filter` from the archive, opened
+    // right after the signature. RAR 1.5-4.x files are listed with the
+    // `read_entry` settings.
+    pub async fn extract_all(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        match self.format() {
+            Format::Rar15 => self.extract_rar15(options).await,
+            Format::Rar50 => self.extract_rar5(options).await,
+            Format::Rar14 => self.extract_rar14(options).await,
+        }
+    }
+
+    async fn extract_rar14(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        let mut report = ExtractReport::default();
+        if let Err(e) = MainHeader14::read(self).await {
+            report.error = Some(e.to_string());
+            return Ok(report);
+        }
+        loop {
+            let header = match FileHeader14::read(self).await {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            };
+            let data_offset = self.position();
+            if options.filter.matches(&header.name) {
+                let result = self.extract_rar14_entry(&header, options).await;
+                report.add(header.name.clone(), result);
+            }
+            self.skip_to(data_offset + u64::from(header.packed_size))
+                .await?;
+        }
+        Ok(report)
+    }
+
+    async fn extract_rar14_entry(
+        &mut self,
+        header: &FileHeader14,
+        options: &ExtractOptions,
+    ) -> Result<Option<Extracted>> {
+        let mtime = Some(DosDateTime::from_u32(header.mtime));
+        let path = match plan(options, &header.name, header.is_directory(), mtime)? {
+            Plan::Nothing => return Ok(None),
+            Plan::Directory(path) => return create_directory(options, path, None).map(Some),
+            Plan::Keep(path) => return Ok(Some(Extracted::Kept(path))),
+            Plan::Write(path) => path,
+        };
+        let dest = prepare(options, &path)?;
+        let mut out = AllowStdIo::new(fs::File::create(&dest)?);
+        if let Err(e) = self.unpack_rar14(header, &mut out).await {
+            drop(out);
+            fs::remove_file(&dest)?;
+            return Err(e);
+        }
+        drop(out);
+        restore(options, &dest, Some(header.permissions().unix_mode), None)?;
+        Ok(Some(Extracted::Written(path)))
+    }
+
+    async fn extract_rar15(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        let mut report = ExtractReport::default();
+        let mut entries = Vec::new();
+        loop {
+            match self.read_entry().await {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => break,
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        for entry in &entries {
+            let name = entry.file.header().full_name();
+            if options.filter.matches(&name) {
+                let result = self.extract_rar15_entry(entry, &name, options).await;
+                report.add(name, result);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn extract_rar15_entry(
+        &mut self,
+        entry: &Entry,
+        name: &str,
+        options: &ExtractOptions,
+    ) -> Result<Option<Extracted>> {
+        let header = entry.file.header();
+        let owner = entry.owner.as_ref();
+        let path = match plan(options, name, header.is_directory(), Some(header.mtime()))? {
+            Plan::Nothing => return Ok(None),
+            Plan::Directory(path) => return create_directory(options, path, owner).map(Some),
+            Plan::Keep(path) => return Ok(Some(Extracted::Kept(path))),
+            Plan::Write(path) => path,
+        };
+        if let Some(ref link) = entry.link {
+            return create_link(options, link, path, owner).map(Some);
+        }
+        let dest = prepare(options, &path)?;
+        let mut out = AllowStdIo::new(fs::File::create(&dest)?);
+        if let Err(e) = self.unpack_rar15(entry, &mut out).await {
+            drop(out);
+            fs::remove_file(&dest)?;
+            return Err(e);
+        }
+        drop(out);
+        for stream in &entry.streams {
+            write_stream(options.streams, &dest, stream)
+                .await
+                .map_err(|e| Error::bad_data(format!("Stream {}: {}", stream.name, e)))?;
+        }
+        restore(options, &dest, Some(header.permissions().unix_mode), owner)?;
+        Ok(Some(Extracted::Written(path)))
+    }
+
+    async fn extract_rar5(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        let mut report = ExtractReport::default();
+        let password = options.password.as_ref().map(|p| &p[..]);
+        let listing = match self.list_rar5_with_password(password).await {
+            Ok(listing) => listing,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                return Ok(report);
+            }
+        };
+        if listing.end.is_none() {
+            report.error = Some("Missing end of archive header".into());
+        }
+        let mut state = Rar5Unpacking::new(&listing, options.password.clone());
+        for entry in &listing.entries {
+            let name = &entry.file.name;
+            if options.filter.matches(name) {
+                let result = self.extract_rar5_entry(entry, &mut state, options).await;
+                report.add(name.clone(), result);
+            } else {
+                self.skip_rar5_entry(entry, &mut state).await;
+            }
+        }
+        Ok(report)
+    }
+
+    async fn extract_rar5_entry(
+        &mut self,
+        entry: &Rar5Entry,
+        state: &mut Rar5Unpacking,
+        options: &ExtractOptions,
+    ) -> Result<Option<Extracted>> {
+        let file = &entry.file;
+        let owner = file.owner.as_ref();
+        let mtime = file
+            .mtime
+            .map(|t| DosDateTime::from_unix_time(u64::from(t)));
+        let planned = plan(options, &file.name, file.is_directory(), mtime);
+        let path = match planned {
+            Ok(Plan::Write(path)) => path,
+            Ok(Plan::Nothing) => return Ok(None),
+            Ok(Plan::Directory(path)) => {
+                return create_directory(options, path, owner).map(Some);
+            }
+            Ok(Plan::Keep(path)) => {
+                self.skip_rar5_entry(entry, state).await;
+                return Ok(Some(Extracted::Kept(path)));
+            }
+            Err(e) => {
+                self.skip_rar5_entry(entry, state).await;
+                return Err(e);
+            }
+        };
+        if let Some(ref link) = file.link {
+            return create_link(options, link, path, owner).map(Some);
+        }
+        let created = prepare(options, &path).and_then(|dest| Ok((fs::File::create(&dest)?, dest)));
+        let (out, dest) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                self.skip_rar5_entry(entry, state).await;
+                return Err(e);
+            }
+        };
+        let mut out = AllowStdIo::new(out);
+        if let Err(e) = self.unpack_rar5(entry, state, &mut out).await {
+            drop(out);
+            fs::remove_file(&dest)?;
+            return Err(e);
+        }
+        drop(out);
+        restore(options, &dest, Some(file.permissions().unix_mode), owner)?;
+        Ok(Some(Extracted::Written(path)))
+    }
+
+    // Solid files continue the decoder state of the ones before them, so
+    // those not extracted still have to be unpacked.
+    async fn skip_rar5_entry(&mut self, entry: &Rar5Entry, state: &mut Rar5Unpacking) {
+        if state.is_solid() && !entry.file.is_directory() && entry.file.link.is_none() {
+            // A failure shows up again on the next file that needs it.
+            let _ = self.unpack_rar5(entry, state, &mut discard()).await;
+        }
+    }
+}
+
+fn plan(
+    options: &ExtractOptions,
+    name: &str,
+    directory: bool,
+    mtime: Option<DosDateTime>,
+) -> Result<Plan> {
+    let path = output_path(name, options.full_paths)?;
+    if directory {
+        return Ok(if options.full_paths {
+            Plan::Directory(path)
+        } else {
+            Plan::Nothing
+        });
+    }
+    let existing = match fs::symlink_metadata(options.output.join(&path)) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(Plan::Write(path)),
+    };
+    Ok(match options.overwrite {
+        Overwrite::Always => Plan::Write(path),
+        Overwrite::Never => Plan::Keep(path),
+        Overwrite::Rename => Plan::Write(free_path(&options.output, &path)),
+        Overwrite::Newer => {
+            // DOS times are local, but this is only a rough comparison.
+            let modified = existing
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| DosDateTime::from_unix_time(d.as_secs()));
+            match (mtime, modified) {
+                (Some(mtime), Some(modified)) if mtime <= modified => Plan::Keep(path),
+                _ => Plan::Write(path),
+            }
+        }
+    })
+}
+
+// Where to write the file named `name`, relative to the output directory.
+// Names that would lead out of it are refused.
+fn output_path(name: &str, full_paths: bool) -> Result<PathBuf> {
+    let name = name.replace('\\', "/");
+    let outside = || Error::bad_data(format!("Path {:?} is outside of the output", name));
+    let path = normalize(Path::new(&name)).ok_or_else(outside)?;
+    let path = if full_paths {
+        path
+    } else {
+        path.file_name().map(PathBuf::from).unwrap_or_default()
+    };
+    if path.as_os_str().is_empty() {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+// The first of `name(1).ext`, `name(2).ext` and so on that isn't taken.
+fn free_path(output: &Path, path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|e| e.to_string_lossy());
+    (1..)
+        .map(|n| {
+            let name = match extension {
+                Some(ref extension) => format!("{}({}).{}", stem, n, extension),
+                None => format!("{}({})", stem, n),
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| fs::symlink_metadata(output.join(candidate)).is_err())
+        .unwrap()
+}
+
+// Directories keep the default mode, so that what goes in them can still be
+// written.
+fn create_directory(
+    options: &ExtractOptions,
+    path: PathBuf,
+    owner: Option<&UnixOwner>,
+) -> Result<Extracted> {
+    refuse_links(&options.output, &path)?;
+    let dest = options.output.join(&path);
+    fs::create_dir_all(&dest)?;
+    restore(options, &dest, None, owner)?;
+    Ok(Extracted::Written(path))
+}
+
+// Links only get their owner, as changing their mode would change that of
+// the target. Hard links and copies are made from the extracted entry, which
+// mustn't be reached through a link either.
+fn create_link(
+    options: &ExtractOptions,
+    link: &Link,
+    path: PathBuf,
+    owner: Option<&UnixOwner>,
+) -> Result<Extracted> {
+    if link.kind.targets_entry() {
+        refuse_links(&options.output, &link.resolve(&path)?)?;
+    }
+    let dest = prepare(options, &path)?;
+    link.create(&options.output, &path)?;
+    restore(options, &dest, None, owner)?;
+    Ok(Extracted::Written(path))
+}
+
+// Give what was extracted to `dest` the mode and owner it was archived with.
+fn restore(
+    options: &ExtractOptions,
+    dest: &Path,
+    mode: Option<u32>,
+    owner: Option<&UnixOwner>,
+) -> Result<()> {
+    if let Some(mode) = mode {
+        restore_mode(dest, mode)?;
+    }
+    if let Some(owner) = owner {
+        restore_owner(dest, owner, options.owner)?;
+    }
+    Ok(())
+}
+
+// Make way for a new file at `path`: create the directories it goes in and
+// remove whatever the overwrite policy lets it replace, so that nothing is
+// written through an existing link.
+fn prepare(options: &ExtractOptions, path: &Path) -> Result<PathBuf> {
+    if let Some(parent) = path.parent() {
+        refuse_links(&options.output, parent)?;
+    }
+    let dest = options.output.join(path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(&dest).is_ok() {
+        fs::remove_file(&dest)?;
+    }
+    Ok(dest)
+}
+
+// Fail if any part of `path` already in the output is a symlink. Links
+// extracted earlier could otherwise lead writes out of the output, as each
+// one is only checked on its own.
+fn refuse_links(output: &Path, path: &Path) -> Result<()> {
+    let mut prefix = output.to_path_buf();
+    for component in path.components() {
+        prefix.push(component);
+        match fs::symlink_metadata(&prefix) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(Error::bad_data(format!(
+                    "Path {:?} goes through the link {:?}",
+                    path,
+                    prefix.strip_prefix(output).unwrap_or(&prefix)
+                )))
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{RAR14_SIGNATURE, RAR15_SIGNATURE};
+    use crate::testing::{file_block, rar14_file_header, service_block, FileBlock};
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "docs/a.txt"));
+        assert!(glob_match("?.txt", "a.txt"));
+        assert!(!glob_match("?.txt", "ab.txt"));
+        assert!(glob_match("docs/*", "docs\\a.txt"));
+        assert!(!glob_match("docs/*", "docs/sub/a.txt"));
+        assert!(glob_match("*/*/a.*", "docs/sub/a.txt"));
+        assert!(!glob_match("*.txt", "a.txt.gz"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            include: vec!["*.txt".into(), "*.md".into()],
+            exclude: vec!["secret*".into()],
+        };
+        assert!(filter.matches("a.txt"));
+        assert!(filter.matches("docs/README.md"));
+        assert!(!filter.matches("a.rs"));
+        assert!(!filter.matches("docs/secret.txt"));
+        assert!(Filter::default().matches("anything"));
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(
+            output_path("docs\\a.txt", true).unwrap(),
+            PathBuf::from("docs/a.txt")
+        );
+        assert_eq!(
+            output_path("docs/./a.txt", false).unwrap(),
+            PathBuf::from("a.txt")
+        );
+        assert!(output_path("../a.txt", true).is_err());
+        assert!(output_path("/etc/passwd", true).is_err());
+        assert!(output_path("docs/..", true).is_err());
+    }
+
+    fn extract(data: &[u8], options: &ExtractOptions) -> ExtractReport {
+        let mut archive = block_on(Archive::open(Cursor::new(data.to_vec()))).unwrap();
+        block_on(archive.extract_all(options)).unwrap()
+    }
+
+    fn outcomes(report: &ExtractReport) -> Vec<Extracted> {
+        report.entries.iter().map(|e| e.outcome.clone()).collect()
+    }
+
+    #[test]
+    fn test_extracts_rar15_files() {
+        let output = std::env::temp_dir().join(format!("roar-extract-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&output);
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(
+            FileBlock {
+                flags: 0xe0,
+                ..FileBlock::new(b"docs", b"")
+            }
+            .build(),
+        );
+        data.extend(file_block(b"docs\\a.txt", b"roar"));
+        data.extend(file_block(b"b.md", b"meow"));
+        let written = |p: &str| Extracted::Written(PathBuf::from(p));
+
+        let mut options = ExtractOptions::new(&output);
+        options.filter.include.push("*.txt".into());
+        let report = extract(&data, &options);
+        assert!(report.passed());
+        assert_eq!(outcomes(&report), vec![written("docs/a.txt")]);
+        assert_eq!(fs::read(output.join("docs/a.txt")).unwrap(), b"roar");
+
+        let mut options = ExtractOptions::new(&output);
+        let report = extract(&data, &options);
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                written("docs"),
+                Extracted::Kept(PathBuf::from("docs/a.txt")),
+                written("b.md"),
+            ]
+        );
+
+        options.full_paths = false;
+        options.overwrite = Overwrite::Rename;
+        let report = extract(&data, &options);
+        assert_eq!(
+            outcomes(&report),
+            vec![written("a.txt"), written("b(1).md")]
+        );
+        assert_eq!(fs::read(output.join("b(1).md")).unwrap(), b"meow");
+
+        // Archived files from 1980 are older than anything on disk.
+        options.overwrite = Overwrite::Newer;
+        let report = extract(&data, &options);
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                Extracted::Kept(PathBuf::from("a.txt")),
+                Extracted::Kept(PathBuf::from("b.md")),
+            ]
+        );
+
+        fs::write(output.join("b.md"), b"old").unwrap();
+        options.overwrite = Overwrite::Always;
+        extract(&data, &options);
+        assert_eq!(fs::read(output.join("b.md")).unwrap(), b"meow");
+
+        let mut bad = RAR15_SIGNATURE.to_vec();
+        let mut block = file_block(b"bad.txt", b"roar");
+        *block.last_mut().unwrap() ^= 1;
+        bad.extend(block);
+        let report = extract(&bad, &options);
+        assert_eq!(report.failures(), 1);
+        assert!(!output.join("bad.txt").exists());
+        fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    fn test_extracts_stored_rar14_files() {
+        let output = std::env::temp_dir().join(format!("roar-extract14-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&output);
+        let mut data = RAR14_SIGNATURE.to_vec();
+        data.extend(&[0x07, 0x00, 0x00]);
+        data.extend(rar14_file_header("A.TXT", b"roar", 0, 0));
+        data.extend(rar14_file_header("PACKED.TXT", b"roar", 3, 0));
+        data.extend(rar14_file_header("B.TXT", b"meow", 0, 0));
+
+        let report = extract(&data, &ExtractOptions::new(&output));
+        match &outcomes(&report)[..] {
+            [Extracted::Written(_), Extracted::Skipped(_), Extracted::Written(_)] => {}
+            outcomes => panic!("unexpected outcomes: {:?}", outcomes),
+        }
+        assert_eq!(fs::read(output.join("A.TXT")).unwrap(), b"roar");
+        assert_eq!(fs::read(output.join("B.TXT")).unwrap(), b"meow");
+        assert!(!output.join("PACKED.TXT").exists());
+        fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[test]
+    fn test_writes_streams_next_to_extracted_files() {
+        let output = std::env::temp_dir().join(format!("roar-streams-x-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&output);
+        let stream_name: Vec<u8> = ":thumb:$DATA"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect();
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        data.extend(service_block(b"STM", &stream_name, b"stream"));
+
+        let mut options = ExtractOptions::new(&output);
+        assert!(extract(&data, &options).passed());
+        assert!(!output.join("a.txt:thumb").exists());
+
+        options.overwrite = Overwrite::Always;
+        options.streams = StreamMode::Sidecar;
+        assert!(extract(&data, &options).passed());
+        assert_eq!(fs::read(output.join("a.txt:thumb")).unwrap(), b"stream");
+        fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restores_mode_and_owner() {
+        use crate::owner::OwnerPolicy;
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let output = std::env::temp_dir().join(format!("roar-owner-x-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&output);
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(
+            FileBlock {
+                host_os: 3,
+                attributes: 0o100_600,
+                ..FileBlock::new(b"a.sh", b"roar")
+            }
+            .build(),
+        );
+        data.extend(service_block(b"UOW", b"root\0root", b""));
+        data.extend(file_block(b"b.txt", b"meow"));
+        let mode = |name| {
+            fs::metadata(output.join(name))
+                .unwrap()
+                .permissions()
+                .mode()
+        };
+
+        let mut options = ExtractOptions::new(&output);
+        assert!(extract(&data, &options).passed());
+        assert_eq!(mode("a.sh") & 0o7777, 0o600);
+        assert_eq!(mode("b.txt") & 0o7777, 0o644);
+
+        options.overwrite = Overwrite::Always;
+        options.owner.policy = OwnerPolicy::ByName;
+        options.owner.only_as_root = false;
+        let report = extract(&data, &options);
+        if unsafe { libc::geteuid() } == 0 {
+            assert!(report.passed());
+            assert_eq!(fs::metadata(output.join("a.sh")).unwrap().uid(), 0);
+        } else {
+            assert_eq!(report.failures(), 1);
+        }
+        fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_writes_through_extracted_links() {
+        let base = std::env::temp_dir().join(format!("roar-links-x-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let output = base.join("a/out");
+        fs::create_dir_all(&output).unwrap();
+        let symlink = |name, target| {
+            FileBlock {
+                host_os: 3,
+                attributes: 0o120_777,
+                ..FileBlock::new(name, target)
+            }
+            .build()
+        };
+        // Each link stays inside the output on its own, but the second one
+        // is made inside the first, so it points at the output's parent.
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(symlink(b"d/up", b".."));
+        data.extend(symlink(b"d/up/up2", b".."));
+        data.extend(file_block(b"d/up/up2/pwned.txt", b"roar"));
+
+        let report = extract(&data, &ExtractOptions::new(&output));
+        assert_eq!(
+            outcomes(&report)[0],
+            Extracted::Written(PathBuf::from("d/up"))
+        );
+        assert_eq!(report.failures(), 2);
+        assert!(fs::symlink_metadata(output.join("up2")).is_err());
+        assert!(!base.join("a/pwned.txt").exists());
+        assert!(!output.join("pwned.txt").exists());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
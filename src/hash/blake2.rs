@@ -0,0 +1,260 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+pub const BLAKE2S_BLOCK_SIZE: usize = 64;
+pub const BLAKE2S_DIGEST_SIZE: usize = 32;
+
+// BLAKE2sp runs this many BLAKE2s leaves in parallel.
+const PARALLELISM: usize = 8;
+
+const IV: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+// BLAKE2s tree hashing parameters, as laid out in the parameter block.
+#[derive(Debug, Copy, Clone, Default)]
+struct Params {
+    fanout: u8,
+    depth: u8,
+    node_offset: u32,
+    node_depth: u8,
+    inner_length: u8,
+    last_node: bool,
+}
+
+#[derive(Clone)]
+pub struct Blake2s {
+    h: [u32; 8],
+    t: u64,
+    buf: [u8; BLAKE2S_BLOCK_SIZE],
+    buf_len: usize,
+    last_node: bool,
+}
+
+impl Blake2s {
+    pub fn new() -> Blake2s {
+        Blake2s::with_params(Params {
+            fanout: 1,
+            depth: 1,
+            ..Params::default()
+        })
+    }
+
+    fn with_params(params: Params) -> Blake2s {
+        let mut h = IV;
+        h[0] ^= BLAKE2S_DIGEST_SIZE as u32
+            | (u32::from(params.fanout) << 16)
+            | (u32::from(params.depth) << 24);
+        h[2] ^= params.node_offset;
+        h[3] ^= (u32::from(params.node_depth) << 16) | (u32::from(params.inner_length) << 24);
+        Blake2s {
+            h,
+            t: 0,
+            buf: [0; BLAKE2S_BLOCK_SIZE],
+            buf_len: 0,
+            last_node: params.last_node,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8; BLAKE2S_BLOCK_SIZE], last: bool) {
+        let mut m = [0u32; 16];
+        LittleEndian::read_u32_into(block, &mut m);
+
+        let mut v = [0u32; 16];
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.t as u32;
+        v[13] ^= (self.t >> 32) as u32;
+        if last {
+            v[14] = !v[14];
+            if self.last_node {
+                v[15] = !v[15];
+            }
+        }
+
+        fn g(v: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(12);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(8);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(7);
+        }
+
+        for s in SIGMA.iter() {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The final block is compressed differently, so only compress a
+            // full buffer once we know more data follows it.
+            if self.buf_len == BLAKE2S_BLOCK_SIZE {
+                self.t += BLAKE2S_BLOCK_SIZE as u64;
+                let block = self.buf;
+                self.compress(&block, false);
+                self.buf_len = 0;
+            }
+            let n = (BLAKE2S_BLOCK_SIZE - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + n].copy_from_slice(&data[..n]);
+            self.buf_len += n;
+            data = &data[n..];
+        }
+    }
+
+    pub fn finalize(mut self) -> [u8; BLAKE2S_DIGEST_SIZE] {
+        self.t += self.buf_len as u64;
+        for b in self.buf[self.buf_len..].iter_mut() {
+            *b = 0;
+        }
+        let block = self.buf;
+        self.compress(&block, true);
+
+        let mut out = [0u8; BLAKE2S_DIGEST_SIZE];
+        LittleEndian::write_u32_into(&self.h, &mut out);
+        out
+    }
+}
+
+impl Default for Blake2s {
+    fn default() -> Blake2s {
+        Blake2s::new()
+    }
+}
+
+// BLAKE2sp, the hash RAR5 uses for file checksums: the input is dealt out
+// in 64 byte blocks to 8 BLAKE2s leaves, whose digests are hashed by a root.
+#[derive(Clone)]
+pub struct Blake2sp {
+    leaves: Vec<Blake2s>,
+    // Total bytes hashed so far, which decides the leaf for the next byte.
+    count: u64,
+}
+
+fn tree_params(node_offset: u32, node_depth: u8, last_node: bool) -> Params {
+    Params {
+        fanout: PARALLELISM as u8,
+        depth: 2,
+        node_offset,
+        node_depth,
+        inner_length: BLAKE2S_DIGEST_SIZE as u8,
+        last_node,
+    }
+}
+
+impl Blake2sp {
+    pub fn new() -> Blake2sp {
+        let leaves = (0..PARALLELISM)
+            .map(|i| Blake2s::with_params(tree_params(i as u32, 0, i == PARALLELISM - 1)))
+            .collect();
+        Blake2sp { leaves, count: 0 }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let block = (self.count / BLAKE2S_BLOCK_SIZE as u64) as usize;
+            let offset = (self.count % BLAKE2S_BLOCK_SIZE as u64) as usize;
+            let n = (BLAKE2S_BLOCK_SIZE - offset).min(data.len());
+            self.leaves[block % PARALLELISM].update(&data[..n]);
+            self.count += n as u64;
+            data = &data[n..];
+        }
+    }
+
+    pub fn finalize(self) -> [u8; BLAKE2S_DIGEST_SIZE] {
+        let mut root = Blake2s::with_params(tree_params(0, 1, true));
+        for leaf in self.leaves {
+            root.update(&leaf.finalize());
+        }
+        root.finalize()
+    }
+}
+
+impl Default for Blake2sp {
+    fn default() -> Blake2sp {
+        Blake2sp::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_blake2s_abc() {
+        let mut h = Blake2s::new();
+        h.update(b"abc");
+        assert_eq!(
+            hex(&h.finalize()),
+            "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982"
+        );
+    }
+
+    #[test]
+    fn test_blake2sp_empty() {
+        assert_eq!(
+            hex(&Blake2sp::new().finalize()),
+            "dd0e891776933f43c7d032b08a917e25741f8aa9a12c12e1cac8801500f2ca4f"
+        );
+    }
+
+    #[test]
+    fn test_blake2sp_abc() {
+        let mut h = Blake2sp::new();
+        h.update(b"abc");
+        assert_eq!(
+            hex(&h.finalize()),
+            "70f75b58f1fecab821db43c88ad84edde5a52600616cd22517b7bb14d440a7d5"
+        );
+    }
+
+    #[test]
+    fn test_blake2sp_spans_several_stripes_in_odd_chunks() {
+        let data: Vec<u8> = (0..1280).map(|i| i as u8).collect();
+        let mut h = Blake2sp::new();
+        for chunk in data.chunks(37) {
+            h.update(chunk);
+        }
+        assert_eq!(
+            hex(&h.finalize()),
+            "f8643dbeacfcc98c1de662e35a800fd8dfc8a369ce62ed514e813e65728ed379"
+        );
+    }
+}
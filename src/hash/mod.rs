@@ -0,0 +1,280 @@
+mod blake2;
+
+pub use blake2::{Blake2s, Blake2sp, BLAKE2S_DIGEST_SIZE};
+
+use crc::crc32;
+use crc::crc32::Hasher32;
+use futures::io::AsyncRead;
+use futures::task::{Context, Poll};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum HashType {
+    Crc32,
+    Blake2sp,
+    // The 16 bit checksum of RAR 1.4 archives.
+    Checksum14,
+}
+
+impl fmt::Display for HashType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HashType::Crc32 => write!(f, "CRC32"),
+            HashType::Blake2sp => write!(f, "BLAKE2sp"),
+            HashType::Checksum14 => write!(f, "RAR 1.4 checksum"),
+        }
+    }
+}
+
+// A checksum of an entry's unpacked contents.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileHash {
+    Crc32(u32),
+    Blake2sp([u8; BLAKE2S_DIGEST_SIZE]),
+    Checksum14(u16),
+}
+
+impl FileHash {
+    pub fn hash_type(self) -> HashType {
+        match self {
+            FileHash::Crc32(_) => HashType::Crc32,
+            FileHash::Blake2sp(_) => HashType::Blake2sp,
+            FileHash::Checksum14(_) => HashType::Checksum14,
+        }
+    }
+
+    // Encrypted RAR5 entries can store their checksums passed through
+    // HMAC-SHA256 keyed with the password derived hash key, so the plain
+    // checksum doesn't leak information about the contents.
+    pub fn to_mac(self, hash_key: &[u8; 32]) -> FileHash {
+        let mut mac = Hmac::<Sha256>::new_varkey(hash_key).expect("HMAC takes any key size");
+        match self {
+            FileHash::Crc32(crc) => {
+                mac.update(&crc.to_le_bytes());
+                let digest = mac.finalize().into_bytes();
+                let mut folded: u32 = 0;
+                for (i, &b) in digest.iter().enumerate() {
+                    folded ^= u32::from(b) << ((i & 3) * 8);
+                }
+                FileHash::Crc32(folded)
+            }
+            FileHash::Blake2sp(digest) => {
+                mac.update(&digest);
+                let mut out = [0u8; BLAKE2S_DIGEST_SIZE];
+                out.copy_from_slice(&mac.finalize().into_bytes());
+                FileHash::Blake2sp(out)
+            }
+            // Only RAR5 stores MACs.
+            FileHash::Checksum14(_) => self,
+        }
+    }
+}
+
+impl fmt::Display for FileHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileHash::Crc32(crc) => write!(f, "{:08X}", crc),
+            FileHash::Blake2sp(ref digest) => {
+                for b in digest.iter() {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+            FileHash::Checksum14(sum) => write!(f, "{:04X}", sum),
+        }
+    }
+}
+
+pub enum Hasher {
+    Crc32(Box<crc32::Digest>),
+    Blake2sp(Box<Blake2sp>),
+    Checksum14(u16),
+}
+
+// Add `data` to a running RAR 1.4 checksum.
+pub fn checksum14(mut sum: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        sum = sum.wrapping_add(u16::from(b)).rotate_left(1);
+    }
+    sum
+}
+
+impl Hasher {
+    pub fn new(hash_type: HashType) -> Hasher {
+        match hash_type {
+            HashType::Crc32 => Hasher::Crc32(Box::new(crc32::Digest::new(crc32::IEEE))),
+            HashType::Blake2sp => Hasher::Blake2sp(Box::default()),
+            HashType::Checksum14 => Hasher::Checksum14(0),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Crc32(ref mut digest) => digest.write(data),
+            Hasher::Blake2sp(ref mut digest) => digest.update(data),
+            Hasher::Checksum14(ref mut sum) => *sum = checksum14(*sum, data),
+        }
+    }
+
+    pub fn finalize(self) -> FileHash {
+        match self {
+            Hasher::Crc32(digest) => FileHash::Crc32(digest.sum32()),
+            Hasher::Blake2sp(digest) => FileHash::Blake2sp(digest.finalize()),
+            Hasher::Checksum14(sum) => FileHash::Checksum14(sum),
+        }
+    }
+}
+
+// Hashes everything read through it and, on reaching the end, fails the read
+// if the result doesn't match `expected`.
+pub struct VerifyReader<R> {
+    inner: R,
+    hasher: Option<Hasher>,
+    expected: Option<FileHash>,
+    hash_key: Option<[u8; 32]>,
+}
+
+impl<R: AsyncRead + Unpin> VerifyReader<R> {
+    // `hash_key` is given when `expected` is stored as a MAC.
+    pub fn new(inner: R, expected: FileHash, hash_key: Option<[u8; 32]>) -> VerifyReader<R> {
+        VerifyReader {
+            inner,
+            hasher: Some(Hasher::new(expected.hash_type())),
+            expected: Some(expected),
+            hash_key,
+        }
+    }
+
+    // Pass everything through unchecked, for data stored without a
+    // checksum.
+    pub fn unchecked(inner: R) -> VerifyReader<R> {
+        VerifyReader {
+            inner,
+            hasher: None,
+            expected: None,
+            hash_key: None,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn check(&mut self) -> io::Result<()> {
+        let (hasher, expected) = match (self.hasher.take(), self.expected) {
+            (Some(hasher), Some(expected)) => (hasher, expected),
+            _ => return Ok(()),
+        };
+        let mut actual = hasher.finalize();
+        if let Some(ref key) = self.hash_key {
+            actual = actual.to_mac(key);
+        }
+        if actual != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} mismatch: expected {}, got {}",
+                    expected.hash_type(),
+                    expected,
+                    actual
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for VerifyReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let n = match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Ready(Ok(n)) => n,
+        };
+        if n == 0 && !buf.is_empty() {
+            return Poll::Ready(this.check().map(|_| 0));
+        }
+        if let Some(ref mut hasher) = this.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+
+    fn read_all(data: &[u8], expected: FileHash, key: Option<[u8; 32]>) -> io::Result<Vec<u8>> {
+        let mut reader = VerifyReader::new(data, expected, key);
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out))?;
+        Ok(out)
+    }
+
+    fn hash_of(hash_type: HashType, data: &[u8]) -> FileHash {
+        let mut hasher = Hasher::new(hash_type);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_crc32_of_known_data() {
+        assert_eq!(
+            hash_of(HashType::Crc32, b"123456789"),
+            FileHash::Crc32(0xcbf4_3926)
+        );
+    }
+
+    #[test]
+    fn test_checksum14_rotates_after_each_byte() {
+        assert_eq!(checksum14(0, &[0x01]), 0x0002);
+        assert_eq!(checksum14(0, &[0x80, 0x00]), 0x0200);
+        assert_eq!(checksum14(0x8000, &[0x00]), 0x0001);
+    }
+
+    #[test]
+    fn test_verify_reader_accepts_matching_hash() {
+        for &hash_type in &[HashType::Crc32, HashType::Blake2sp, HashType::Checksum14] {
+            let expected = hash_of(hash_type, b"roar");
+            assert_eq!(read_all(b"roar", expected, None).unwrap(), b"roar".to_vec());
+        }
+    }
+
+    #[test]
+    fn test_verify_reader_rejects_mismatch() {
+        for &hash_type in &[HashType::Crc32, HashType::Blake2sp] {
+            let expected = hash_of(hash_type, b"roar");
+            assert!(read_all(b"meow", expected, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_verify_reader_converts_to_mac() {
+        let key = [3u8; 32];
+        for &hash_type in &[HashType::Crc32, HashType::Blake2sp] {
+            let plain = hash_of(hash_type, b"roar");
+            let mac = plain.to_mac(&key);
+            assert_ne!(plain, mac);
+            assert!(read_all(b"roar", mac, Some(key)).is_ok());
+            assert!(read_all(b"roar", plain, Some(key)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_file_hash_display() {
+        assert_eq!(FileHash::Crc32(0xbeef).to_string(), "0000BEEF");
+        assert_eq!(HashType::Blake2sp.to_string(), "BLAKE2sp");
+    }
+}
@@ -0,0 +1,229 @@
+// Persistent form of the entry index of a RAR 1.5-4.x archive, so large
+// archives don't need to be scanned again after a restart. The archive size
+// and a CRC32 of the start of the archive are stored along with the entries
+// to tell whether the index still matches the archive, and so are the
+// archive format and the settings that picked which files were indexed.
+//
+// Layout, integers as RAR5 vints unless noted:
+//   magic "RIDX", format version (1 byte), archive format (1 byte), index
+//   flags, archive size, header CRC32 (u32), entry count, then per entry: flags, name length, name (UTF-8), header
+//   offset, header size, packed size, unpacked size, CRC32 (u32),
+//   and a CRC32 (u32) of everything before it.
+
+use crate::archive::{Format, IndexEntry, Versions};
+use crate::block::BufferCursor;
+use crate::error::{Error, Result};
+use crate::rar5::{read_vint, write_vint};
+use byteorder::{ByteOrder, LittleEndian};
+
+const MAGIC: &[u8] = b"RIDX";
+pub const FORMAT_VERSION: u8 = 2;
+
+// How much of the archive, from the signature on, the header CRC covers.
+pub const CHECKED_LEN: u64 = 0x1000;
+
+// Index flags.
+const INDEX_ALL_VERSIONS: u64 = 0x01;
+const INDEX_INCLUDE_DELETED: u64 = 0x02;
+
+// Entry flags.
+const ENTRY_SOLID: u64 = 0x01;
+
+fn format_to_byte(format: Format) -> u8 {
+    match format {
+        Format::Rar14 => 14,
+        Format::Rar15 => 15,
+        Format::Rar50 => 50,
+    }
+}
+
+fn format_from_byte(byte: u8) -> Result<Format> {
+    match byte {
+        14 => Ok(Format::Rar14),
+        15 => Ok(Format::Rar15),
+        50 => Ok(Format::Rar50),
+        _ => Err(Error::bad_data(format!(
+            "Unknown archive format {} in archive index",
+            byte
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ArchiveIndex {
+    pub format: Format,
+    // The archive settings the entries were picked with.
+    pub versions: Versions,
+    pub include_deleted: bool,
+    pub archive_size: u64,
+    pub header_crc: u32,
+    pub entries: Vec<IndexEntry>,
+}
+
+impl ArchiveIndex {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.push(FORMAT_VERSION);
+        buf.push(format_to_byte(self.format));
+        let mut flags = 0;
+        if self.versions == Versions::All {
+            flags |= INDEX_ALL_VERSIONS;
+        }
+        if self.include_deleted {
+            flags |= INDEX_INCLUDE_DELETED;
+        }
+        write_vint(flags, &mut buf);
+        write_vint(self.archive_size, &mut buf);
+        buf.extend(&self.header_crc.to_le_bytes());
+        write_vint(self.entries.len() as u64, &mut buf);
+        for entry in &self.entries {
+            write_vint(if entry.solid { ENTRY_SOLID } else { 0 }, &mut buf);
+            write_vint(entry.name.len() as u64, &mut buf);
+            buf.extend(entry.name.as_bytes());
+            write_vint(entry.header_offset, &mut buf);
+            write_vint(entry.data_offset - entry.header_offset, &mut buf);
+            write_vint(entry.packed_size, &mut buf);
+            write_vint(entry.unpacked_size, &mut buf);
+            buf.extend(&entry.crc32.to_le_bytes());
+        }
+        let crc = crc::crc32::checksum_ieee(&buf);
+        buf.extend(&crc.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<ArchiveIndex> {
+        if buf.len() < MAGIC.len() + 6 || !buf.starts_with(MAGIC) {
+            return Err(Error::bad_data("Not an archive index".into()));
+        }
+        if buf[MAGIC.len()] != FORMAT_VERSION {
+            return Err(Error::unsupported(format!(
+                "Archive index format version {}",
+                buf[MAGIC.len()]
+            )));
+        }
+        let (body, crc) = buf.split_at(buf.len() - 4);
+        if crc::crc32::checksum_ieee(body) != LittleEndian::read_u32(crc) {
+            return Err(Error::bad_data("Archive index checksum mismatch".into()));
+        }
+
+        let format = format_from_byte(body[MAGIC.len() + 1])?;
+        let mut cursor = BufferCursor::new(&body[MAGIC.len() + 2..]);
+        let flags = read_vint(&mut cursor)?;
+        let archive_size = read_vint(&mut cursor)?;
+        let header_crc = LittleEndian::read_u32(cursor.read(4)?);
+        let count = read_vint(&mut cursor)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let flags = read_vint(&mut cursor)?;
+            let len = read_vint(&mut cursor)? as usize;
+            let name = String::from_utf8(cursor.read(len)?.to_vec())
+                .map_err(|_| Error::bad_data("Archive index name isn't UTF-8".into()))?;
+            let header_offset = read_vint(&mut cursor)?;
+            let header_size = read_vint(&mut cursor)?;
+            entries.push(IndexEntry {
+                name,
+                header_offset,
+                data_offset: header_offset + header_size,
+                packed_size: read_vint(&mut cursor)?,
+                unpacked_size: read_vint(&mut cursor)?,
+                crc32: LittleEndian::read_u32(cursor.read(4)?),
+                solid: flags & ENTRY_SOLID != 0,
+            });
+        }
+        Ok(ArchiveIndex {
+            format,
+            versions: if flags & INDEX_ALL_VERSIONS != 0 {
+                Versions::All
+            } else {
+                Versions::Latest
+            },
+            include_deleted: flags & INDEX_INCLUDE_DELETED != 0,
+            archive_size,
+            header_crc,
+            entries,
+        })
+    }
+
+    // Solid groups as ranges of entries. A group starts at each entry that
+    // doesn't depend on the ones before it.
+    pub fn solid_groups(&self) -> Vec<std::ops::Range<usize>> {
+        let mut groups = Vec::new();
+        let mut start = 0;
+        for (i, entry) in self.entries.iter().enumerate().skip(1) {
+            if !entry.solid {
+                groups.push(start..i);
+                start = i;
+            }
+        }
+        if !self.entries.is_empty() {
+            groups.push(start..self.entries.len());
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    fn entry(name: &str, header_offset: u64, solid: bool) -> IndexEntry {
+        IndexEntry {
+            name: name.into(),
+            header_offset,
+            data_offset: header_offset + 40,
+            packed_size: 1000,
+            unpacked_size: 5000,
+            crc32: 0xdead_beef,
+            solid,
+        }
+    }
+
+    fn index() -> ArchiveIndex {
+        ArchiveIndex {
+            format: Format::Rar15,
+            versions: Versions::All,
+            include_deleted: false,
+            archive_size: 1 << 33,
+            header_crc: 0x1234_5678,
+            entries: vec![
+                entry("a.txt", 20, false),
+                entry("dir/b.txt", 1060, true),
+                entry("c.txt", 2100, false),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let bytes = index().to_bytes();
+        assert_eq!(ArchiveIndex::from_bytes(&bytes).unwrap(), index());
+    }
+
+    #[test]
+    fn test_solid_groups() {
+        assert_eq!(index().solid_groups(), vec![0..2, 2..3]);
+    }
+
+    #[test]
+    fn test_rejects_damaged_or_newer_index() {
+        let mut bytes = index().to_bytes();
+        bytes[10] ^= 1;
+        assert!(ArchiveIndex::from_bytes(&bytes).is_err());
+
+        let mut bytes = index().to_bytes();
+        bytes[4] = FORMAT_VERSION + 1;
+        match *ArchiveIndex::from_bytes(&bytes).unwrap_err().kind() {
+            ErrorKind::Unsupported(_) => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+        assert!(ArchiveIndex::from_bytes(b"RIDX").is_err());
+
+        let mut bytes = index().to_bytes();
+        bytes[5] = 20;
+        let len = bytes.len() - 4;
+        let crc = crc::crc32::checksum_ieee(&bytes[..len]);
+        bytes[len..].copy_from_slice(&crc.to_le_bytes());
+        assert!(ArchiveIndex::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+// Testing archives the way `unrar t` does: every file is unpacked and checked
+// against its stored checksum, and nothing is written out.
+
+use crate::archive::{Archive, Entry, Format};
+use crate::error::{ErrorKind, Result};
+use crate::rar14::{FileHeader14, MainHeader14};
+use crate::rar5::{Rar5Entry, Rar5Unpacking};
+use crate::traits::AsyncFile;
+use futures::io::{AllowStdIo, AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+// How much unpacked data to read at a time.
+const UNPACK_CHUNK: usize = 0x8000;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    // The file uses something this crate can't unpack, e.g. encryption.
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct EntryReport {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TestReport {
+    pub entries: Vec<EntryReport>,
+    // Why the archive couldn't be read to the end, if it couldn't. The files
+    // before that point are still tested.
+    pub error: Option<String>,
+    // Whether the archive data CRC of a RAR 1.5-4.x end block matched, if
+    // there was one to check.
+    pub data_crc_matches: Option<bool>,
+}
+
+impl TestReport {
+    // Whether the archive is intact. Skipped files don't count as failures.
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.failures() == 0 && self.data_crc_matches != Some(false)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.outcome, Outcome::Failed(_)))
+            .count()
+    }
+
+    fn add(&mut self, name: String, result: Result<()>) {
+        let outcome = match result {
+            Ok(()) => Outcome::Passed,
+            Err(e) => match *e.kind() {
+                ErrorKind::Unsupported(ref what) => Outcome::Skipped(what.clone()),
+                _ => Outcome::Failed(e.to_string()),
+            },
+        };
+        self.entries.push(EntryReport { name, outcome });
+    }
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Unpack every file of the archive, opened right after the signature,
+    // and check it against its checksum. Header CRCs are checked as headers
+    // are read. RAR 1.5-4.x files are listed with the `read_entry` settings.
+    pub async fn test(&mut self) -> Result<TestReport> {
+        self.test_with_password(None).await
+    }
+
+    // Like `test`, decrypting RAR5 files and headers with `password`.
+    pub async fn test_with_password(&mut self, password: Option<&str>) -> Result<TestReport> {
+        match self.format() {
+            Format::Rar15 => self.test_rar15().await,
+            Format::Rar50 => self.test_rar5(password).await,
+            Format::Rar14 => self.test_rar14().await,
+        }
+    }
+
+    // RAR 1.4 headers have no CRCs, so only the file data is checked.
+    async fn test_rar14(&mut self) -> Result<TestReport> {
+        let mut report = TestReport::default();
+        if let Err(e) = MainHeader14::read(self).await {
+            report.error = Some(e.to_string());
+            return Ok(report);
+        }
+        loop {
+            let header = match FileHeader14::read(self).await {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            };
+            let data_offset = self.position();
+            let result = if header.is_directory() {
+                Ok(())
+            } else {
+                self.unpack_rar14(&header, &mut discard()).await.map(|_| ())
+            };
+            report.add(header.name.clone(), result);
+            // Skipped and failed files may not have been read to their end.
+            self.skip_to(data_offset + u64::from(header.packed_size))
+                .await?;
+        }
+        Ok(report)
+    }
+
+    async fn test_rar15(&mut self) -> Result<TestReport> {
+        let mut report = TestReport::default();
+        let mut entries = Vec::new();
+        loop {
+            match self.read_entry().await {
+                Ok(Some(entry)) => entries.push(entry),
+                Ok(None) => break,
+                Err(e) => {
+                    report.error = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+        report.data_crc_matches = self.end().and_then(|end| end.data_crc_matches);
+        for entry in &entries {
+            let result = self.unpack_rar15(entry, &mut discard()).await.map(|_| ());
+            report.add(entry.file.header().full_name(), result);
+        }
+        Ok(report)
+    }
+
+    async fn test_rar5(&mut self, password: Option<&str>) -> Result<TestReport> {
+        let mut report = TestReport::default();
+        let listing = match self.list_rar5_with_password(password).await {
+            Ok(listing) => listing,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                return Ok(report);
+            }
+        };
+        if listing.end.is_none() {
+            report.error = Some("Missing end of archive header".into());
+        }
+        let mut state = Rar5Unpacking::new(&listing, password.map(String::from));
+        for entry in &listing.entries {
+            let result = if entry.file.is_directory() {
+                Ok(())
+            } else {
+                self.unpack_rar5(entry, &mut state, &mut discard())
+                    .await
+                    .map(|_| ())
+            };
+            report.add(entry.file.name.clone(), result);
+        }
+        Ok(report)
+    }
+
+    // Write the contents of a RAR 1.4 file to `out`, from right after its
+    // header, checking them against the stored checksum, and return how many
+    // bytes were written.
+    pub(crate) async fn unpack_rar14<W: AsyncWrite + Unpin>(
+        &mut self,
+        header: &FileHeader14,
+        out: &mut W,
+    ) -> Result<u64> {
+        let mut reader = header.reader(&mut *self)?;
+        copy(&mut reader, out).await
+    }
+
+    // Write the contents of a RAR 1.5-4.x file to `out`, checking them
+    // against the stored CRC, and return how many bytes were written.
+    pub(crate) async fn unpack_rar15<W: AsyncWrite + Unpin>(
+        &mut self,
+        entry: &Entry,
+        out: &mut W,
+    ) -> Result<u64> {
+        self.skip_to(entry.data_offset).await?;
+        let mut reader = entry.file.reader(&mut *self)?;
+        copy(&mut reader, out).await
+    }
+
+    // Write the contents of a RAR5 file to `out`, checking them against the
+    // stored checksum and size, and return how many bytes were written.
+    // Files must be unpacked in order for solid archives.
+    pub(crate) async fn unpack_rar5<W: AsyncWrite + Unpin>(
+        &mut self,
+        entry: &Rar5Entry,
+        state: &mut Rar5Unpacking,
+        out: &mut W,
+    ) -> Result<u64> {
+        let mut reader = self.open_rar5_entry(entry, state).await?;
+        copy(&mut reader, out).await
+    }
+}
+
+// A writer that throws everything away, for unpacking without output.
+pub(crate) fn discard() -> AllowStdIo<io::Sink> {
+    AllowStdIo::new(io::sink())
+}
+
+// Copy `reader` to `out` and return how many bytes it gave. Checksums are
+// checked by the readers themselves.
+async fn copy<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: &mut R,
+    out: &mut W,
+) -> Result<u64> {
+    let mut buf = vec![0; UNPACK_CHUNK];
+    let mut total = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(total);
+        }
+        out.write_all(&buf[..n]).await?;
+        total += n as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{RAR14_SIGNATURE, RAR15_SIGNATURE, RAR50_SIGNATURE};
+    use crate::crypt::Rar5Key;
+    use crate::testing::{
+        aes_cbc_encrypt, file_block, rar14_file_header, rar5_file_header, rar5_header,
+        with_header_crc, FileBlock,
+    };
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    fn test(data: Vec<u8>) -> TestReport {
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        block_on(archive.test()).unwrap()
+    }
+
+    #[test]
+    fn test_rar15_outcomes() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"good.txt", b"roar"));
+        let mut bad_data = file_block(b"bad_data.txt", b"roar");
+        *bad_data.last_mut().unwrap() ^= 1;
+        data.extend(bad_data);
+        data.extend(
+            FileBlock {
+                method: 0x33,
+                ..FileBlock::new(b"packed.txt", b"roar")
+            }
+            .build(),
+        );
+
+        let report = test(data);
+        let outcomes: Vec<_> = report.entries.iter().map(|e| &e.outcome).collect();
+        assert_eq!(outcomes[0], &Outcome::Passed);
+        match outcomes[1] {
+            Outcome::Failed(data) => assert!(data.contains("CRC32 mismatch")),
+            _ => panic!("unexpected outcome: {:?}", outcomes[1]),
+        }
+        match outcomes[2] {
+            Outcome::Skipped(_) => {}
+            _ => panic!("unexpected outcome: {:?}", outcomes[2]),
+        }
+        assert_eq!(report.failures(), 1);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_rar15_reports_bad_header_crc() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        data.extend(file_block(b"b.txt", b"roar"));
+        let mut bad_header = file_block(b"bad_header.txt", b"roar");
+        bad_header[11] ^= 1;
+        data.extend(bad_header);
+
+        let report = test(data);
+        assert_eq!(report.entries[0].outcome, Outcome::Passed);
+        assert!(report.error.unwrap().contains("Bad header CRC"));
+    }
+
+    #[test]
+    fn test_rar14_outcomes() {
+        let mut data = RAR14_SIGNATURE.to_vec();
+        data.extend(&[0x07, 0x00, 0x00]);
+        data.extend(rar14_file_header("good.txt", b"roar", 0, 0));
+        let mut bad_data = rar14_file_header("bad_data.txt", b"roar", 0, 0);
+        *bad_data.last_mut().unwrap() ^= 1;
+        data.extend(bad_data);
+        data.extend(rar14_file_header("packed.txt", b"roar", 3, 0));
+        data.extend(rar14_file_header("last.txt", b"meow", 0, 0));
+
+        let report = test(data);
+        let outcomes: Vec<_> = report.entries.iter().map(|e| &e.outcome).collect();
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(outcomes[0], &Outcome::Passed);
+        assert!(matches!(outcomes[1], Outcome::Failed(_)));
+        assert!(matches!(outcomes[2], Outcome::Skipped(_)));
+        assert_eq!(outcomes[3], &Outcome::Passed);
+        assert!(report.error.is_none());
+        assert_eq!(report.failures(), 1);
+    }
+
+    #[test]
+    fn test_rar15_reports_unreadable_archive() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        data.extend(file_block(b"cut.txt", b"roar"));
+        data.truncate(data.len() - 2);
+        let report = test(data);
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].outcome, Outcome::Passed);
+        assert!(report.error.is_some());
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_rar15_reports_archive_data_crc() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(file_block(b"a.txt", b"roar"));
+        let mut end = vec![0, 0, 0x7b, 0x02, 0x40, 11, 0];
+        end.extend(&crc::crc32::checksum_ieee(&data).to_le_bytes());
+        data.extend(with_header_crc(end));
+
+        let report = test(data.clone());
+        assert_eq!(report.data_crc_matches, Some(true));
+        assert!(report.passed());
+
+        let data_end = data.len() - 12;
+        data[data_end] ^= 1;
+        let report = test(data);
+        assert_eq!(report.failures(), 1);
+        assert_eq!(report.data_crc_matches, Some(false));
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn test_rar5_outcomes() {
+        let mut data = RAR50_SIGNATURE.to_vec();
+        data.extend(rar5_header(&[0x01, 0x00, 0x00]));
+        let crc = crc::crc32::checksum_ieee(b"roar");
+        data.extend(rar5_file_header(2, "a.txt", 4, crc));
+        data.extend(b"roar");
+        data.extend(rar5_file_header(2, "b.txt", 4, 0));
+        data.extend(b"meow");
+        data.extend(rar5_header(&[0x05, 0x00, 0x00]));
+
+        let report = test(data.clone());
+        assert_eq!(report.entries.len(), 2);
+        assert_eq!(report.entries[0].outcome, Outcome::Passed);
+        assert_eq!(report.entries[1].name, "b.txt");
+        assert_eq!(report.failures(), 1);
+        assert!(report.error.is_none());
+
+        // Cut off before the end of archive header.
+        data.truncate(data.len() - 8);
+        let report = test(data);
+        assert!(report.error.is_some());
+    }
+
+    #[test]
+    fn test_rar5_with_password() {
+        let salt = *b"0123456789abcdef";
+        let iv = [7u8; 16];
+        let key = Rar5Key::derive("roar", &salt, 0).unwrap();
+        let data = aes_cbc_encrypt(&key, &iv, b"roar");
+        // File header with the data and extra areas, then the extra area
+        // holding an encryption record without a password check.
+        let mut body = vec![0x02, 0x03, 37, data.len() as u8, 0x04, 4, 0x20];
+        body.extend(&crc::crc32::checksum_ieee(b"roar").to_le_bytes());
+        body.extend(&[0x00, 0x01, 5]);
+        body.extend(b"a.txt");
+        body.extend(&[36, 0x01, 0x00, 0x00, 0]);
+        body.extend(&salt);
+        body.extend(&iv);
+        let mut archive = RAR50_SIGNATURE.to_vec();
+        archive.extend(rar5_header(&[0x01, 0x00, 0x00]));
+        archive.extend(rar5_header(&body));
+        archive.extend(data);
+        archive.extend(rar5_header(&[0x05, 0x00, 0x00]));
+
+        let test_with = |password| {
+            let mut archive = block_on(Archive::open(Cursor::new(archive.clone()))).unwrap();
+            block_on(archive.test_with_password(password)).unwrap()
+        };
+        let report = test_with(None);
+        assert!(matches!(report.entries[0].outcome, Outcome::Skipped(_)));
+        let report = test_with(Some("roar"));
+        assert_eq!(report.entries[0].outcome, Outcome::Passed);
+        assert!(report.passed());
+        let report = test_with(Some("meow"));
+        assert_eq!(report.failures(), 1);
+    }
+}
@@ -1,3 +1,38 @@
+#![feature(async_await)]
+#![feature(trait_alias)]
+
+extern crate num;
+
+#[macro_use]
+extern crate num_derive;
+
+pub mod archive;
+pub mod block;
+#[cfg(feature = "tokio")]
+pub mod compat;
+pub mod crypt;
+pub mod dostime;
+pub mod error;
+pub mod extract;
+pub mod hash;
+pub mod index;
+pub mod integrity;
+pub mod links;
+pub mod listing;
+pub mod owner;
+pub mod permissions;
+pub mod rar14;
+pub mod rar5;
+pub mod recovery;
+pub mod recvol;
+pub mod streams;
+pub mod sync;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod traits;
+pub mod unpack;
+pub mod volume;
+
 #[cfg(test)]
 mod tests {
 
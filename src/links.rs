@@ -0,0 +1,218 @@
+// Link entries. RAR 3.x archives made on Unix store a symlink as a file with
+// the symlink type in its mode and the target as its data. RAR5 has a
+// redirection extra record for symlinks, junctions, hard links and file
+// copies, the last two pointing at another entry of the archive.
+//
+// Targets come from the archive, so before creating a link its target has
+// to be checked to stay inside the output directory.
+
+use crate::block::BufferCursor;
+use crate::error::{Error, Result};
+use crate::rar5::read_vint;
+use num::FromPrimitive;
+use std::path::{Component, Path, PathBuf};
+
+// Redirection flag marking a link to a directory.
+const REDIRECTION_DIRECTORY: u64 = 0x01;
+
+// Unix file type of symlinks.
+pub const UNIX_SYMLINK: u32 = 0o120_000;
+
+#[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
+pub enum LinkKind {
+    UnixSymlink = 1,
+    WindowsSymlink = 2,
+    Junction = 3,
+    HardLink = 4,
+    FileCopy = 5,
+}
+
+impl LinkKind {
+    pub fn from_u64(that: u64) -> Option<LinkKind> {
+        FromPrimitive::from_u64(that)
+    }
+
+    // Whether the target names another entry of the archive rather than a
+    // path relative to the link.
+    pub fn targets_entry(self) -> bool {
+        matches!(self, LinkKind::HardLink | LinkKind::FileCopy)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub target: String,
+    pub directory: bool,
+}
+
+impl Link {
+    // Parse the data of a RAR5 redirection extra record.
+    pub fn parse_rar5(data: &[u8]) -> Result<Link> {
+        let mut cursor = BufferCursor::new(data);
+        let raw_kind = read_vint(&mut cursor)?;
+        let kind = LinkKind::from_u64(raw_kind)
+            .ok_or_else(|| Error::unsupported(format!("Redirection type {}", raw_kind)))?;
+        let flags = read_vint(&mut cursor)?;
+        let len = read_vint(&mut cursor)? as usize;
+        Ok(Link {
+            kind,
+            target: String::from_utf8_lossy(cursor.read(len)?).into_owned(),
+            directory: flags & REDIRECTION_DIRECTORY != 0,
+        })
+    }
+
+    // A RAR 3.x Unix symlink, whose data is the target.
+    pub fn unix_symlink(target: &[u8]) -> Link {
+        Link {
+            kind: LinkKind::UnixSymlink,
+            target: String::from_utf8_lossy(target).into_owned(),
+            directory: false,
+        }
+    }
+
+    // Where the link points, relative to `output`, or an error if that's
+    // outside of it. `path` is the path of the link relative to `output`.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let target = self.target.replace('\\', "/");
+        let target = Path::new(&target);
+        if target.has_root() {
+            return Err(self.escapes());
+        }
+        let joined = if self.kind.targets_entry() {
+            target.to_path_buf()
+        } else {
+            path.parent().unwrap_or_else(|| Path::new("")).join(target)
+        };
+        normalize(&joined).ok_or_else(|| self.escapes())
+    }
+
+    fn escapes(&self) -> Error {
+        Error::bad_data(format!(
+            "Link target {:?} points outside of the output directory",
+            self.target
+        ))
+    }
+
+    // Create the link at `path`, relative to `output`.
+    #[cfg(unix)]
+    pub fn create(&self, output: &Path, path: &Path) -> Result<()> {
+        let link_path = output.join(normalize(path).ok_or_else(|| {
+            Error::bad_data(format!("Link path {:?} is outside of the output", path))
+        })?);
+        let resolved = self.resolve(path)?;
+        match self.kind {
+            LinkKind::UnixSymlink | LinkKind::WindowsSymlink | LinkKind::Junction => {
+                std::os::unix::fs::symlink(self.target.replace('\\', "/"), &link_path)?
+            }
+            LinkKind::HardLink => std::fs::hard_link(output.join(resolved), &link_path)?,
+            LinkKind::FileCopy => {
+                std::fs::copy(output.join(resolved), &link_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn create(&self, _output: &Path, _path: &Path) -> Result<()> {
+        Err(Error::unsupported("Links on this platform".into()))
+    }
+}
+
+// Drop `.` components and resolve `..` ones without touching the file
+// system, or `None` if the path climbs out of where it starts.
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symlink(target: &str) -> Link {
+        Link::unix_symlink(target.as_bytes())
+    }
+
+    #[test]
+    fn test_parse_rar5_redirection() {
+        let mut data = vec![0x04, 0x00, 0x05];
+        data.extend(b"a/b.c");
+        let link = Link::parse_rar5(&data).unwrap();
+        assert_eq!(link.kind, LinkKind::HardLink);
+        assert_eq!(link.target, "a/b.c");
+        assert!(!link.directory);
+
+        let data = vec![0x03, 0x01, 0x02, b'.', b'.'];
+        let link = Link::parse_rar5(&data).unwrap();
+        assert_eq!(link.kind, LinkKind::Junction);
+        assert!(link.directory);
+
+        assert!(Link::parse_rar5(&[0x09, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_resolves_targets_inside_output() {
+        let path = Path::new("dir/sub/link");
+        assert_eq!(
+            symlink("../file").resolve(path).unwrap(),
+            PathBuf::from("dir/file")
+        );
+        assert_eq!(
+            symlink("./x/./y").resolve(path).unwrap(),
+            PathBuf::from("dir/sub/x/y")
+        );
+        let hard = Link {
+            kind: LinkKind::HardLink,
+            target: "other\\file".into(),
+            directory: false,
+        };
+        assert_eq!(hard.resolve(path).unwrap(), PathBuf::from("other/file"));
+    }
+
+    #[test]
+    fn test_rejects_targets_escaping_output() {
+        let path = Path::new("dir/link");
+        assert!(symlink("../../etc/passwd").resolve(path).is_err());
+        assert!(symlink("/etc/passwd").resolve(path).is_err());
+        assert!(symlink("\\??\\C:\\Windows").resolve(path).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_creates_links() {
+        let output = std::env::temp_dir().join(format!("roar-links-{}", std::process::id()));
+        std::fs::create_dir_all(output.join("dir")).unwrap();
+        std::fs::write(output.join("dir/file"), b"roar").unwrap();
+
+        symlink("file")
+            .create(&output, Path::new("dir/soft"))
+            .unwrap();
+        let hard = Link {
+            kind: LinkKind::HardLink,
+            target: "dir/file".into(),
+            directory: false,
+        };
+        hard.create(&output, Path::new("hard")).unwrap();
+        let escaping = symlink("../../x").create(&output, Path::new("dir/bad"));
+
+        let soft = std::fs::read(output.join("dir/soft")).unwrap();
+        let hard = std::fs::read(output.join("hard")).unwrap();
+        std::fs::remove_dir_all(&output).unwrap();
+        assert_eq!(soft, b"roar".to_vec());
+        assert_eq!(hard, b"roar".to_vec());
+        assert!(escaping.is_err());
+    }
+}
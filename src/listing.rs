@@ -0,0 +1,196 @@
+// What listing an archive shows about each file, taken from the headers of
+// any of the formats.
+
+use crate::archive::{Archive, Format};
+use crate::block::FileHeader;
+use crate::dostime::DosDateTime;
+use crate::error::Result;
+use crate::hash::FileHash;
+use crate::permissions::Permissions;
+use crate::rar14::{FileFlags14, FileHeader14, MainHeader14};
+use crate::rar5;
+use crate::traits::AsyncFile;
+use futures::io::AsyncSeek;
+
+const METHOD_NAMES: [&str; 6] = ["Store", "Fastest", "Fast", "Normal", "Good", "Best"];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ListEntry {
+    pub name: String,
+    pub packed_size: u64,
+    // Unknown for RAR5 files packed from a stream.
+    pub unpacked_size: Option<u64>,
+    pub mtime: Option<DosDateTime>,
+    pub permissions: Permissions,
+    pub hash: Option<FileHash>,
+    // 0 for stored files, 1 (fastest) to 5 (best) otherwise.
+    pub method: Option<u8>,
+    pub host_os: Option<String>,
+    pub solid: bool,
+    pub dictionary_size: Option<u64>,
+    // Version of RAR needed to unpack the file, times ten.
+    pub version_needed: u8,
+    pub encrypted: bool,
+    pub directory: bool,
+}
+
+impl ListEntry {
+    // Packed size as a percentage of the unpacked size.
+    pub fn ratio(&self) -> Option<u64> {
+        match self.unpacked_size {
+            Some(size) if size > 0 => Some(self.packed_size * 100 / size),
+            _ => None,
+        }
+    }
+
+    pub fn method_name(&self) -> &'static str {
+        self.method
+            .and_then(|m| METHOD_NAMES.get(usize::from(m)))
+            .cloned()
+            .unwrap_or("Unknown")
+    }
+}
+
+impl<'a> From<&FileHeader<'a>> for ListEntry {
+    fn from(header: &FileHeader<'a>) -> ListEntry {
+        let prefix = header.prefix();
+        ListEntry {
+            name: header.full_name(),
+            packed_size: header.packed_size(),
+            unpacked_size: Some(header.unpacked_size()),
+            mtime: Some(header.mtime()),
+            permissions: header.permissions(),
+            hash: Some(header.hash()),
+            method: prefix.packing_method().map(|m| m as u8 - 0x30),
+            host_os: prefix.creation_os().map(|os| format!("{:?}", os)),
+            solid: header.is_solid(),
+            dictionary_size: header.dictionary_size(),
+            version_needed: prefix.unpack_version(),
+            encrypted: header.is_encrypted(),
+            directory: header.is_directory(),
+        }
+    }
+}
+
+impl From<&rar5::FileHeader> for ListEntry {
+    fn from(file: &rar5::FileHeader) -> ListEntry {
+        let compression = file.compression().ok();
+        ListEntry {
+            name: file.name.clone(),
+            packed_size: file.packed_size(),
+            unpacked_size: file.unpacked_size(),
+            mtime: file
+                .mtime
+                .map(|t| DosDateTime::from_unix_time(u64::from(t))),
+            permissions: file.permissions(),
+            hash: file.hash(),
+            method: compression.map(|c| c.method),
+            host_os: file.host_os.map(|os| format!("{:?}", os)),
+            solid: compression.is_some_and(|c| c.solid),
+            dictionary_size: if file.is_directory() {
+                None
+            } else {
+                compression.map(|c| c.dictionary_size)
+            },
+            // Version 0 of the algorithm is the one of RAR 5.0.
+            version_needed: 50,
+            encrypted: file.is_encrypted(),
+            directory: file.is_directory(),
+        }
+    }
+}
+
+impl From<&FileHeader14> for ListEntry {
+    fn from(header: &FileHeader14) -> ListEntry {
+        ListEntry {
+            name: header.name.clone(),
+            packed_size: u64::from(header.packed_size),
+            unpacked_size: Some(u64::from(header.unpacked_size)),
+            mtime: Some(DosDateTime::from_u32(header.mtime)),
+            permissions: header.permissions(),
+            hash: Some(header.hash()),
+            method: Some(header.method),
+            host_os: Some("Dos".into()),
+            solid: header.flags.contains(FileFlags14::SOLID),
+            dictionary_size: None,
+            version_needed: header.unpack_version,
+            encrypted: header.is_encrypted(),
+            directory: header.is_directory(),
+        }
+    }
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // List every file of the archive, opened right after the signature.
+    // RAR 1.5-4.x files are listed with the `read_entry` settings.
+    pub async fn list(&mut self) -> Result<Vec<ListEntry>> {
+        let mut entries = Vec::new();
+        match self.format() {
+            Format::Rar14 => {
+                MainHeader14::read(self).await?;
+                while let Some(header) = FileHeader14::read(self).await? {
+                    entries.push(ListEntry::from(&header));
+                    header.skip_data(self).await?;
+                }
+            }
+            Format::Rar15 => {
+                while let Some(entry) = self.read_entry().await? {
+                    entries.push(ListEntry::from(&entry.file.header()));
+                }
+            }
+            Format::Rar50 => {
+                for entry in self.list_rar5(None).await?.entries {
+                    entries.push(ListEntry::from(&entry.file));
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR15_SIGNATURE;
+    use crate::testing::FileBlock;
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_lists_rar15_files() {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        // Archived on Unix, with the CRC and size of what was packed.
+        let unix = |name, flags, method, data| FileBlock {
+            flags,
+            unpacked_size: Some(10),
+            host_os: 3,
+            file_crc: Some(0xd262_4711),
+            mtime: 0x3173_b82b,
+            method,
+            attributes: 0o100_644,
+            ..FileBlock::new(name, data)
+        };
+        data.extend(unix(b"a.txt", 0xc0, 0x33, b"roar").build());
+        data.extend(unix(b"b.txt", 0x14, 0x30, b"meow").build());
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        let entries = block_on(archive.list()).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        let a = &entries[0];
+        assert_eq!(a.name, "a.txt");
+        assert_eq!((a.packed_size, a.unpacked_size), (4, Some(10)));
+        assert_eq!(a.ratio(), Some(40));
+        assert_eq!(a.mtime.unwrap().to_string(), "2004-11-19 23:01:22");
+        assert_eq!(a.permissions.to_string(), "-rw-r--r--");
+        assert_eq!(a.hash.unwrap().to_string(), "D2624711");
+        assert_eq!(a.method_name(), "Normal");
+        assert_eq!(a.host_os, Some("Unix".into()));
+        assert_eq!(a.dictionary_size, Some(0x40_0000));
+        assert_eq!(a.version_needed, 29);
+        assert!(!a.solid && !a.encrypted);
+
+        let b = &entries[1];
+        assert_eq!(b.method_name(), "Store");
+        assert!(b.solid && b.encrypted);
+    }
+}
@@ -0,0 +1,249 @@
+// Unix ownership and permissions. Archives made on Unix keep the full mode
+// in the file attributes, and the owner in a "UOW" service block (RAR 3.x)
+// or an extra record of the file header (RAR5). Names are preferred over
+// numeric ids, which rarely match between systems.
+
+use crate::block::BufferCursor;
+use crate::error::{Error, Result};
+use crate::rar5::read_vint;
+use std::path::Path;
+
+// Service type of RAR 3.x owner blocks.
+pub const OWNER_SERVICE: &[u8] = b"UOW";
+
+// Flags of the RAR5 owner record.
+const OWNER_USER_NAME: u64 = 0x01;
+const OWNER_GROUP_NAME: u64 = 0x02;
+const OWNER_USER_ID: u64 = 0x04;
+const OWNER_GROUP_ID: u64 = 0x08;
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UnixOwner {
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OwnerPolicy {
+    Ignore,
+    // Look the names up, falling back to the ids.
+    ByName,
+    ById,
+}
+
+impl OwnerPolicy {
+    pub fn from_name(name: &str) -> Option<OwnerPolicy> {
+        match name {
+            "ignore" => Some(OwnerPolicy::Ignore),
+            "name" => Some(OwnerPolicy::ByName),
+            "id" => Some(OwnerPolicy::ById),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct OwnerOptions {
+    pub policy: OwnerPolicy,
+    // Skip ownership instead of failing when not running as root.
+    pub only_as_root: bool,
+}
+
+impl Default for OwnerOptions {
+    fn default() -> OwnerOptions {
+        OwnerOptions {
+            policy: OwnerPolicy::Ignore,
+            only_as_root: true,
+        }
+    }
+}
+
+fn name(raw: &[u8]) -> Option<String> {
+    if raw.is_empty() {
+        None
+    } else {
+        Some(String::from_utf8_lossy(raw).into_owned())
+    }
+}
+
+impl UnixOwner {
+    // RAR 3.x keeps the owner and group names in the sub data of the UOW
+    // block, separated by a zero byte.
+    pub fn parse_uow(sub_data: &[u8]) -> Result<UnixOwner> {
+        let zero = sub_data
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| Error::bad_block("Owner block without group name".into()))?;
+        let group = &sub_data[zero + 1..];
+        let group = &group[..group.iter().position(|&b| b == 0).unwrap_or(group.len())];
+        Ok(UnixOwner {
+            user: name(&sub_data[..zero]),
+            group: name(group),
+            uid: None,
+            gid: None,
+        })
+    }
+
+    // Parse the data of a RAR5 owner extra record.
+    pub fn parse_rar5(data: &[u8]) -> Result<UnixOwner> {
+        let mut cursor = BufferCursor::new(data);
+        let flags = read_vint(&mut cursor)?;
+        let mut owner = UnixOwner::default();
+        if flags & OWNER_USER_NAME != 0 {
+            let len = read_vint(&mut cursor)? as usize;
+            owner.user = name(cursor.read(len)?);
+        }
+        if flags & OWNER_GROUP_NAME != 0 {
+            let len = read_vint(&mut cursor)? as usize;
+            owner.group = name(cursor.read(len)?);
+        }
+        if flags & OWNER_USER_ID != 0 {
+            owner.uid = Some(read_vint(&mut cursor)? as u32);
+        }
+        if flags & OWNER_GROUP_ID != 0 {
+            owner.gid = Some(read_vint(&mut cursor)? as u32);
+        }
+        Ok(owner)
+    }
+}
+
+impl std::fmt::Display for UnixOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.user, self.uid) {
+            (Some(user), _) => write!(f, "{}", user)?,
+            (None, Some(uid)) => write!(f, "{}", uid)?,
+            (None, None) => write!(f, "?")?,
+        }
+        match (&self.group, self.gid) {
+            (Some(group), _) => write!(f, ":{}", group),
+            (None, Some(gid)) => write!(f, ":{}", gid),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+// Apply the permission bits of a Unix mode to an extracted file.
+#[cfg(unix)]
+pub fn restore_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn restore_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+// Change the owner of an extracted file as `options` asks for. Returns
+// whether anything was changed.
+#[cfg(unix)]
+pub fn restore_owner(path: &Path, owner: &UnixOwner, options: OwnerOptions) -> Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    if options.policy == OwnerPolicy::Ignore {
+        return Ok(false);
+    }
+    if options.only_as_root && unsafe { libc::geteuid() } != 0 {
+        return Ok(false);
+    }
+    let (uid, gid) = match options.policy {
+        OwnerPolicy::ByName => (
+            owner.user.as_ref().and_then(|u| user_id(u)).or(owner.uid),
+            owner.group.as_ref().and_then(|g| group_id(g)).or(owner.gid),
+        ),
+        _ => (owner.uid, owner.gid),
+    };
+    if uid.is_none() && gid.is_none() {
+        return Ok(false);
+    }
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::bad_data(format!("Invalid path {:?}", path)))?;
+    // -1 leaves the id unchanged.
+    let ret = unsafe {
+        libc::lchown(
+            path.as_ptr(),
+            uid.unwrap_or(!0) as libc::uid_t,
+            gid.unwrap_or(!0) as libc::gid_t,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::io(std::io::Error::last_os_error()));
+    }
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn restore_owner(_path: &Path, _owner: &UnixOwner, _options: OwnerOptions) -> Result<bool> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        None
+    } else {
+        Some(unsafe { (*passwd).pw_uid })
+    }
+}
+
+#[cfg(unix)]
+fn group_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        None
+    } else {
+        Some(unsafe { (*group).gr_gid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_uow() {
+        let owner = UnixOwner::parse_uow(b"erich\0staff").unwrap();
+        assert_eq!(owner.user, Some("erich".to_string()));
+        assert_eq!(owner.group, Some("staff".to_string()));
+        assert_eq!(owner.to_string(), "erich:staff");
+        assert!(UnixOwner::parse_uow(b"erich").is_err());
+    }
+
+    #[test]
+    fn test_parse_rar5_owner() {
+        let mut data = vec![0x0d, 5];
+        data.extend(b"erich");
+        data.extend(&[0xe8, 0x07, 20]);
+        let owner = UnixOwner::parse_rar5(&data).unwrap();
+        assert_eq!(owner.user, Some("erich".to_string()));
+        assert_eq!(owner.group, None);
+        assert_eq!(owner.uid, Some(1000));
+        assert_eq!(owner.gid, Some(20));
+        assert_eq!(owner.to_string(), "erich:20");
+        assert!(UnixOwner::parse_rar5(&data[..4]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restore_mode_and_ignored_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("roar-owner-{}", std::process::id()));
+        std::fs::write(&path, b"roar").unwrap();
+        restore_mode(&path, 0o100_640).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let owner = UnixOwner::parse_uow(b"root\0root").unwrap();
+        let changed = restore_owner(&path, &owner, OwnerOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o7777, 0o640);
+        assert!(!changed);
+    }
+}
@@ -0,0 +1,114 @@
+// File attributes mean different things depending on the system the archive
+// was made on: DOS attribute bits on DOS, OS/2 and Windows, and the whole
+// mode on Unix. `Permissions` maps both to one portable form.
+
+use std::fmt;
+
+// DOS attribute bits.
+const DOS_READ_ONLY: u32 = 0x01;
+const DOS_HIDDEN: u32 = 0x02;
+const DOS_SYSTEM: u32 = 0x04;
+const DOS_DIRECTORY: u32 = 0x10;
+
+// Unix file type bits.
+const UNIX_TYPE_MASK: u32 = 0o170_000;
+const UNIX_DIRECTORY: u32 = 0o040_000;
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Permissions {
+    pub read_only: bool,
+    pub hidden: bool,
+    pub system: bool,
+    pub directory: bool,
+    pub executable: bool,
+    // Permission bits to give the file on Unix.
+    pub unix_mode: u32,
+}
+
+impl Permissions {
+    pub fn from_dos(attrs: u32) -> Permissions {
+        let read_only = attrs & DOS_READ_ONLY != 0;
+        let directory = attrs & DOS_DIRECTORY != 0;
+        let mut unix_mode = if directory { 0o755 } else { 0o644 };
+        if read_only {
+            unix_mode &= !0o222;
+        }
+        Permissions {
+            read_only,
+            hidden: attrs & DOS_HIDDEN != 0,
+            system: attrs & DOS_SYSTEM != 0,
+            directory,
+            executable: false,
+            unix_mode,
+        }
+    }
+
+    // Defaults for files from systems whose attributes mean nothing here.
+    pub fn unknown(directory: bool) -> Permissions {
+        Permissions::from_dos(if directory { DOS_DIRECTORY } else { 0 })
+    }
+
+    pub fn from_unix(mode: u32) -> Permissions {
+        let directory = mode & UNIX_TYPE_MASK == UNIX_DIRECTORY;
+        Permissions {
+            read_only: mode & 0o222 == 0,
+            hidden: false,
+            system: false,
+            directory,
+            executable: !directory && mode & 0o111 != 0,
+            unix_mode: mode & 0o7777,
+        }
+    }
+}
+
+// Shown like `ls -l` does, with the DOS only attributes appended.
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", if self.directory { 'd' } else { '-' })?;
+        for shift in &[6, 3, 0] {
+            let bits = self.unix_mode >> shift;
+            write!(
+                f,
+                "{}{}{}",
+                if bits & 4 != 0 { 'r' } else { '-' },
+                if bits & 2 != 0 { 'w' } else { '-' },
+                if bits & 1 != 0 { 'x' } else { '-' },
+            )?;
+        }
+        if self.hidden {
+            write!(f, " hidden")?;
+        }
+        if self.system {
+            write!(f, " system")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dos_attributes() {
+        let perms = Permissions::from_dos(DOS_READ_ONLY | DOS_HIDDEN | 0x20);
+        assert!(perms.read_only && perms.hidden && !perms.system && !perms.directory);
+        assert_eq!(perms.unix_mode, 0o444);
+        assert_eq!(perms.to_string(), "-r--r--r-- hidden");
+        assert_eq!(
+            Permissions::from_dos(DOS_DIRECTORY).to_string(),
+            "drwxr-xr-x"
+        );
+    }
+
+    #[test]
+    fn test_unix_mode() {
+        let perms = Permissions::from_unix(0o100_755);
+        assert!(perms.executable && !perms.read_only && !perms.directory);
+        assert_eq!(perms.unix_mode, 0o755);
+
+        let perms = Permissions::from_unix(0o040_555);
+        assert!(perms.directory && perms.read_only && !perms.executable);
+        assert_eq!(perms.to_string(), "dr-xr-xr-x");
+    }
+}
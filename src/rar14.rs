@@ -0,0 +1,259 @@
+// The RAR 1.4 format: a short main header after the `RE~^` signature,
+// followed by file headers that each carry their packed data. There are no
+// header CRCs and no block types; the archive simply ends at end of file.
+
+use crate::error::{Error, Result};
+use crate::hash::{FileHash, VerifyReader};
+use crate::permissions::Permissions;
+use crate::traits::AsyncFile;
+use crate::unpack::{EntryReader, Store};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::AsyncReadExt;
+
+// Sizes of the fixed parts of the headers. The main header size includes
+// the signature.
+const MAIN_HEADER_SIZE: u16 = 7;
+const FILE_HEADER_SIZE: u16 = 21;
+
+// DOS attribute marking a directory.
+const ATTRIBUTE_DIRECTORY: u8 = 0x10;
+
+bitflags! {
+    pub struct MainFlags14: u8 {
+        const VOLUME = 0x01;
+        const COMMENT = 0x02;
+        const LOCK = 0x04;
+        const SOLID = 0x08;
+    }
+}
+
+bitflags! {
+    pub struct FileFlags14: u8 {
+        const SPLIT_BEFORE = 0x01;
+        const SPLIT_AFTER = 0x02;
+        const PASSWORD = 0x04;
+        const COMMENT = 0x08;
+        const SOLID = 0x10;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MainHeader14 {
+    pub head_size: u16,
+    pub flags: MainFlags14,
+}
+
+impl MainHeader14 {
+    // Read the main header from `f`, positioned right after the signature.
+    // Anything else in the header, like the archive comment, is skipped.
+    pub async fn read<T: AsyncFile>(f: &mut T) -> Result<MainHeader14> {
+        let mut buf = [0u8; 3];
+        f.read_exact(&mut buf).await?;
+        let head_size = LittleEndian::read_u16(&buf[0..2]);
+        if head_size < MAIN_HEADER_SIZE {
+            return Err(Error::bad_block(format!(
+                "Main header size {} is too small",
+                head_size
+            )));
+        }
+        skip(f, u64::from(head_size - MAIN_HEADER_SIZE)).await?;
+        Ok(MainHeader14 {
+            head_size,
+            flags: MainFlags14::from_bits_truncate(buf[2]),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileHeader14 {
+    pub packed_size: u32,
+    pub unpacked_size: u32,
+    pub checksum: u16,
+    pub head_size: u16,
+    // DOS date and time.
+    pub mtime: u32,
+    pub attributes: u8,
+    pub flags: FileFlags14,
+    pub unpack_version: u8,
+    pub method: u8,
+    pub name: String,
+}
+
+impl FileHeader14 {
+    // Read the next file header, or `None` at the end of the archive.
+    pub async fn read<T: AsyncFile>(f: &mut T) -> Result<Option<FileHeader14>> {
+        let mut buf = [0u8; FILE_HEADER_SIZE as usize];
+        if f.read(&mut buf[..1]).await? == 0 {
+            return Ok(None);
+        }
+        f.read_exact(&mut buf[1..]).await?;
+
+        let head_size = LittleEndian::read_u16(&buf[10..12]);
+        let name_size = buf[19];
+        if head_size < FILE_HEADER_SIZE + u16::from(name_size) {
+            return Err(Error::bad_block(format!(
+                "File header size {} is too small",
+                head_size
+            )));
+        }
+        let mut name = vec![0u8; usize::from(name_size)];
+        f.read_exact(&mut name).await?;
+        skip(
+            f,
+            u64::from(head_size - FILE_HEADER_SIZE - u16::from(name_size)),
+        )
+        .await?;
+
+        Ok(Some(FileHeader14 {
+            packed_size: LittleEndian::read_u32(&buf[0..4]),
+            unpacked_size: LittleEndian::read_u32(&buf[4..8]),
+            checksum: LittleEndian::read_u16(&buf[8..10]),
+            head_size,
+            mtime: LittleEndian::read_u32(&buf[12..16]),
+            attributes: buf[16],
+            flags: FileFlags14::from_bits_truncate(buf[17]),
+            unpack_version: if buf[18] == 2 { 13 } else { 10 },
+            method: buf[20],
+            name: String::from_utf8_lossy(&name).into_owned(),
+        }))
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTRIBUTE_DIRECTORY != 0
+    }
+
+    // RAR 1.4 only ran on DOS.
+    pub fn permissions(&self) -> Permissions {
+        Permissions::from_dos(u32::from(self.attributes))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags.contains(FileFlags14::PASSWORD)
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.method == 0
+    }
+
+    pub fn hash(&self) -> FileHash {
+        FileHash::Checksum14(self.checksum)
+    }
+
+    // Move `f` past the packed data of this entry, to the next header.
+    pub async fn skip_data<T: AsyncFile>(&self, f: &mut T) -> Result<()> {
+        skip(f, u64::from(self.packed_size)).await
+    }
+
+    // Stream the contents of this entry from `f`, positioned right after its
+    // header. Only stored entries can be read; RAR 1.4 compression and
+    // encryption aren't implemented.
+    pub fn reader<T: AsyncFile>(&self, f: T) -> Result<VerifyReader<EntryReader<T, Store>>> {
+        if self.is_encrypted() {
+            return Err(Error::unsupported("RAR 1.4 encryption".into()));
+        }
+        if self
+            .flags
+            .intersects(FileFlags14::SPLIT_BEFORE | FileFlags14::SPLIT_AFTER)
+        {
+            return Err(Error::unsupported("RAR 1.4 multivolume entries".into()));
+        }
+        if !self.is_stored() {
+            return Err(Error::unsupported(format!(
+                "RAR 1.4 compression method {}",
+                self.method
+            )));
+        }
+        let packed_size = u64::from(self.packed_size);
+        let entry = EntryReader::new(f, Store::new(), packed_size);
+        Ok(VerifyReader::new(entry, self.hash(), None))
+    }
+}
+
+async fn skip<T: AsyncFile>(f: &mut T, mut n: u64) -> Result<()> {
+    let mut buf = [0u8; 0x1000];
+    while n > 0 {
+        let want = n.min(buf.len() as u64) as usize;
+        f.read_exact(&mut buf[..want]).await?;
+        n -= want as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::rar14_file_header as file_header;
+    use futures::executor::block_on;
+
+    fn archive() -> Vec<u8> {
+        // Main header with two bytes of padding after it.
+        let mut buf = vec![0x09, 0x00, 0x08, 0xaa, 0xbb];
+        buf.extend(file_header("roar.txt", b"roar", 0, 0));
+        buf.extend(file_header("packed.txt", b"????", 3, 0));
+        buf
+    }
+
+    #[test]
+    fn test_lists_entries() {
+        let data = archive();
+        let mut f = &data[..];
+        let main = block_on(MainHeader14::read(&mut f)).unwrap();
+        assert!(main.flags.contains(MainFlags14::SOLID));
+
+        let mut names = Vec::new();
+        while let Some(file) = block_on(FileHeader14::read(&mut f)).unwrap() {
+            assert_eq!(file.unpacked_size, 4);
+            assert_eq!(file.unpack_version, 13);
+            block_on(file.skip_data(&mut f)).unwrap();
+            names.push(file.name);
+        }
+        assert_eq!(names, vec!["roar.txt", "packed.txt"]);
+    }
+
+    #[test]
+    fn test_reads_stored_entry() {
+        let data = archive();
+        let mut f = &data[5..];
+        let file = block_on(FileHeader14::read(&mut f)).unwrap().unwrap();
+        assert!(file.is_stored());
+        assert_eq!(file.permissions().unix_mode, 0o644);
+        let mut out = Vec::new();
+        block_on(file.reader(&mut f).unwrap().read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"roar".to_vec());
+    }
+
+    #[test]
+    fn test_stored_entry_checksum_mismatch_errors() {
+        let mut data = file_header("roar.txt", b"roar", 0, 0);
+        let len = data.len();
+        data[len - 1] = b'!';
+        let mut f = &data[..];
+        let file = block_on(FileHeader14::read(&mut f)).unwrap().unwrap();
+        let mut out = Vec::new();
+        assert!(block_on(file.reader(&mut f).unwrap().read_to_end(&mut out)).is_err());
+    }
+
+    #[test]
+    fn test_compressed_and_encrypted_entries_are_unsupported() {
+        for &(method, flags) in &[(3, 0), (0, FileFlags14::PASSWORD.bits())] {
+            let data = file_header("x", b"data", method, flags);
+            let mut f = &data[..];
+            let file = block_on(FileHeader14::read(&mut f)).unwrap().unwrap();
+            match file.reader(&mut f) {
+                Err(ref e) => match *e.kind() {
+                    ErrorKind::Unsupported(_) => {}
+                    ref kind => panic!("unexpected error: {}", kind),
+                },
+                Ok(_) => panic!("expected an unsupported error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_short_header_errors() {
+        let data = file_header("roar.txt", b"roar", 0, 0);
+        assert!(block_on(FileHeader14::read(&mut &data[..10])).is_err());
+    }
+}
@@ -0,0 +1,520 @@
+// Archive level structures of RAR5: the main and end of archive headers, and
+// listing the files of an archive. Archives can keep copies of all file
+// headers in a quick open service header near the end, which the locator
+// record of the main header points to, so the list can be read with one seek
+// instead of walking every header.
+
+use super::encryption::ArchiveEncryption;
+use super::file::FileHeader;
+use super::header::{read_header, Header, HeaderType};
+use super::vint::read_vint;
+use crate::archive::Archive;
+use crate::block::{read_data, BufferCursor};
+use crate::crypt::Rar5Key;
+use crate::error::{Error, Result};
+use crate::traits::AsyncFile;
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::AsyncSeek;
+
+// Service name of the quick open header.
+pub const QUICK_OPEN_SERVICE: &str = "QO";
+
+// Extra record type of the main header pointing at other structures.
+const MAIN_EXTRA_LOCATOR: u64 = 0x01;
+// Locator flags.
+const LOCATOR_QUICK_OPEN: u64 = 0x01;
+const LOCATOR_RECOVERY: u64 = 0x02;
+
+bitflags! {
+    pub struct ArchiveFlags: u64 {
+        const VOLUME = 0x0001;
+        const VOLUME_NUMBER = 0x0002;
+        const SOLID = 0x0004;
+        const RECOVERY = 0x0008;
+        const LOCKED = 0x0010;
+    }
+}
+
+bitflags! {
+    pub struct EndFlags: u64 {
+        const NEXT_VOLUME = 0x0001;
+    }
+}
+
+// Where the quick open header and the recovery record are, as offsets from
+// the start of the main header.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Locator {
+    pub quick_open: Option<u64>,
+    pub recovery: Option<u64>,
+}
+
+impl Locator {
+    pub fn parse(data: &[u8]) -> Result<Locator> {
+        let mut cursor = BufferCursor::new(data);
+        let flags = read_vint(&mut cursor)?;
+        let mut locator = Locator::default();
+        // 0 means the structure wasn't written after all.
+        if flags & LOCATOR_QUICK_OPEN != 0 {
+            locator.quick_open = Some(read_vint(&mut cursor)?).filter(|&o| o != 0);
+        }
+        if flags & LOCATOR_RECOVERY != 0 {
+            locator.recovery = Some(read_vint(&mut cursor)?).filter(|&o| o != 0);
+        }
+        Ok(locator)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MainHeader {
+    // Where the header starts in the file.
+    pub offset: u64,
+    pub header: Header,
+    pub archive_flags: ArchiveFlags,
+    // Missing for the first volume.
+    pub volume_number: Option<u64>,
+    pub locator: Option<Locator>,
+}
+
+impl MainHeader {
+    pub fn parse(header: Header, offset: u64) -> Result<MainHeader> {
+        if header.header_type != Some(HeaderType::Main) {
+            return Err(Error::bad_block(format!(
+                "Expected main header, found type {}",
+                header.raw_type
+            )));
+        }
+        let mut cursor = BufferCursor::new(header.fields());
+        let archive_flags = ArchiveFlags::from_bits_truncate(read_vint(&mut cursor)?);
+        let volume_number = if archive_flags.contains(ArchiveFlags::VOLUME_NUMBER) {
+            Some(read_vint(&mut cursor)?)
+        } else {
+            None
+        };
+
+        let mut locator = None;
+        for record in header.extra_records()? {
+            if record.record_type == MAIN_EXTRA_LOCATOR {
+                locator = Some(Locator::parse(record.data)?);
+            }
+        }
+
+        Ok(MainHeader {
+            offset,
+            header,
+            archive_flags,
+            volume_number,
+            locator,
+        })
+    }
+
+    pub fn is_volume(&self) -> bool {
+        self.archive_flags.contains(ArchiveFlags::VOLUME)
+    }
+
+    // Where the quick open header starts in the file, if there is one.
+    pub fn quick_open_offset(&self) -> Option<u64> {
+        let offset = self.locator?.quick_open?;
+        Some(self.offset + offset)
+    }
+
+    // Where the recovery record starts in the file, if there is one.
+    pub fn recovery_offset(&self) -> Option<u64> {
+        let offset = self.locator?.recovery?;
+        Some(self.offset + offset)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EndOfArchive {
+    pub header: Header,
+    pub end_flags: EndFlags,
+}
+
+impl EndOfArchive {
+    pub fn parse(header: Header) -> Result<EndOfArchive> {
+        if header.header_type != Some(HeaderType::EndOfArchive) {
+            return Err(Error::bad_block(format!(
+                "Expected end of archive header, found type {}",
+                header.raw_type
+            )));
+        }
+        let mut cursor = BufferCursor::new(header.fields());
+        let end_flags = EndFlags::from_bits_truncate(read_vint(&mut cursor)?);
+        Ok(EndOfArchive { header, end_flags })
+    }
+
+    // Whether the archive continues in another volume.
+    pub fn has_next_volume(&self) -> bool {
+        self.end_flags.contains(EndFlags::NEXT_VOLUME)
+    }
+}
+
+// The headers of a RAR5 archive, or of one volume.
+#[derive(Debug, Clone)]
+pub struct Rar5Listing {
+    pub main: MainHeader,
+    pub entries: Vec<Rar5Entry>,
+    // Missing if the archive is cut off.
+    pub end: Option<EndOfArchive>,
+}
+
+// A file of a RAR5 archive and where it's stored.
+#[derive(Debug, Clone)]
+pub struct Rar5Entry {
+    pub header_offset: u64,
+    pub data_offset: u64,
+    pub file: FileHeader,
+}
+
+// Split the data of a quick open header into the headers it caches, each
+// with its distance back from the quick open header. Every cached header
+// has its own CRC32, and any mismatch fails the whole cache.
+pub fn parse_quick_open(data: &[u8]) -> Result<Vec<(u64, &[u8])>> {
+    let mut cursor = BufferCursor::new(data);
+    let mut cached = Vec::new();
+    while cursor.remaining() > 0 {
+        let expected_crc = LittleEndian::read_u32(cursor.read(4)?);
+        let record = cursor.peek(cursor.remaining())?;
+        let mut record_cursor = BufferCursor::new(record);
+        let size = read_vint(&mut record_cursor)? as usize;
+        let size_len = record.len() - record_cursor.remaining();
+        let record = cursor.read(size_len + size)?;
+        if crc::crc32::checksum_ieee(record) != expected_crc {
+            return Err(Error::bad_block("Quick open record CRC mismatch".into()));
+        }
+
+        let mut record_cursor = BufferCursor::new(&record[size_len..]);
+        let _flags = read_vint(&mut record_cursor)?;
+        let offset = read_vint(&mut record_cursor)?;
+        let header_size = read_vint(&mut record_cursor)? as usize;
+        cached.push((offset, record_cursor.read(header_size)?));
+    }
+    Ok(cached)
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Read the main header of a RAR5 archive, opened right after the
+    // signature. `key` is needed for archives with encrypted headers.
+    pub async fn read_rar5_main_header(&mut self, key: Option<&Rar5Key>) -> Result<MainHeader> {
+        let mut offset = self.position();
+        let mut header = read_header(self, None).await?;
+        if header.header_type == Some(HeaderType::Encryption) {
+            offset = self.position();
+            let key = key.ok_or_else(Error::wrong_password)?;
+            header = read_header(self, Some(key)).await?;
+        }
+        MainHeader::parse(header, offset)
+    }
+
+    // List the files of a RAR5 archive, opened right after the signature.
+    // Uses the quick open header when there is one and it's intact, and
+    // reads every header otherwise. `key` is needed for archives with
+    // encrypted headers, which don't use the quick open header.
+    pub async fn list_rar5(&mut self, key: Option<&Rar5Key>) -> Result<Rar5Listing> {
+        let start = self.position();
+        let main = self.read_rar5_main_header(key).await?;
+        // Headers are encrypted when an encryption header came first.
+        let key = if main.offset != start { key } else { None };
+        let headers_start = self.position();
+
+        let mut entries = None;
+        if let (None, Some(offset)) = (key, main.quick_open_offset()) {
+            entries = self.read_quick_open(offset).await.ok();
+            if entries.is_none() {
+                self.skip_to(headers_start).await?;
+            }
+        }
+
+        let mut scanned = Vec::new();
+        let mut end = None;
+        while !self.at_eof().await? {
+            let header_offset = self.position();
+            let header = read_header(self, key).await?;
+            let data_size = header.data_size;
+            match header.header_type {
+                Some(HeaderType::File) if entries.is_none() => scanned.push(Rar5Entry {
+                    header_offset,
+                    data_offset: self.position(),
+                    file: FileHeader::parse(header)?,
+                }),
+                Some(HeaderType::EndOfArchive) => {
+                    end = Some(EndOfArchive::parse(header)?);
+                    break;
+                }
+                _ => {}
+            }
+            self.skip_to(self.position() + data_size).await?;
+        }
+        Ok(Rar5Listing {
+            main,
+            entries: entries.unwrap_or(scanned),
+            end,
+        })
+    }
+
+    // Like `list_rar5`, deriving the header key from `password` when the
+    // headers turn out to be encrypted.
+    pub async fn list_rar5_with_password(&mut self, password: Option<&str>) -> Result<Rar5Listing> {
+        let start = self.position();
+        let header = read_header(self, None).await?;
+        let key = if header.header_type == Some(HeaderType::Encryption) {
+            let password = password.ok_or_else(Error::wrong_password)?;
+            Some(ArchiveEncryption::parse(&header)?.key(password)?)
+        } else {
+            None
+        };
+        self.skip_to(start).await?;
+        self.list_rar5(key.as_ref()).await
+    }
+
+    async fn read_quick_open(&mut self, offset: u64) -> Result<Vec<Rar5Entry>> {
+        self.skip_to(offset).await?;
+        let service = FileHeader::parse(read_header(self, None).await?)?;
+        if service.header.header_type != Some(HeaderType::Service)
+            || service.name != QUICK_OPEN_SERVICE
+        {
+            return Err(Error::bad_block(format!(
+                "No quick open header at offset {}",
+                offset
+            )));
+        }
+        if service.is_encrypted() || service.compression()?.method != 0 {
+            return Err(Error::unsupported("Packed quick open data".into()));
+        }
+        let data = read_data(self, service.packed_size()).await?;
+
+        let mut entries = Vec::new();
+        for (back, raw) in parse_quick_open(&data)? {
+            let header_offset = offset
+                .checked_sub(back)
+                .ok_or_else(|| Error::bad_block("Quick open offset out of range".into()))?;
+            let header = read_header(&mut &raw[..], None).await?;
+            if header.header_type == Some(HeaderType::File) {
+                entries.push(Rar5Entry {
+                    header_offset,
+                    data_offset: header_offset + raw.len() as u64,
+                    file: FileHeader::parse(header)?,
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR50_SIGNATURE;
+    use crate::error::ErrorKind;
+    use crate::rar5::write_vint;
+    use crate::testing::{rar5_file_header, rar5_header};
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    // A stored file or service header followed by its data.
+    fn file_header(header_type: u8, name: &str, data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let crc = crc::crc32::checksum_ieee(data);
+        let header = rar5_file_header(header_type, name, data.len() as u8, crc);
+        (header, data.to_vec())
+    }
+
+    fn main_header(quick_open: Option<u64>) -> Vec<u8> {
+        match quick_open {
+            None => rar5_header(&[0x01, 0x00, 0x00]),
+            Some(offset) => {
+                // The offset always takes two bytes to keep the size fixed.
+                let locator = [0x04, 0x01, 0x01, offset as u8 | 0x80, (offset >> 7) as u8];
+                let mut body = vec![0x01, 0x01, locator.len() as u8, 0x00];
+                body.extend(&locator);
+                rar5_header(&body)
+            }
+        }
+    }
+
+    fn quick_open_record(back: u64, header: &[u8]) -> Vec<u8> {
+        let mut fields = Vec::new();
+        write_vint(0, &mut fields);
+        write_vint(back, &mut fields);
+        write_vint(header.len() as u64, &mut fields);
+        fields.extend(header);
+        let mut record = Vec::new();
+        write_vint(fields.len() as u64, &mut record);
+        record.extend(fields);
+        let mut out = crc::crc32::checksum_ieee(&record).to_le_bytes().to_vec();
+        out.extend(record);
+        out
+    }
+
+    // An archive with two files and a quick open header caching both,
+    // along with where the first file header starts.
+    fn archive(with_quick_open: bool) -> (Vec<u8>, usize) {
+        let files = vec![
+            file_header(2, "a.txt", b"roar"),
+            file_header(2, "b.txt", b"meow"),
+        ];
+        let main_len = main_header(Some(0)).len();
+        let main_start = RAR50_SIGNATURE.len();
+
+        let mut offsets = Vec::new();
+        let mut body: Vec<u8> = Vec::new();
+        for (header, data) in &files {
+            offsets.push(main_start + main_len + body.len());
+            body.extend(header);
+            body.extend(data);
+        }
+        let quick_open_start = main_start + main_len + body.len();
+        let mut cache = Vec::new();
+        for (offset, (header, _)) in offsets.iter().zip(&files) {
+            cache.extend(quick_open_record(
+                (quick_open_start - offset) as u64,
+                header,
+            ));
+        }
+        let (quick_open, _) = file_header(3, QUICK_OPEN_SERVICE, &cache);
+
+        let mut buf = RAR50_SIGNATURE.to_vec();
+        if with_quick_open {
+            buf.extend(main_header(Some((quick_open_start - main_start) as u64)));
+        } else {
+            buf.extend(main_header(Some(0)));
+        }
+        buf.extend(body);
+        buf.extend(quick_open);
+        buf.extend(cache);
+        buf.extend(rar5_header(&[0x05, 0x00, 0x00]));
+        (buf, offsets[0])
+    }
+
+    fn names(data: Vec<u8>) -> Result<Vec<(String, u64)>> {
+        let mut archive = block_on(Archive::open(Cursor::new(data)))?;
+        let listing = block_on(archive.list_rar5(None))?;
+        assert!(listing.end.is_some());
+        Ok(listing
+            .entries
+            .into_iter()
+            .map(|e| (e.file.name, e.data_offset))
+            .collect())
+    }
+
+    fn parse_main(raw: &[u8], offset: u64) -> MainHeader {
+        let header = block_on(read_header(&mut &raw[..], None)).unwrap();
+        MainHeader::parse(header, offset).unwrap()
+    }
+
+    #[test]
+    fn test_parse_main_header() {
+        let main = parse_main(&main_header(Some(300)), 8);
+        assert_eq!(main.archive_flags, ArchiveFlags::empty());
+        assert_eq!(main.volume_number, None);
+        assert_eq!(main.quick_open_offset(), Some(308));
+        assert_eq!(main.recovery_offset(), None);
+
+        let main = parse_main(&rar5_header(&[0x01, 0x00, 0x03, 0x05]), 8);
+        assert!(main.is_volume());
+        assert_eq!(main.volume_number, Some(5));
+        assert_eq!(main.locator, None);
+    }
+
+    #[test]
+    fn test_parse_locator() {
+        let locator = Locator::parse(&[0x03, 0x00, 0xe8, 0x07]).unwrap();
+        assert_eq!(locator.quick_open, None);
+        assert_eq!(locator.recovery, Some(1000));
+        let main = parse_main(
+            &rar5_header(&[0x01, 0x01, 0x04, 0x00, 0x03, 0x01, 0x02, 0x10]),
+            20,
+        );
+        assert_eq!(main.recovery_offset(), Some(36));
+        assert_eq!(main.quick_open_offset(), None);
+        assert!(Locator::parse(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_parse_end_of_archive() {
+        for &(flags, next) in &[(0x00, false), (0x01, true)] {
+            let raw = rar5_header(&[0x05, 0x00, flags]);
+            let header = block_on(read_header(&mut &raw[..], None)).unwrap();
+            assert_eq!(EndOfArchive::parse(header).unwrap().has_next_volume(), next);
+        }
+        let raw = main_header(None);
+        let header = block_on(read_header(&mut &raw[..], None)).unwrap();
+        assert!(EndOfArchive::parse(header).is_err());
+    }
+
+    #[test]
+    fn test_lists_with_password() {
+        let (data, _) = archive(true);
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        let listing = block_on(archive.list_rar5_with_password(Some("roar"))).unwrap();
+        assert_eq!(listing.entries.len(), 2);
+
+        // Encrypted headers without a password.
+        let mut data = RAR50_SIGNATURE.to_vec();
+        let mut body = vec![0x04, 0x00, 0x00, 0x00, 0x0f];
+        body.extend(&[0; 16]);
+        data.extend(rar5_header(&body));
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        match *block_on(archive.list_rar5_with_password(None))
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::WrongPassword => {}
+            ref kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn test_lists_from_quick_open() {
+        let (mut data, first) = archive(true);
+        let expected = names(data.clone()).unwrap();
+        assert_eq!(expected[0].0, "a.txt");
+        assert_eq!(expected[1].0, "b.txt");
+        assert_eq!(&data[expected[1].1 as usize..][..4], b"meow");
+
+        // The headers themselves aren't read.
+        data[first] ^= 1;
+        assert_eq!(names(data).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_falls_back_to_scan() {
+        let (data, _) = archive(true);
+        let expected = names(data.clone()).unwrap();
+
+        let (without, _) = archive(false);
+        assert_eq!(names(without).unwrap(), expected);
+
+        // A damaged cache.
+        let mut damaged = data;
+        let last = damaged.len() - 12;
+        damaged[last] ^= 1;
+        assert_eq!(names(damaged).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_forged_quick_open_size_falls_back_to_scan() {
+        let (mut data, _) = archive(true);
+        let expected = names(data.clone()).unwrap();
+
+        // Claim a terabyte of cached headers. The scan that follows runs
+        // off the end with it too, so there's no end of archive header.
+        let name_end = data.windows(2).position(|w| w == b"QO").unwrap() + 2;
+        let start = name_end - rar5_file_header(3, QUICK_OPEN_SERVICE, 0, 0).len();
+        let mut body = vec![0x03, 0x02];
+        write_vint(1 << 40, &mut body);
+        body.extend(&[0x04, 0x00, 0x20, 0, 0, 0, 0, 0x00, 0x01, 0x02]);
+        body.extend(QUICK_OPEN_SERVICE.as_bytes());
+        data.splice(start..name_end, rar5_header(&body));
+
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        let listing = block_on(archive.list_rar5(None)).unwrap();
+        let found: Vec<_> = listing
+            .entries
+            .into_iter()
+            .map(|e| (e.file.name, e.data_offset))
+            .collect();
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,192 @@
+use super::header::{Header, HeaderType};
+use super::vint::read_vint;
+use crate::block::BufferCursor;
+use crate::crypt::{
+    CbcDecryptor, PasswordCheck, Rar5Key, AES_BLOCK_SIZE, PASSWORD_CHECK_CSUM_SIZE,
+    PASSWORD_CHECK_SIZE, SALT_SIZE,
+};
+use crate::error::{Error, Result};
+use bitflags::bitflags;
+
+// The only encryption version defined so far.
+const ENCRYPTION_AES256: u64 = 0;
+
+bitflags! {
+    pub struct EncryptionFlags: u64 {
+        // A password check value follows the salt (and IV, for files).
+        const PASSWORD_CHECK = 0x0001;
+
+        // File checksums are passed through HMAC with the hash key.
+        const TWEAKED_CHECKSUMS = 0x0002;
+    }
+}
+
+fn read_common<'a>(
+    cursor: &mut BufferCursor<'a>,
+) -> Result<(EncryptionFlags, u8, [u8; SALT_SIZE])> {
+    let version = read_vint(cursor)?;
+    if version != ENCRYPTION_AES256 {
+        return Err(Error::bad_block(format!(
+            "Unknown encryption version {}",
+            version
+        )));
+    }
+    let flags = EncryptionFlags::from_bits_truncate(read_vint(cursor)?);
+    let kdf_count = cursor.read(1)?[0];
+    let mut salt = [0u8; SALT_SIZE];
+    salt.copy_from_slice(cursor.read(SALT_SIZE)?);
+    Ok((flags, kdf_count, salt))
+}
+
+fn read_check<'a>(
+    cursor: &mut BufferCursor<'a>,
+    flags: EncryptionFlags,
+) -> Result<Option<PasswordCheck>> {
+    if flags.contains(EncryptionFlags::PASSWORD_CHECK) {
+        let buf = cursor.read(PASSWORD_CHECK_SIZE + PASSWORD_CHECK_CSUM_SIZE)?;
+        Ok(Some(PasswordCheck::from_bytes(buf)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn derive_key(
+    password: &str,
+    salt: &[u8],
+    kdf_count: u8,
+    check: &Option<PasswordCheck>,
+) -> Result<Rar5Key> {
+    let key = Rar5Key::derive(password, salt, kdf_count)?;
+    if let Some(ref check) = *check {
+        key.verify(check)?;
+    }
+    Ok(key)
+}
+
+// The archive encryption header, present when headers are encrypted. Every
+// header after it is encrypted with the key derived from this salt.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ArchiveEncryption {
+    pub flags: EncryptionFlags,
+    pub kdf_count: u8,
+    pub salt: [u8; SALT_SIZE],
+    pub check: Option<PasswordCheck>,
+}
+
+impl ArchiveEncryption {
+    pub fn parse(header: &Header) -> Result<ArchiveEncryption> {
+        if header.header_type != Some(HeaderType::Encryption) {
+            return Err(Error::bad_block(format!(
+                "Expected encryption header, found type {}",
+                header.raw_type
+            )));
+        }
+        let mut cursor = BufferCursor::new(header.fields());
+        let (flags, kdf_count, salt) = read_common(&mut cursor)?;
+        let check = read_check(&mut cursor, flags)?;
+        Ok(ArchiveEncryption {
+            flags,
+            kdf_count,
+            salt,
+            check,
+        })
+    }
+
+    // Derive the header key, failing with `WrongPassword` straight away if
+    // the archive carries a check value that doesn't match.
+    pub fn key(&self, password: &str) -> Result<Rar5Key> {
+        derive_key(password, &self.salt, self.kdf_count, &self.check)
+    }
+}
+
+// The encryption record from the extra area of a file or service header.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FileEncryption {
+    pub flags: EncryptionFlags,
+    pub kdf_count: u8,
+    pub salt: [u8; SALT_SIZE],
+    pub iv: [u8; AES_BLOCK_SIZE],
+    pub check: Option<PasswordCheck>,
+}
+
+impl FileEncryption {
+    pub fn parse(data: &[u8]) -> Result<FileEncryption> {
+        let mut cursor = BufferCursor::new(data);
+        let (flags, kdf_count, salt) = read_common(&mut cursor)?;
+        let mut iv = [0u8; AES_BLOCK_SIZE];
+        iv.copy_from_slice(cursor.read(AES_BLOCK_SIZE)?);
+        let check = read_check(&mut cursor, flags)?;
+        Ok(FileEncryption {
+            flags,
+            kdf_count,
+            salt,
+            iv,
+            check,
+        })
+    }
+
+    pub fn key(&self, password: &str) -> Result<Rar5Key> {
+        derive_key(password, &self.salt, self.kdf_count, &self.check)
+    }
+
+    pub fn decryptor(&self, key: &Rar5Key) -> CbcDecryptor {
+        CbcDecryptor::new(key.key(), &self.iv)
+    }
+
+    pub fn uses_tweaked_checksums(&self) -> bool {
+        self.flags.contains(EncryptionFlags::TWEAKED_CHECKSUMS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use sha2::{Digest, Sha256};
+
+    fn record(password: &str, with_check: bool) -> Vec<u8> {
+        let salt = *b"0123456789abcdef";
+        let flags = if with_check { 0x03 } else { 0x02 };
+        let mut buf = vec![0x00, flags, 0x01];
+        buf.extend(&salt);
+        buf.extend(&[9u8; AES_BLOCK_SIZE]);
+        if with_check {
+            let key = Rar5Key::derive(password, &salt, 1).unwrap();
+            buf.extend(key.password_check());
+            buf.extend(&Sha256::digest(key.password_check())[..PASSWORD_CHECK_CSUM_SIZE]);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_parse_file_encryption() {
+        let enc = FileEncryption::parse(&record("roar", true)).unwrap();
+        assert_eq!(enc.kdf_count, 1);
+        assert_eq!(&enc.salt, b"0123456789abcdef");
+        assert_eq!(enc.iv, [9u8; AES_BLOCK_SIZE]);
+        assert!(enc.check.is_some());
+        assert!(enc.uses_tweaked_checksums());
+    }
+
+    #[test]
+    fn test_file_encryption_key_checks_password() {
+        let enc = FileEncryption::parse(&record("roar", true)).unwrap();
+        assert!(enc.key("roar").is_ok());
+        let err = enc.key("meow").unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::WrongPassword);
+    }
+
+    #[test]
+    fn test_file_encryption_without_check_accepts_any_password() {
+        let enc = FileEncryption::parse(&record("roar", false)).unwrap();
+        assert!(enc.check.is_none());
+        assert!(enc.key("meow").is_ok());
+    }
+
+    #[test]
+    fn test_rejects_unknown_encryption_version() {
+        let mut buf = record("roar", false);
+        buf[0] = 1;
+        assert!(FileEncryption::parse(&buf).is_err());
+    }
+}
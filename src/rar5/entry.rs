@@ -0,0 +1,261 @@
+// Streaming the contents of RAR5 files. The packed data is decrypted if need
+// be and unpacked, and what comes out is checked against the stored checksum
+// and size as it's read, so the end of the stream is only reached when the
+// file is intact.
+
+use super::archive::{ArchiveFlags, MainHeader, Rar5Entry, Rar5Listing};
+use super::file::FileHeader;
+use crate::archive::Archive;
+use crate::crypt::DecryptReader;
+use crate::error::{Error, Result};
+use crate::hash::VerifyReader;
+use crate::traits::AsyncFile;
+use crate::unpack::{EntryReader, Status, Store, Unpack50, Unpacker};
+use futures::io::{AsyncRead, AsyncSeek};
+use futures::task::{Context, Poll};
+use std::io;
+use std::pin::Pin;
+
+// What's carried from one RAR5 file to the next while reading them in order.
+// Files of solid archives must be read in order, each to its end.
+pub struct Rar5Unpacking {
+    solid_archive: bool,
+    // The decoder of the last packed file, which solid files continue.
+    solid: Option<Box<Unpack50>>,
+    password: Option<String>,
+}
+
+impl Rar5Unpacking {
+    pub fn new(listing: &Rar5Listing, password: Option<String>) -> Rar5Unpacking {
+        Rar5Unpacking::from_main(&listing.main, password)
+    }
+
+    // For the files of the archive or volume set with main header `main`.
+    pub fn from_main(main: &MainHeader, password: Option<String>) -> Rar5Unpacking {
+        Rar5Unpacking {
+            solid_archive: main.archive_flags.contains(ArchiveFlags::SOLID),
+            solid: None,
+            password,
+        }
+    }
+
+    pub fn is_solid(&self) -> bool {
+        self.solid_archive
+    }
+}
+
+// The packed data of a file, decrypted if it's encrypted.
+enum Rar5Input<R> {
+    Plain(R),
+    Encrypted(Box<DecryptReader<R>>),
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Rar5Input<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match *self {
+            Rar5Input::Plain(ref mut inner) => Pin::new(inner).poll_read(cx, buf),
+            Rar5Input::Encrypted(ref mut inner) => Pin::new(inner).poll_read(cx, buf),
+        }
+    }
+}
+
+// Stored files are copied, the others go through the version 50 decoder.
+enum Rar5Decoder {
+    Store(Store),
+    Lz(Box<Unpack50>),
+}
+
+impl Unpacker for Rar5Decoder {
+    fn feed(&mut self, data: &[u8]) {
+        match *self {
+            Rar5Decoder::Store(ref mut store) => store.feed(data),
+            Rar5Decoder::Lz(ref mut lz) => lz.feed(data),
+        }
+    }
+
+    fn finish(&mut self) {
+        match *self {
+            Rar5Decoder::Store(ref mut store) => store.finish(),
+            Rar5Decoder::Lz(ref mut lz) => lz.finish(),
+        }
+    }
+
+    fn unpack(&mut self, out: &mut [u8]) -> Result<Status> {
+        match *self {
+            Rar5Decoder::Store(ref mut store) => store.unpack(out),
+            Rar5Decoder::Lz(ref mut lz) => lz.unpack(out),
+        }
+    }
+}
+
+type Rar5Chain<R> = VerifyReader<EntryReader<Rar5Input<R>, Rar5Decoder>>;
+
+// Streams the unpacked contents of a RAR5 file. Reading fails if the data
+// doesn't match the stored checksum or unpacked size. Once the end is
+// reached, the decoder is handed back for the next file of a solid archive.
+pub struct Rar5EntryReader<'a, R> {
+    // `None` once the end was reached.
+    inner: Option<Rar5Chain<R>>,
+    solid: &'a mut Option<Box<Unpack50>>,
+    unpacked_size: Option<u64>,
+    unpacked: u64,
+}
+
+impl<'a, R: AsyncRead + Unpin> Rar5EntryReader<'a, R> {
+    // `input` must be positioned at the packed data of `file`.
+    pub fn new(
+        input: R,
+        file: &FileHeader,
+        state: &'a mut Rar5Unpacking,
+    ) -> Result<Rar5EntryReader<'a, R>> {
+        let info = file.compression()?;
+        let packed_size = file.packed_size();
+        let (input, key) = match file.encryption {
+            None => (Rar5Input::Plain(input), None),
+            Some(ref encryption) => {
+                let password = state
+                    .password
+                    .as_ref()
+                    .ok_or_else(|| Error::unsupported("Encrypted files".into()))?;
+                let key = encryption.key(password)?;
+                let decryptor = encryption.decryptor(&key);
+                let input = DecryptReader::new(input, decryptor, packed_size);
+                (Rar5Input::Encrypted(Box::new(input)), Some(key))
+            }
+        };
+        let decoder = if info.is_stored() {
+            // Encrypted data is padded, so stop at the unpacked size if known.
+            let store = file
+                .unpacked_size()
+                .map_or_else(Store::new, Store::with_size);
+            Rar5Decoder::Store(store)
+        } else {
+            let size = if state.solid_archive {
+                None
+            } else {
+                file.unpacked_size()
+            };
+            // Any failure breaks the chain of solid files, as the decoder is
+            // only handed back at the end.
+            let lz = match state.solid.take() {
+                Some(mut lz) if info.solid => {
+                    lz.next_entry(&info, size)?;
+                    lz
+                }
+                _ => Box::new(Unpack50::new(&info, size)?),
+            };
+            Rar5Decoder::Lz(lz)
+        };
+        let reader = EntryReader::new(input, decoder, packed_size);
+        Ok(Rar5EntryReader {
+            inner: Some(file.verify(reader, key.as_ref())),
+            solid: &mut state.solid,
+            unpacked_size: file.unpacked_size(),
+            unpacked: 0,
+        })
+    }
+
+    // Called at the end of the data, after the checksum matched.
+    fn finish(&mut self) -> io::Result<()> {
+        let inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => return Ok(()),
+        };
+        if let Some(size) = self.unpacked_size {
+            if size != self.unpacked {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Unpacked size mismatch: expected {}, got {}",
+                        size, self.unpacked
+                    ),
+                ));
+            }
+        }
+        if let (_, Rar5Decoder::Lz(lz)) = inner.into_inner().into_parts() {
+            *self.solid = Some(lz);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRead for Rar5EntryReader<'a, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let inner = match this.inner {
+            Some(ref mut inner) => inner,
+            None => return Poll::Ready(Ok(0)),
+        };
+        let n = match Pin::new(inner).poll_read(cx, buf) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Ready(Ok(n)) => n,
+        };
+        if n == 0 && !buf.is_empty() {
+            return Poll::Ready(this.finish().map(|_| 0));
+        }
+        this.unpacked += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Stream the contents of a file listed by `list_rar5`. Files of solid
+    // archives must be read in order, sharing `state`.
+    pub async fn open_rar5_entry<'a>(
+        &'a mut self,
+        entry: &Rar5Entry,
+        state: &'a mut Rar5Unpacking,
+    ) -> Result<Rar5EntryReader<'a, &'a mut Archive<T>>> {
+        self.skip_to(entry.data_offset).await?;
+        Rar5EntryReader::new(self, &entry.file, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR50_SIGNATURE;
+    use crate::testing::{rar5_file_header, rar5_header};
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+    use std::io::Cursor;
+
+    fn read_all(data: Vec<u8>) -> Vec<io::Result<Vec<u8>>> {
+        let mut archive = block_on(Archive::open(Cursor::new(data))).unwrap();
+        let listing = block_on(archive.list_rar5(None)).unwrap();
+        let mut state = Rar5Unpacking::new(&listing, None);
+        let mut results = Vec::new();
+        for entry in &listing.entries {
+            let mut reader = block_on(archive.open_rar5_entry(entry, &mut state)).unwrap();
+            let mut out = Vec::new();
+            results.push(block_on(reader.read_to_end(&mut out)).map(|_| out));
+        }
+        results
+    }
+
+    #[test]
+    fn test_streams_stored_files_and_checks_them() {
+        let mut data = RAR50_SIGNATURE.to_vec();
+        data.extend(rar5_header(&[0x01, 0x00, 0x00]));
+        let crc = crc::crc32::checksum_ieee(b"roar");
+        data.extend(rar5_file_header(2, "a.txt", 4, crc));
+        data.extend(b"roar");
+        data.extend(rar5_file_header(2, "b.txt", 4, crc));
+        data.extend(b"meow");
+        data.extend(rar5_header(&[0x05, 0x00, 0x00]));
+
+        let results = read_all(data);
+        assert_eq!(results[0].as_ref().unwrap(), b"roar");
+        let err = results[1].as_ref().unwrap_err();
+        assert!(err.to_string().contains("CRC32 mismatch"));
+    }
+}
@@ -0,0 +1,347 @@
+use super::encryption::FileEncryption;
+use super::header::{FileExtraType, Header, HeaderType};
+use super::vint::read_vint;
+use crate::block::BufferCursor;
+use crate::crypt::Rar5Key;
+use crate::error::{Error, Result};
+use crate::hash::{FileHash, HashType, VerifyReader, BLAKE2S_DIGEST_SIZE};
+use crate::links::Link;
+use crate::owner::UnixOwner;
+use crate::permissions::Permissions;
+use crate::streams::{utf8_stream_name, STREAM_SERVICE};
+use crate::unpack::CompressionInfo;
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::AsyncRead;
+use num::FromPrimitive;
+
+// Hash types used by the hash extra record.
+const HASH_BLAKE2SP: u64 = 0;
+
+#[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
+pub enum HostOs {
+    Windows = 0,
+    Unix = 1,
+}
+
+impl HostOs {
+    pub fn from_u64(that: u64) -> Option<HostOs> {
+        FromPrimitive::from_u64(that)
+    }
+}
+
+bitflags! {
+    pub struct FileFlags: u64 {
+        const DIRECTORY = 0x0001;
+        const UNIX_MTIME = 0x0002;
+        const CRC32 = 0x0004;
+        const UNKNOWN_UNPACKED_SIZE = 0x0008;
+    }
+}
+
+// A RAR5 file header, or a service header, which shares its layout.
+#[derive(Debug, Clone)]
+pub struct FileHeader {
+    pub header: Header,
+    pub file_flags: FileFlags,
+    pub unpacked_size: u64,
+    pub attributes: u64,
+    pub mtime: Option<u32>,
+    pub data_crc32: Option<u32>,
+    pub compression_info: u64,
+    pub host_os: Option<HostOs>,
+    pub name: String,
+
+    pub encryption: Option<FileEncryption>,
+    pub blake2sp: Option<[u8; BLAKE2S_DIGEST_SIZE]>,
+    // Data specific to the service type, like the name of an NTFS stream.
+    pub service_data: Option<Vec<u8>>,
+    pub owner: Option<UnixOwner>,
+    pub link: Option<Link>,
+    // Version number of an old version of a file.
+    pub version: Option<u64>,
+}
+
+impl FileHeader {
+    pub fn parse(header: Header) -> Result<FileHeader> {
+        match header.header_type {
+            Some(HeaderType::File) | Some(HeaderType::Service) => {}
+            _ => {
+                return Err(Error::bad_block(format!(
+                    "Expected file header, found type {}",
+                    header.raw_type
+                )))
+            }
+        }
+
+        let mut cursor = BufferCursor::new(header.fields());
+        let file_flags = FileFlags::from_bits_truncate(read_vint(&mut cursor)?);
+        let unpacked_size = read_vint(&mut cursor)?;
+        let attributes = read_vint(&mut cursor)?;
+        let mtime = if file_flags.contains(FileFlags::UNIX_MTIME) {
+            Some(LittleEndian::read_u32(cursor.read(4)?))
+        } else {
+            None
+        };
+        let data_crc32 = if file_flags.contains(FileFlags::CRC32) {
+            Some(LittleEndian::read_u32(cursor.read(4)?))
+        } else {
+            None
+        };
+        let compression_info = read_vint(&mut cursor)?;
+        let host_os = HostOs::from_u64(read_vint(&mut cursor)?);
+        let name_len = read_vint(&mut cursor)? as usize;
+        let name = String::from_utf8_lossy(cursor.read(name_len)?).into_owned();
+
+        let mut encryption = None;
+        let mut blake2sp = None;
+        let mut service_data = None;
+        let mut owner = None;
+        let mut link = None;
+        let mut version = None;
+        for record in header.extra_records()? {
+            match FileExtraType::from_u64(record.record_type) {
+                Some(FileExtraType::Encryption) => {
+                    encryption = Some(FileEncryption::parse(record.data)?);
+                }
+                Some(FileExtraType::Hash) => {
+                    let mut cursor = BufferCursor::new(record.data);
+                    if read_vint(&mut cursor)? == HASH_BLAKE2SP {
+                        let mut digest = [0u8; BLAKE2S_DIGEST_SIZE];
+                        digest.copy_from_slice(cursor.read(BLAKE2S_DIGEST_SIZE)?);
+                        blake2sp = Some(digest);
+                    }
+                }
+                Some(FileExtraType::UnixOwner) => {
+                    owner = Some(UnixOwner::parse_rar5(record.data)?);
+                }
+                Some(FileExtraType::Redirection) => {
+                    link = Some(Link::parse_rar5(record.data)?);
+                }
+                Some(FileExtraType::Version) => {
+                    let mut cursor = BufferCursor::new(record.data);
+                    let _flags = read_vint(&mut cursor)?;
+                    version = Some(read_vint(&mut cursor)?);
+                }
+                Some(FileExtraType::ServiceData) => {
+                    service_data = Some(record.data.to_vec());
+                }
+                _ => {}
+            }
+        }
+
+        Ok(FileHeader {
+            header,
+            file_flags,
+            unpacked_size,
+            attributes,
+            mtime,
+            data_crc32,
+            compression_info,
+            host_os,
+            name,
+            encryption,
+            blake2sp,
+            service_data,
+            owner,
+            link,
+            version,
+        })
+    }
+
+    pub fn packed_size(&self) -> u64 {
+        self.header.data_size
+    }
+
+    pub fn unpacked_size(&self) -> Option<u64> {
+        if self.file_flags.contains(FileFlags::UNKNOWN_UNPACKED_SIZE) {
+            None
+        } else {
+            Some(self.unpacked_size)
+        }
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.file_flags.contains(FileFlags::DIRECTORY)
+    }
+
+    // Archives made on Unix store the whole mode in the attributes.
+    pub fn unix_mode(&self) -> Option<u32> {
+        match self.host_os {
+            Some(HostOs::Unix) => Some(self.attributes as u32),
+            _ => None,
+        }
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        match self.host_os {
+            Some(HostOs::Unix) => Permissions::from_unix(self.attributes as u32),
+            _ => Permissions::from_dos(self.attributes as u32),
+        }
+    }
+
+    // The name of the NTFS stream held by an STM service header.
+    pub fn stream_name(&self) -> Option<Result<String>> {
+        if self.header.header_type != Some(HeaderType::Service)
+            || self.name.as_bytes() != STREAM_SERVICE
+        {
+            return None;
+        }
+        let data = self.service_data.as_ref().map_or(&[][..], |d| &d[..]);
+        Some(utf8_stream_name(data))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    pub fn compression(&self) -> Result<CompressionInfo> {
+        CompressionInfo::from_raw(self.compression_info)
+    }
+
+    // The checksum to verify the unpacked data against. BLAKE2sp is
+    // preferred when the header carries both.
+    pub fn hash(&self) -> Option<FileHash> {
+        if let Some(digest) = self.blake2sp {
+            Some(FileHash::Blake2sp(digest))
+        } else {
+            self.data_crc32.map(FileHash::Crc32)
+        }
+    }
+
+    pub fn hash_type(&self) -> Option<HashType> {
+        self.hash().map(|h| h.hash_type())
+    }
+
+    // Whether the stored checksum is a MAC keyed from the password.
+    pub fn uses_mac(&self) -> bool {
+        self.encryption
+            .as_ref()
+            .is_some_and(|e| e.uses_tweaked_checksums())
+    }
+
+    // Wrap the unpacked data stream of this entry so that reaching its end
+    // fails if the checksum doesn't match. Entries without a checksum are
+    // passed through. `key` must be the file key when the entry is
+    // encrypted.
+    pub fn verify<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        key: Option<&Rar5Key>,
+    ) -> VerifyReader<R> {
+        let expected = match self.hash() {
+            Some(expected) => expected,
+            None => return VerifyReader::unchecked(reader),
+        };
+        let hash_key = if self.uses_mac() {
+            key.map(|k| *k.hash_key())
+        } else {
+            None
+        };
+        VerifyReader::new(reader, expected, hash_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::links::LinkKind;
+
+    fn header_with(fields: &[u8], extra: &[u8]) -> Header {
+        let mut body = vec![0x02, 0x03, extra.len() as u8, 0x10];
+        body.extend(fields);
+        body.extend(extra);
+        Header::parse(0, body).unwrap()
+    }
+
+    fn fields() -> Vec<u8> {
+        fields_named(b"roar")
+    }
+
+    fn fields_named(name: &[u8]) -> Vec<u8> {
+        let mut buf = vec![
+            0x06, // flags: mtime and crc32
+            0x04, // unpacked size
+            0x20, // attributes
+        ];
+        buf.extend(&[1, 0, 0, 0]); // mtime
+        buf.extend(&[0x26, 0x39, 0xf4, 0xcb]); // crc32
+        buf.extend(&[0x80, 0x01]); // compression info 0x80: method 1
+        buf.push(0x01); // unix
+        buf.push(name.len() as u8);
+        buf.extend(name);
+        buf
+    }
+
+    #[test]
+    fn test_parse_file_header() {
+        let file = FileHeader::parse(header_with(&fields(), &[])).unwrap();
+        assert_eq!(file.name, "roar");
+        assert_eq!(file.unpacked_size(), Some(4));
+        assert_eq!(file.packed_size(), 0x10);
+        assert_eq!(file.attributes, 0x20);
+        assert_eq!(file.mtime, Some(1));
+        assert_eq!(file.host_os, Some(HostOs::Unix));
+        assert_eq!(file.compression().unwrap().method, 1);
+        assert!(!file.is_directory());
+        assert!(!file.is_encrypted());
+        assert_eq!(file.hash(), Some(FileHash::Crc32(0xcbf4_3926)));
+    }
+
+    #[test]
+    fn test_blake2sp_record_takes_precedence() {
+        let mut extra = vec![0x22, 0x02, 0x00];
+        extra.extend(&[0xab; BLAKE2S_DIGEST_SIZE]);
+        let file = FileHeader::parse(header_with(&fields(), &extra)).unwrap();
+        assert_eq!(file.hash_type(), Some(HashType::Blake2sp));
+        assert_eq!(
+            file.hash(),
+            Some(FileHash::Blake2sp([0xab; BLAKE2S_DIGEST_SIZE]))
+        );
+    }
+
+    #[test]
+    fn test_owner_record_and_unix_mode() {
+        let extra = vec![0x04, 0x06, 0x0c, 0x00, 0x00];
+        let file = FileHeader::parse(header_with(&fields(), &extra)).unwrap();
+        assert_eq!(file.owner.as_ref().unwrap().to_string(), "0:0");
+        assert_eq!(file.unix_mode(), Some(0x20));
+        assert_eq!(file.permissions().unix_mode, 0x20);
+    }
+
+    #[test]
+    fn test_redirection_record() {
+        let extra = vec![0x05, 0x05, 0x01, 0x00, 0x01, b'x'];
+        let file = FileHeader::parse(header_with(&fields(), &extra)).unwrap();
+        let link = file.link.unwrap();
+        assert_eq!(link.kind, LinkKind::UnixSymlink);
+        assert_eq!(link.target, "x");
+    }
+
+    #[test]
+    fn test_version_record() {
+        let extra = vec![0x03, 0x04, 0x00, 0x03];
+        let file = FileHeader::parse(header_with(&fields(), &extra)).unwrap();
+        assert_eq!(file.version, Some(3));
+    }
+
+    #[test]
+    fn test_service_header_stream_name() {
+        let mut extra = vec![0x07, 0x07];
+        extra.extend(b":thumb");
+        let mut body = vec![0x03, 0x03, extra.len() as u8, 0x10];
+        body.extend(fields_named(b"STM"));
+        body.extend(&extra);
+        let file = FileHeader::parse(Header::parse(0, body).unwrap()).unwrap();
+        assert_eq!(file.stream_name().unwrap().unwrap(), "thumb");
+
+        let file = FileHeader::parse(header_with(&fields(), &[])).unwrap();
+        assert!(file.stream_name().is_none());
+    }
+
+    #[test]
+    fn test_rejects_other_header_types() {
+        let header = Header::parse(0, vec![0x01, 0x00]).unwrap();
+        assert!(FileHeader::parse(header).is_err());
+    }
+}
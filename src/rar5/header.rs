@@ -0,0 +1,315 @@
+use super::vint::{parse_vint, read_vint, MAX_VINT_SIZE};
+use crate::block::BufferCursor;
+use crate::crypt::{CbcDecryptor, Rar5Key, AES_BLOCK_SIZE};
+use crate::error::{Error, Result};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::{AsyncRead, AsyncReadExt};
+use num::FromPrimitive;
+
+// RAR refuses headers larger than this.
+pub const MAX_HEADER_SIZE: u64 = 0x20_0000;
+
+#[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
+pub enum HeaderType {
+    Main = 1,
+    File = 2,
+    Service = 3,
+    Encryption = 4,
+    EndOfArchive = 5,
+}
+
+impl HeaderType {
+    pub fn from_u64(that: u64) -> Option<HeaderType> {
+        FromPrimitive::from_u64(that)
+    }
+}
+
+// Record types found in the extra area of file and service headers.
+#[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
+pub enum FileExtraType {
+    Encryption = 0x01,
+    Hash = 0x02,
+    Time = 0x03,
+    Version = 0x04,
+    Redirection = 0x05,
+    UnixOwner = 0x06,
+    ServiceData = 0x07,
+}
+
+impl FileExtraType {
+    pub fn from_u64(that: u64) -> Option<FileExtraType> {
+        FromPrimitive::from_u64(that)
+    }
+}
+
+bitflags! {
+    pub struct HeaderFlags: u64 {
+        const EXTRA_AREA = 0x0001;
+        const DATA_AREA = 0x0002;
+        const SKIP_IF_UNKNOWN = 0x0004;
+        const SPLIT_BEFORE = 0x0008;
+        const SPLIT_AFTER = 0x0010;
+        const CHILD = 0x0020;
+        const INHERITED = 0x0040;
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExtraRecord<'a> {
+    pub record_type: u64,
+    pub data: &'a [u8],
+}
+
+// A RAR5 header with the fields common to every header type decoded. The
+// type-specific fields and extra area are left for the per-type parsers.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub crc32: u32,
+    pub raw_type: u64,
+    pub header_type: Option<HeaderType>,
+    pub flags: HeaderFlags,
+    pub extra_size: u64,
+    pub data_size: u64,
+
+    // Everything after the header size field.
+    body: Vec<u8>,
+    fields_start: usize,
+}
+
+impl Header {
+    pub fn parse(crc32: u32, body: Vec<u8>) -> Result<Header> {
+        let (raw_type, flags, extra_size, data_size, fields_start) = {
+            let mut cursor = BufferCursor::new(&body);
+            let raw_type = read_vint(&mut cursor)?;
+            let flags = HeaderFlags::from_bits_truncate(read_vint(&mut cursor)?);
+            let extra_size = if flags.contains(HeaderFlags::EXTRA_AREA) {
+                read_vint(&mut cursor)?
+            } else {
+                0
+            };
+            let data_size = if flags.contains(HeaderFlags::DATA_AREA) {
+                read_vint(&mut cursor)?
+            } else {
+                0
+            };
+            if extra_size > cursor.remaining() as u64 {
+                return Err(Error::bad_block("Extra area larger than header".into()));
+            }
+            (
+                raw_type,
+                flags,
+                extra_size,
+                data_size,
+                body.len() - cursor.remaining(),
+            )
+        };
+
+        Ok(Header {
+            crc32,
+            raw_type,
+            header_type: HeaderType::from_u64(raw_type),
+            flags,
+            extra_size,
+            data_size,
+            body,
+            fields_start,
+        })
+    }
+
+    // The type-specific fields between the common fields and the extra area.
+    pub fn fields(&self) -> &[u8] {
+        &self.body[self.fields_start..self.body.len() - self.extra_size as usize]
+    }
+
+    pub fn extra(&self) -> &[u8] {
+        &self.body[self.body.len() - self.extra_size as usize..]
+    }
+
+    pub fn extra_records(&self) -> Result<Vec<ExtraRecord<'_>>> {
+        let mut records = Vec::new();
+        let mut cursor = BufferCursor::new(self.extra());
+        while cursor.remaining() > 0 {
+            let size = read_vint(&mut cursor)? as usize;
+            if size > cursor.remaining() {
+                return Err(Error::bad_block("Extra record overruns header".into()));
+            }
+            let record = cursor.read(size)?;
+            let mut record_cursor = BufferCursor::new(record);
+            let record_type = read_vint(&mut record_cursor)?;
+            records.push(ExtraRecord {
+                record_type,
+                data: record_cursor.rest(),
+            });
+        }
+        Ok(records)
+    }
+
+    pub fn is_split_before(&self) -> bool {
+        self.flags.contains(HeaderFlags::SPLIT_BEFORE)
+    }
+
+    pub fn is_split_after(&self) -> bool {
+        self.flags.contains(HeaderFlags::SPLIT_AFTER)
+    }
+}
+
+fn check_header_size(size: u64) -> Result<usize> {
+    if size == 0 || size > MAX_HEADER_SIZE {
+        return Err(Error::bad_block(format!("Bad header size {}", size)));
+    }
+    Ok(size as usize)
+}
+
+// Read one header. Archives with encrypted headers store each one behind its
+// own IV, and need the key derived from the archive encryption header.
+pub async fn read_header<T: AsyncRead + Unpin>(f: &mut T, key: Option<&Rar5Key>) -> Result<Header> {
+    match key {
+        None => read_plain_header(f).await,
+        Some(key) => read_encrypted_header(f, key).await,
+    }
+}
+
+async fn read_plain_header<T: AsyncRead + Unpin>(f: &mut T) -> Result<Header> {
+    let mut crc_buf = [0u8; 4];
+    f.read_exact(&mut crc_buf).await?;
+    let expected_crc = LittleEndian::read_u32(&crc_buf);
+
+    let mut raw = Vec::with_capacity(MAX_VINT_SIZE);
+    let size = loop {
+        let mut b = [0u8; 1];
+        f.read_exact(&mut b).await?;
+        raw.push(b[0]);
+        if let Some((size, _)) = parse_vint(&raw) {
+            break check_header_size(size)?;
+        }
+        if raw.len() >= MAX_VINT_SIZE {
+            return Err(Error::bad_block("Header size too long".into()));
+        }
+    };
+    let size_len = raw.len();
+
+    raw.resize(size_len + size, 0);
+    f.read_exact(&mut raw[size_len..]).await?;
+    if crc::crc32::checksum_ieee(&raw) != expected_crc {
+        return Err(Error::bad_block("Header CRC mismatch".into()));
+    }
+    Header::parse(expected_crc, raw.split_off(size_len))
+}
+
+async fn read_encrypted_header<T: AsyncRead + Unpin>(f: &mut T, key: &Rar5Key) -> Result<Header> {
+    let mut iv = [0u8; AES_BLOCK_SIZE];
+    f.read_exact(&mut iv).await?;
+    let mut decryptor = CbcDecryptor::new(key.key(), &iv);
+
+    let mut buf = vec![0u8; AES_BLOCK_SIZE];
+    f.read_exact(&mut buf).await?;
+    decryptor.decrypt(&mut buf);
+
+    let expected_crc = LittleEndian::read_u32(&buf[..4]);
+    // A wrong key turns the size into garbage, which is the first place a
+    // bad password shows when the archive has no check value.
+    let (size, size_len) = parse_vint(&buf[4..]).ok_or_else(Error::wrong_password)?;
+    let size = check_header_size(size).map_err(|_| Error::wrong_password())?;
+
+    let total = 4 + size_len + size;
+    let padded = total.div_ceil(AES_BLOCK_SIZE) * AES_BLOCK_SIZE;
+    buf.resize(padded, 0);
+    f.read_exact(&mut buf[AES_BLOCK_SIZE..]).await?;
+    decryptor.decrypt(&mut buf[AES_BLOCK_SIZE..]);
+
+    if crc::crc32::checksum_ieee(&buf[4..total]) != expected_crc {
+        return Err(Error::wrong_password());
+    }
+    buf.truncate(total);
+    Header::parse(expected_crc, buf.split_off(4 + size_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::testing::aes_cbc_encrypt;
+    use futures::executor::block_on;
+
+    // A file header with an extra area holding one record and a data area.
+    fn file_header_bytes() -> Vec<u8> {
+        let body = vec![
+            0x02, // type
+            0x03, // flags: extra and data area
+            0x04, // extra size
+            0x0a, // data size
+            0xaa, 0xbb, // type-specific fields
+            0x03, 0x02, 0x01, 0x02, // record: size 3, type 2, data [1, 2]
+        ];
+        let mut raw = vec![body.len() as u8];
+        raw.extend(&body);
+        let mut out = vec![0u8; 4];
+        LittleEndian::write_u32(&mut out, crc::crc32::checksum_ieee(&raw));
+        out.extend(raw);
+        out
+    }
+
+    #[test]
+    fn test_read_plain_header() {
+        let buf = file_header_bytes();
+        let header = block_on(read_header(&mut &buf[..], None)).unwrap();
+        assert_eq!(header.header_type, Some(HeaderType::File));
+        assert_eq!(header.data_size, 10);
+        assert_eq!(header.extra_size, 4);
+        assert_eq!(header.fields(), &[0xaa, 0xbb]);
+        assert_eq!(
+            header.extra_records().unwrap(),
+            vec![ExtraRecord {
+                record_type: 2,
+                data: &[1, 2]
+            }]
+        );
+    }
+
+    #[test]
+    fn test_read_plain_header_checks_crc() {
+        let mut buf = file_header_bytes();
+        buf[6] ^= 1;
+        assert!(block_on(read_header(&mut &buf[..], None)).is_err());
+    }
+
+    #[test]
+    fn test_unknown_header_type_is_kept_raw() {
+        let raw = vec![0x02, 0x09, 0x04];
+        let mut buf = vec![0u8; 4];
+        LittleEndian::write_u32(&mut buf, crc::crc32::checksum_ieee(&raw));
+        buf.extend(raw);
+        let header = block_on(read_header(&mut &buf[..], None)).unwrap();
+        assert_eq!(header.raw_type, 9);
+        assert_eq!(header.header_type, None);
+        assert!(header.flags.contains(HeaderFlags::SKIP_IF_UNKNOWN));
+    }
+
+    fn encrypt(key: &Rar5Key, iv: &[u8; 16], plain: &[u8]) -> Vec<u8> {
+        let mut out = iv.to_vec();
+        out.extend(aes_cbc_encrypt(key, iv, plain));
+        out
+    }
+
+    #[test]
+    fn test_read_encrypted_header() {
+        let key = Rar5Key::derive("roar", b"0123456789abcdef", 0).unwrap();
+        let buf = encrypt(&key, &[7; 16], &file_header_bytes());
+        assert_eq!(buf.len(), 16 + 16);
+        let header = block_on(read_header(&mut &buf[..], Some(&key))).unwrap();
+        assert_eq!(header.header_type, Some(HeaderType::File));
+        assert_eq!(header.fields(), &[0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn test_encrypted_header_with_wrong_key_errors() {
+        let key = Rar5Key::derive("roar", b"0123456789abcdef", 0).unwrap();
+        let other = Rar5Key::derive("meow", b"0123456789abcdef", 0).unwrap();
+        let mut buf = encrypt(&key, &[7; 16], &file_header_bytes());
+        // Enough trailing data that a garbage size can't hit end of file.
+        buf.resize(MAX_HEADER_SIZE as usize + 64, 0);
+        let err = block_on(read_header(&mut &buf[..], Some(&other))).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::WrongPassword);
+    }
+}
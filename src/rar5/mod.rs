@@ -0,0 +1,18 @@
+mod archive;
+mod encryption;
+mod entry;
+mod file;
+mod header;
+mod recovery;
+mod vint;
+
+pub use archive::{
+    parse_quick_open, ArchiveFlags, EndFlags, EndOfArchive, Locator, MainHeader, Rar5Entry,
+    Rar5Listing, QUICK_OPEN_SERVICE,
+};
+pub use encryption::{ArchiveEncryption, EncryptionFlags, FileEncryption};
+pub use entry::{Rar5EntryReader, Rar5Unpacking};
+pub use file::{FileFlags, FileHeader, HostOs};
+pub use recovery::{repair_rar5, Rar5RecoveryRecord, RECOVERY_SERVICE};
+pub use header::{read_header, ExtraRecord, FileExtraType, Header, HeaderFlags, HeaderType};
+pub use vint::{parse_vint, read_vint, write_vint};
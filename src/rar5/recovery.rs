@@ -0,0 +1,426 @@
+// Recovery records of RAR5 archives, kept in the data of an "RR" service
+// header which the locator of the main header points to. Everything from
+// the signature up to that header is protected. It's split into
+// `data_count` sectors of `sector_size` bytes, the last one zero padded, and
+// the record holds `rec_count` recovery sectors coded from them with the
+// GF(2^16) Reed-Solomon code of `Rs16Coder`. Any `rec_count` damaged sectors
+// can be rebuilt, wherever they are.
+//
+// RAR doesn't document the layout of the record data. The one read here is,
+// all little endian: the CRC32 of the rest of the header, its size as a
+// u32, then a version byte (1), the u16 data and recovery sector counts, the
+// u32 sector size, and the CRC32 of every data sector and then of every
+// recovery sector, which tell the damaged ones apart. The recovery sectors
+// follow the header.
+
+use super::file::FileHeader;
+use super::header::{read_header, HeaderType};
+use crate::archive::Archive;
+use crate::block::read_data;
+use crate::error::{Error, Result};
+use crate::recovery::{read_sector, RepairReport};
+use crate::recvol::Rs16Coder;
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::{AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use std::io::SeekFrom;
+
+// Service name of the recovery record.
+pub const RECOVERY_SERVICE: &str = "RR";
+
+// Fixed part of the record header after its CRC32 and size.
+const RECORD_FIXED_SIZE: usize = 9;
+
+// How many bytes of all the data sectors together to hold in memory while
+// rebuilding.
+const REBUILD_WINDOW: usize = 0x10_0000;
+
+#[derive(Debug, Clone)]
+pub struct Rar5RecoveryRecord {
+    sector_size: usize,
+    data_crcs: Vec<u32>,
+    rec_crcs: Vec<u32>,
+    recovery: Vec<u8>,
+}
+
+impl Rar5RecoveryRecord {
+    // Parse the data of the recovery record service header.
+    pub fn parse(data: &[u8]) -> Result<Rar5RecoveryRecord> {
+        let truncated = || Error::bad_block("Recovery record is truncated".into());
+        if data.len() < 8 {
+            return Err(truncated());
+        }
+        let expected_crc = LittleEndian::read_u32(&data[..4]);
+        let size = LittleEndian::read_u32(&data[4..8]) as usize;
+        let header = data
+            .get(8..)
+            .and_then(|rest| rest.get(..size))
+            .filter(|h| h.len() >= RECORD_FIXED_SIZE)
+            .ok_or_else(truncated)?;
+        if crc::crc32::checksum_ieee(header) != expected_crc {
+            return Err(Error::bad_block(
+                "Recovery record header CRC mismatch".into(),
+            ));
+        }
+        if header[0] != 1 {
+            return Err(Error::unsupported(format!(
+                "RAR5 recovery record version {}",
+                header[0]
+            )));
+        }
+        let data_count = usize::from(LittleEndian::read_u16(&header[1..3]));
+        let rec_count = usize::from(LittleEndian::read_u16(&header[3..5]));
+        let sector_size = LittleEndian::read_u32(&header[5..9]) as usize;
+        // Sectors are coded in 16 bit words.
+        if data_count == 0 || rec_count == 0 || sector_size == 0 || !sector_size.is_multiple_of(2) {
+            return Err(Error::bad_block("Invalid recovery record geometry".into()));
+        }
+        let crcs = &header[RECORD_FIXED_SIZE..];
+        if crcs.len() != (data_count + rec_count) * 4 {
+            return Err(Error::bad_block(
+                "Invalid recovery record header size".into(),
+            ));
+        }
+        let mut all_crcs = vec![0u32; data_count + rec_count];
+        LittleEndian::read_u32_into(crcs, &mut all_crcs);
+        let rec_crcs = all_crcs.split_off(data_count);
+
+        let recovery = rec_count
+            .checked_mul(sector_size)
+            .and_then(|n| data.get(8 + size..)?.get(..n))
+            .ok_or_else(truncated)?;
+        Ok(Rar5RecoveryRecord {
+            sector_size,
+            data_crcs: all_crcs,
+            rec_crcs,
+            recovery: recovery.to_vec(),
+        })
+    }
+
+    pub fn data_count(&self) -> usize {
+        self.data_crcs.len()
+    }
+
+    pub fn rec_count(&self) -> usize {
+        self.rec_crcs.len()
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn recovery_sector(&self, index: usize) -> &[u8] {
+        &self.recovery[index * self.sector_size..(index + 1) * self.sector_size]
+    }
+
+    // Size of data sector `index` of a protected area of `protected_size`
+    // bytes.
+    fn sector_len(&self, protected_size: u64, index: usize) -> usize {
+        let start = index as u64 * self.sector_size as u64;
+        protected_size
+            .saturating_sub(start)
+            .min(self.sector_size as u64) as usize
+    }
+}
+
+// Rebuild the data sectors `damaged` from the intact ones, reading the
+// protected area a slice of every sector at a time. `intact` has a flag for
+// every data sector and then for every recovery sector.
+async fn rebuild_sectors<R: AsyncFile + AsyncSeek>(
+    record: &Rar5RecoveryRecord,
+    intact: &[bool],
+    damaged: &[usize],
+    start: u64,
+    protected_size: u64,
+    archive: &mut R,
+) -> Result<Vec<Vec<u8>>> {
+    let data_count = record.data_count();
+    let rec_count = record.rec_count();
+    let coder = Rs16Coder::new(data_count, rec_count)?;
+    let decoder = coder.decoder(intact)?;
+
+    let size = record.sector_size;
+    let window = (REBUILD_WINDOW / data_count).max(2).min(size) & !1;
+    let mut slices = vec![vec![0u8; window]; data_count];
+    let mut rebuilt = vec![vec![0u8; size]; damaged.len()];
+    let mut word = vec![0u16; data_count + rec_count];
+    let mut offset = 0;
+    while offset < size {
+        let len = window.min(size - offset);
+        for (i, slice) in slices.iter_mut().enumerate() {
+            if !intact[i] {
+                continue;
+            }
+            let available = record.sector_len(protected_size, i).saturating_sub(offset);
+            archive
+                .seek(SeekFrom::Start(start + (i * size) as u64 + offset as u64))
+                .await?;
+            read_sector(archive, &mut slice[..len], available.min(len)).await?;
+        }
+        for pos in (0..len).step_by(2) {
+            for (w, slice) in word.iter_mut().zip(slices.iter()) {
+                *w = LittleEndian::read_u16(&slice[pos..]);
+            }
+            for r in 0..rec_count {
+                let sector = record.recovery_sector(r);
+                word[data_count + r] = LittleEndian::read_u16(&sector[offset + pos..]);
+            }
+            decoder.decode(&mut word);
+            for (sector, &i) in rebuilt.iter_mut().zip(damaged) {
+                LittleEndian::write_u16(&mut sector[offset + pos..], word[i]);
+            }
+        }
+        offset += len;
+    }
+    Ok(rebuilt)
+}
+
+// Write a repaired copy of the whole of `archive` to `out`. The protected
+// data runs for `protected_size` bytes from `start` up to the recovery
+// record. A truncated archive is padded with zeros so that lost sectors can
+// be rebuilt. If there are more damaged sectors than intact recovery
+// sectors, none can be rebuilt and the copy is written as it is.
+pub async fn repair_rar5<R: AsyncFile + AsyncSeek, W: AsyncWrite + Unpin>(
+    record: &Rar5RecoveryRecord,
+    start: u64,
+    protected_size: u64,
+    archive: &mut R,
+    out: &mut W,
+) -> Result<RepairReport> {
+    let size = record.sector_size;
+    if protected_size > record.data_count() as u64 * size as u64 {
+        return Err(Error::bad_block(
+            "Recovery record doesn't cover the whole archive".into(),
+        ));
+    }
+    let mut sector = vec![0u8; size];
+    archive.seek(SeekFrom::Start(start)).await?;
+    let mut report = RepairReport::default();
+    let mut intact = Vec::with_capacity(record.data_count() + record.rec_count());
+    for i in 0..record.data_count() {
+        read_sector(archive, &mut sector, record.sector_len(protected_size, i)).await?;
+        intact.push(crc::crc32::checksum_ieee(&sector) == record.data_crcs[i]);
+        if !intact[i] {
+            report.damaged.push(i);
+        }
+    }
+    for r in 0..record.rec_count() {
+        let sector = record.recovery_sector(r);
+        intact.push(crc::crc32::checksum_ieee(sector) == record.rec_crcs[r]);
+    }
+
+    let usable = intact[record.data_count()..]
+        .iter()
+        .filter(|&&ok| ok)
+        .count();
+    let mut rebuilt = Vec::new();
+    if report.damaged.len() > usable {
+        report.unrecoverable = report.damaged.clone();
+    } else if !report.damaged.is_empty() {
+        rebuilt = rebuild_sectors(
+            record,
+            &intact,
+            &report.damaged,
+            start,
+            protected_size,
+            archive,
+        )
+        .await?;
+        report.repaired = report.damaged.clone();
+    }
+
+    archive.seek(SeekFrom::Start(0)).await?;
+    let mut stub = start;
+    while stub > 0 {
+        let want = stub.min(size as u64) as usize;
+        archive.read_exact(&mut sector[..want]).await?;
+        out.write_all(&sector[..want]).await?;
+        stub -= want as u64;
+    }
+    for i in 0..record.data_count() {
+        let len = record.sector_len(protected_size, i);
+        read_sector(archive, &mut sector, len).await?;
+        let data = match report.repaired.binary_search(&i) {
+            Ok(k) => &rebuilt[k],
+            Err(_) => &sector,
+        };
+        out.write_all(&data[..len]).await?;
+    }
+    archive.copy_into(out).await?;
+    out.flush().await?;
+    Ok(report)
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // The recovery record of a RAR5 archive, opened right after the
+    // signature, and the offset of its service header, where the protected
+    // data ends. The locator of the main header is followed if there is
+    // one, otherwise the headers are walked.
+    pub async fn find_rar5_recovery_record(&mut self) -> Result<(u64, Rar5RecoveryRecord)> {
+        let main = self.read_rar5_main_header(None).await?;
+        if let Some(offset) = main.recovery_offset() {
+            self.skip_to(offset).await?;
+        }
+        while !self.at_eof().await? {
+            let offset = self.position();
+            let header = read_header(self, None).await?;
+            let data_size = header.data_size;
+            match header.header_type {
+                Some(HeaderType::Service) => {
+                    let service = FileHeader::parse(header)?;
+                    if service.name == RECOVERY_SERVICE {
+                        if service.is_encrypted() || service.compression()?.method != 0 {
+                            return Err(Error::unsupported("Packed recovery records".into()));
+                        }
+                        let data = read_data(self, data_size).await?;
+                        return Ok((offset, Rar5RecoveryRecord::parse(&data)?));
+                    }
+                }
+                Some(HeaderType::EndOfArchive) => break,
+                _ => {}
+            }
+            self.skip_to(self.position() + data_size).await?;
+        }
+        Err(Error::bad_data("Archive has no recovery record".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR50_SIGNATURE;
+    use crate::testing::{rar5_header, rar5_stored_header};
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    // Build the recovery record data for `protected`.
+    fn record_data(protected: &[u8], sector_size: usize, rec_count: usize) -> Vec<u8> {
+        let sectors: Vec<Vec<u8>> = protected
+            .chunks(sector_size)
+            .map(|chunk| {
+                let mut sector = chunk.to_vec();
+                sector.resize(sector_size, 0);
+                sector
+            })
+            .collect();
+        let coder = Rs16Coder::new(sectors.len(), rec_count).unwrap();
+        let mut recovery = vec![vec![0u8; sector_size]; rec_count];
+        let mut parity = vec![0u16; rec_count];
+        for pos in (0..sector_size).step_by(2) {
+            let word: Vec<u16> = sectors
+                .iter()
+                .map(|s| LittleEndian::read_u16(&s[pos..]))
+                .collect();
+            coder.encode(&word, &mut parity);
+            for (sector, &p) in recovery.iter_mut().zip(parity.iter()) {
+                LittleEndian::write_u16(&mut sector[pos..], p);
+            }
+        }
+
+        let mut header = vec![1];
+        header.extend(&(sectors.len() as u16).to_le_bytes());
+        header.extend(&(rec_count as u16).to_le_bytes());
+        header.extend(&(sector_size as u32).to_le_bytes());
+        for sector in sectors.iter().chain(recovery.iter()) {
+            header.extend(&crc::crc32::checksum_ieee(sector).to_le_bytes());
+        }
+        let mut data = crc::crc32::checksum_ieee(&header).to_le_bytes().to_vec();
+        data.extend(&(header.len() as u32).to_le_bytes());
+        data.extend(header);
+        for sector in recovery {
+            data.extend(sector);
+        }
+        data
+    }
+
+    fn protected() -> Vec<u8> {
+        (0..1000).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn repair_copy(
+        damaged: Vec<u8>,
+        record: &[u8],
+        protected_size: u64,
+    ) -> (RepairReport, Vec<u8>) {
+        let record = Rar5RecoveryRecord::parse(record).unwrap();
+        let mut out = Vec::new();
+        let mut archive = Cursor::new(damaged);
+        let report = block_on(repair_rar5(
+            &record,
+            0,
+            protected_size,
+            &mut archive,
+            &mut out,
+        ))
+        .unwrap();
+        (report, out)
+    }
+
+    #[test]
+    fn test_parse_record() {
+        let data = record_data(&protected(), 64, 3);
+        let record = Rar5RecoveryRecord::parse(&data).unwrap();
+        assert_eq!(record.data_count(), 16);
+        assert_eq!(record.rec_count(), 3);
+        assert_eq!(record.sector_size(), 64);
+        assert!(Rar5RecoveryRecord::parse(&data[..data.len() - 1]).is_err());
+        let mut damaged = data;
+        damaged[10] ^= 1;
+        assert!(Rar5RecoveryRecord::parse(&damaged).is_err());
+    }
+
+    #[test]
+    fn test_repairs_scattered_damage() {
+        let original = protected();
+        let record = record_data(&original, 64, 3);
+        let mut damaged = original.clone();
+        damaged[5] ^= 0xff;
+        damaged[64 * 7 + 1] ^= 0xff;
+        // Cut off in the middle of the last sector.
+        damaged.truncate(990);
+        let (report, out) = repair_copy(damaged, &record, 1000);
+        assert_eq!(report.damaged, vec![0, 7, 15]);
+        assert!(report.is_complete());
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_reports_too_much_damage() {
+        let original = protected();
+        let record = record_data(&original, 64, 2);
+        let mut damaged = original.clone();
+        for &i in &[0, 100, 200] {
+            damaged[i] ^= 1;
+        }
+        let (report, out) = repair_copy(damaged.clone(), &record, 1000);
+        assert_eq!(report.unrecoverable, vec![0, 1, 3]);
+        assert!(report.repaired.is_empty());
+        assert_eq!(out, damaged);
+    }
+
+    #[test]
+    fn test_archive_repair_follows_locator() {
+        let file = rar5_stored_header(2, "a.txt", &protected());
+        // The offset always takes two bytes to keep the size fixed.
+        let main_len = rar5_header(&[0; 9]).len();
+        let offset = main_len + file.len();
+        let locator = [0x04, 0x01, 0x02, offset as u8 | 0x80, (offset >> 7) as u8];
+        let mut body = vec![0x01, 0x01, locator.len() as u8, 0x00];
+        body.extend(&locator);
+
+        let mut archive = RAR50_SIGNATURE.to_vec();
+        archive.extend(rar5_header(&body));
+        archive.extend(file);
+        let record = record_data(&archive, 128, 2);
+        archive.extend(rar5_stored_header(3, RECOVERY_SERVICE, &record));
+        archive.extend(rar5_header(&[0x05, 0x00, 0x00]));
+
+        let mut damaged = archive.clone();
+        damaged[RAR50_SIGNATURE.len() + main_len + 200] ^= 0xff;
+        let mut opened = block_on(Archive::open(Cursor::new(damaged))).unwrap();
+        let mut out = Vec::new();
+        let report = block_on(opened.repair(&mut out)).unwrap();
+        assert_eq!(report.repaired.len(), 1);
+        assert_eq!(out, archive);
+    }
+}
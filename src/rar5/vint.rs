@@ -0,0 +1,78 @@
+use crate::block::BufferCursor;
+use crate::error::{Error, Result};
+
+// RAR5 stores most integers as little-endian groups of 7 bits, with the high
+// bit of each byte set when another byte follows.
+pub const MAX_VINT_SIZE: usize = 10;
+
+// Decode a vint from the start of `buf`, returning it and its encoded size,
+// or `None` if `buf` ends before the vint does.
+pub fn parse_vint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value: u64 = 0;
+    for (i, &b) in buf.iter().take(MAX_VINT_SIZE).enumerate() {
+        value |= u64::from(b & 0x7f) << (i * 7);
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+pub fn read_vint(cursor: &mut BufferCursor) -> Result<u64> {
+    for len in 1..=MAX_VINT_SIZE {
+        let peek = cursor.peek(len)?;
+        if peek[len - 1] & 0x80 == 0 {
+            let (value, _) = parse_vint(cursor.read(len)?).expect("vint terminated");
+            return Ok(value);
+        }
+    }
+    Err(Error::bad_block("Variable length integer too long".into()))
+}
+
+// Append `value` to `out` as a vint.
+pub fn write_vint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_byte_vint() {
+        assert_eq!(parse_vint(&[0x05, 0xff]), Some((5, 1)));
+    }
+
+    #[test]
+    fn test_parse_multi_byte_vint() {
+        assert_eq!(parse_vint(&[0x81, 0x01]), Some((0x81, 2)));
+        assert_eq!(parse_vint(&[0xff, 0xff, 0x7f]), Some((0x1f_ffff, 3)));
+    }
+
+    #[test]
+    fn test_parse_unterminated_vint() {
+        assert_eq!(parse_vint(&[0x80, 0x80]), None);
+    }
+
+    #[test]
+    fn test_write_vint_round_trips() {
+        for &value in &[0, 0x7f, 0x80, 0x1f_ffff, u64::MAX] {
+            let mut buf = Vec::new();
+            write_vint(value, &mut buf);
+            assert_eq!(parse_vint(&buf), Some((value, buf.len())));
+        }
+    }
+
+    #[test]
+    fn test_read_vint_advances_cursor() {
+        let buf = [0x81, 0x01, 0x02];
+        let mut cursor = BufferCursor::new(&buf);
+        assert_eq!(read_vint(&mut cursor).unwrap(), 0x81);
+        assert_eq!(read_vint(&mut cursor).unwrap(), 2);
+        assert!(read_vint(&mut cursor).is_err());
+    }
+}
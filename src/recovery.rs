@@ -0,0 +1,476 @@
+// Recovery records, which let damaged archives be repaired. RAR5 ones use a
+// Reed-Solomon code and are read in `rar5::recovery`. Those of RAR 2.x to
+// 4.x, in the old style protect block and in RR sub-blocks, split the
+// protected part of the archive into 512 byte sectors, each with a 16 bit
+// tag to spot damage, and XOR every sector into parity sector
+// `index % recovery_sectors`. A damaged sector can be rebuilt as long as no
+// other sector sharing its parity is damaged too, so any run of up to
+// `recovery_sectors` consecutive bad sectors can be repaired.
+
+use crate::archive::{Archive, Format};
+use crate::block::{self, Block};
+use crate::error::{Error, Result};
+use crate::rar5::repair_rar5;
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+use futures::io::{AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use std::io::SeekFrom;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// Marks the old style protect block and the data of RR sub-blocks.
+pub const PROTECT_MARK: [u8; 8] = *b"Protect!";
+pub const RR_SUB_DATA_MARK: [u8; 8] = *b"Protect+";
+
+// Name of the service sub-block holding the recovery record in RAR 3.x.
+pub const RECOVERY_SERVICE: &[u8] = b"RR";
+
+// Tag stored for each protected sector: the low half of its CRC32, with a
+// short last sector padded with zeros.
+pub fn sector_tag(sector: &[u8]) -> u16 {
+    crc::crc32::checksum_ieee(sector) as u16
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoveryRecord {
+    recovery_sectors: usize,
+    tags: Vec<u16>,
+    parity: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RepairReport {
+    // Sector indices whose tags didn't match.
+    pub damaged: Vec<usize>,
+    // Damaged sectors that were rebuilt.
+    pub repaired: Vec<usize>,
+    // Damaged sectors sharing their parity with another damaged sector.
+    pub unrecoverable: Vec<usize>,
+}
+
+impl RepairReport {
+    pub fn is_complete(&self) -> bool {
+        self.unrecoverable.is_empty()
+    }
+}
+
+impl RecoveryRecord {
+    // Parse the data area of a recovery record: a tag per protected sector
+    // followed by the parity sectors.
+    pub fn parse(recovery_sectors: u32, total_sectors: u64, data: &[u8]) -> Result<RecoveryRecord> {
+        let recovery_sectors = recovery_sectors as usize;
+        if recovery_sectors == 0 {
+            return Err(Error::bad_block("Recovery record has no sectors".into()));
+        }
+        let tags_size = total_sectors
+            .checked_mul(2)
+            .filter(|&n| n <= data.len() as u64)
+            .ok_or_else(|| Error::bad_block("Recovery record sector count is too large".into()))?
+            as usize;
+        let parity_size = recovery_sectors
+            .checked_mul(SECTOR_SIZE)
+            .filter(|&n| tags_size + n <= data.len())
+            .ok_or_else(|| Error::bad_block("Recovery record is truncated".into()))?;
+
+        let mut tags = vec![0u16; tags_size / 2];
+        LittleEndian::read_u16_into(&data[..tags_size], &mut tags);
+        Ok(RecoveryRecord {
+            recovery_sectors,
+            tags,
+            parity: data[tags_size..tags_size + parity_size].to_vec(),
+        })
+    }
+
+    // RR sub-blocks keep the sector counts in their sub data: the mark, the
+    // number of recovery sectors and the number of protected sectors.
+    pub fn parse_sub_data(sub_data: &[u8]) -> Result<(u32, u64)> {
+        if sub_data.len() < 20 || sub_data[..8] != RR_SUB_DATA_MARK {
+            return Err(Error::bad_block("Invalid recovery record sub data".into()));
+        }
+        Ok((
+            LittleEndian::read_u32(&sub_data[8..12]),
+            LittleEndian::read_u64(&sub_data[12..20]),
+        ))
+    }
+
+    pub fn recovery_sectors(&self) -> usize {
+        self.recovery_sectors
+    }
+
+    pub fn total_sectors(&self) -> usize {
+        self.tags.len()
+    }
+
+    fn sector<'a>(&self, archive: &'a [u8], index: usize) -> &'a [u8] {
+        let start = (index * SECTOR_SIZE).min(archive.len());
+        let end = (start + SECTOR_SIZE).min(archive.len());
+        &archive[start..end]
+    }
+
+    fn padded_sector(&self, archive: &[u8], index: usize) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let data = self.sector(archive, index);
+        sector[..data.len()].copy_from_slice(data);
+        sector
+    }
+
+    pub fn damaged_sectors(&self, archive: &[u8]) -> Vec<usize> {
+        (0..self.tags.len())
+            .filter(|&i| sector_tag(&self.padded_sector(archive, i)) != self.tags[i])
+            .collect()
+    }
+
+    // Rebuild what can be rebuilt of `archive`, the protected part of the
+    // archive up to the recovery record, in place.
+    pub fn repair(&self, archive: &mut [u8]) -> RepairReport {
+        let mut check = ParityCheck::new(self);
+        for i in 0..self.tags.len() {
+            check.add(i, &self.padded_sector(archive, i));
+        }
+        let rebuilt = check.finish();
+        for &i in &rebuilt.report.repaired {
+            let start = (i * SECTOR_SIZE).min(archive.len());
+            let end = (start + SECTOR_SIZE).min(archive.len());
+            archive[start..end].copy_from_slice(&rebuilt.sector(i).unwrap()[..end - start]);
+        }
+        rebuilt.report
+    }
+}
+
+// Checks the protected sectors against their tags as they're fed in, and
+// XORs the intact ones into a copy of the parity. A group left with a single
+// damaged sector then holds that sector's original contents, so only the
+// parity has to be kept in memory.
+struct ParityCheck<'a> {
+    record: &'a RecoveryRecord,
+    sums: Vec<u8>,
+    damaged: Vec<usize>,
+}
+
+struct Rebuilt {
+    report: RepairReport,
+    sums: Vec<u8>,
+    recovery_sectors: usize,
+}
+
+impl<'a> ParityCheck<'a> {
+    fn new(record: &'a RecoveryRecord) -> ParityCheck<'a> {
+        ParityCheck {
+            record,
+            sums: record.parity.clone(),
+            damaged: Vec::new(),
+        }
+    }
+
+    // Sectors must be added in order, short ones padded with zeros.
+    fn add(&mut self, index: usize, sector: &[u8; SECTOR_SIZE]) {
+        if sector_tag(sector) != self.record.tags[index] {
+            self.damaged.push(index);
+            return;
+        }
+        let group = index % self.record.recovery_sectors;
+        let sum = &mut self.sums[group * SECTOR_SIZE..(group + 1) * SECTOR_SIZE];
+        for (s, &x) in sum.iter_mut().zip(sector.iter()) {
+            *s ^= x;
+        }
+    }
+
+    fn finish(self) -> Rebuilt {
+        let recovery_sectors = self.record.recovery_sectors;
+        let mut damaged_in_group = vec![0usize; recovery_sectors];
+        for &i in &self.damaged {
+            damaged_in_group[i % recovery_sectors] += 1;
+        }
+        let (repaired, unrecoverable) = self
+            .damaged
+            .iter()
+            .cloned()
+            .partition(|&i| damaged_in_group[i % recovery_sectors] == 1);
+        Rebuilt {
+            report: RepairReport {
+                damaged: self.damaged,
+                repaired,
+                unrecoverable,
+            },
+            sums: self.sums,
+            recovery_sectors,
+        }
+    }
+}
+
+impl Rebuilt {
+    // The original contents of a repaired sector, padded to the full size.
+    fn sector(&self, index: usize) -> Option<&[u8]> {
+        self.report.repaired.binary_search(&index).ok()?;
+        let group = index % self.recovery_sectors;
+        Some(&self.sums[group * SECTOR_SIZE..(group + 1) * SECTOR_SIZE])
+    }
+}
+
+// Read up to `len` bytes of a sector into `sector`, zeroing the rest, so
+// that short sectors are padded and sectors lost to truncation can still be
+// rebuilt.
+pub(crate) async fn read_sector<R: AsyncFile>(
+    archive: &mut R,
+    sector: &mut [u8],
+    len: usize,
+) -> Result<()> {
+    let mut filled = 0;
+    while filled < len {
+        let n = archive.read(&mut sector[filled..len]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    for b in &mut sector[filled..] {
+        *b = 0;
+    }
+    Ok(())
+}
+
+// Size of sector `index` of a protected area of `protected_size` bytes.
+fn sector_len(protected_size: u64, index: usize) -> usize {
+    let start = index as u64 * SECTOR_SIZE as u64;
+    protected_size.saturating_sub(start).min(SECTOR_SIZE as u64) as usize
+}
+
+// Write a repaired copy of the whole of `archive` to `out`. The protected
+// data runs for `protected_size` bytes from `start` up to the recovery
+// record. The archive is read twice, a sector at a time: once to find the
+// damage and rebuild it from the parity, then to write the copy. A truncated
+// archive is padded with zeros so that lost sectors can be rebuilt.
+pub async fn repair<R: AsyncFile + AsyncSeek, W: AsyncWrite + Unpin>(
+    record: &RecoveryRecord,
+    start: u64,
+    protected_size: u64,
+    archive: &mut R,
+    out: &mut W,
+) -> Result<RepairReport> {
+    if protected_size > record.total_sectors() as u64 * SECTOR_SIZE as u64 {
+        return Err(Error::bad_block(
+            "Recovery record doesn't cover the whole archive".into(),
+        ));
+    }
+    let mut sector = [0u8; SECTOR_SIZE];
+    archive.seek(SeekFrom::Start(start)).await?;
+    let mut check = ParityCheck::new(record);
+    for i in 0..record.total_sectors() {
+        read_sector(archive, &mut sector, sector_len(protected_size, i)).await?;
+        check.add(i, &sector);
+    }
+    let rebuilt = check.finish();
+
+    archive.seek(SeekFrom::Start(0)).await?;
+    let mut stub = start;
+    while stub > 0 {
+        let want = stub.min(SECTOR_SIZE as u64) as usize;
+        archive.read_exact(&mut sector[..want]).await?;
+        out.write_all(&sector[..want]).await?;
+        stub -= want as u64;
+    }
+    for i in 0..record.total_sectors() {
+        let len = sector_len(protected_size, i);
+        read_sector(archive, &mut sector, len).await?;
+        let data = rebuilt.sector(i).unwrap_or(&sector[..]);
+        out.write_all(&data[..len]).await?;
+    }
+    archive.copy_into(out).await?;
+    out.flush().await?;
+    Ok(rebuilt.report)
+}
+
+impl<T: AsyncFile + AsyncSeek> Archive<T> {
+    // Write a copy of the whole file to `out`, with every damaged sector the
+    // recovery record can rebuild repaired. The archive must be opened right
+    // after the signature. Only blocks are walked to find the record, so
+    // damage to block headers before it can't be repaired this way.
+    pub async fn repair<W: AsyncWrite + Unpin>(&mut self, out: &mut W) -> Result<RepairReport> {
+        let start = self.sfx_size();
+        match self.format() {
+            Format::Rar15 => {
+                let (offset, record) = self.find_recovery_record().await?;
+                repair(&record, start, offset - start, self.get_mut(), out).await
+            }
+            Format::Rar50 => {
+                let (offset, record) = self.find_rar5_recovery_record().await?;
+                repair_rar5(&record, start, offset - start, self.get_mut(), out).await
+            }
+            format => Err(Error::unsupported(format!(
+                "Recovery records of {:?} archives",
+                format
+            ))),
+        }
+    }
+
+    // The recovery record and the offset of its block, where the protected
+    // data ends.
+    async fn find_recovery_record(&mut self) -> Result<(u64, RecoveryRecord)> {
+        while !self.at_eof().await? {
+            let offset = self.position();
+            let data_size = match self.read_block().await? {
+                Block::Protect(ref protect) => {
+                    return Ok((offset, protect.read_record(self).await?));
+                }
+                Block::Service(ref service) if service.is_recovery_record() => {
+                    let header = service.header();
+                    let (recovery_sectors, total_sectors) =
+                        RecoveryRecord::parse_sub_data(header.sub_data())?;
+                    let data = block::read_data(self, header.packed_size()).await?;
+                    let record = RecoveryRecord::parse(recovery_sectors, total_sectors, &data)?;
+                    return Ok((offset, record));
+                }
+                Block::File(ref file) | Block::Service(ref file) => file.header().packed_size(),
+                Block::End(_) => break,
+                _ => 0,
+            };
+            self.skip_to(self.position() + data_size).await?;
+        }
+        Err(Error::bad_data("Archive has no recovery record".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{file_block, with_header_crc};
+    use futures::executor::block_on;
+    use std::io::Cursor;
+
+    // Build the recovery record data for `archive`.
+    fn record_data(archive: &[u8], recovery_sectors: usize) -> Vec<u8> {
+        let mut tags = Vec::new();
+        let mut parity = vec![0u8; recovery_sectors * SECTOR_SIZE];
+        for (i, chunk) in archive.chunks(SECTOR_SIZE).enumerate() {
+            let mut sector = [0u8; SECTOR_SIZE];
+            sector[..chunk.len()].copy_from_slice(chunk);
+            tags.extend(&sector_tag(&sector).to_le_bytes());
+            let group = i % recovery_sectors;
+            for (p, &x) in parity[group * SECTOR_SIZE..].iter_mut().zip(sector.iter()) {
+                *p ^= x;
+            }
+        }
+        tags.extend(parity);
+        tags
+    }
+
+    fn archive() -> Vec<u8> {
+        (0..SECTOR_SIZE * 7 + 100)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect()
+    }
+
+    fn record(archive: &[u8], recovery_sectors: usize) -> RecoveryRecord {
+        let total = archive.len().div_ceil(SECTOR_SIZE);
+        let data = record_data(archive, recovery_sectors);
+        RecoveryRecord::parse(recovery_sectors as u32, total as u64, &data).unwrap()
+    }
+
+    #[test]
+    fn test_intact_archive_has_no_damage() {
+        let original = archive();
+        let rr = record(&original, 2);
+        assert_eq!(rr.total_sectors(), 8);
+        let mut copy = original.clone();
+        assert_eq!(rr.repair(&mut copy), RepairReport::default());
+        assert_eq!(copy, original);
+    }
+
+    #[test]
+    fn test_repairs_consecutive_damage() {
+        let original = archive();
+        let rr = record(&original, 3);
+        let mut damaged = original.clone();
+        for b in damaged[SECTOR_SIZE * 2 + 10..SECTOR_SIZE * 5 - 10].iter_mut() {
+            *b = 0xff;
+        }
+        let report = rr.repair(&mut damaged);
+        assert_eq!(report.damaged, vec![2, 3, 4]);
+        assert!(report.is_complete());
+        assert_eq!(damaged, original);
+    }
+
+    #[test]
+    fn test_repairs_short_last_sector() {
+        let original = archive();
+        let rr = record(&original, 2);
+        let mut damaged = original.clone();
+        let len = damaged.len();
+        damaged[len - 1] ^= 1;
+        assert_eq!(rr.repair(&mut damaged).repaired, vec![7]);
+        assert_eq!(damaged, original);
+    }
+
+    #[test]
+    fn test_reports_sectors_sharing_parity() {
+        let original = archive();
+        let rr = record(&original, 2);
+        let mut damaged = original.clone();
+        damaged[0] ^= 1;
+        damaged[SECTOR_SIZE * 2] ^= 1;
+        damaged[SECTOR_SIZE * 3] ^= 1;
+        let report = rr.repair(&mut damaged);
+        assert_eq!(report.repaired, vec![3]);
+        assert_eq!(report.unrecoverable, vec![0, 2]);
+        assert!(!report.is_complete());
+    }
+
+    #[test]
+    fn test_repair_writes_fixed_copy_of_truncated_archive() {
+        let mut original = archive();
+        let rr = record(&original, 2);
+        original.extend(b"recovery record and beyond");
+
+        let mut damaged = original.clone();
+        damaged.truncate(SECTOR_SIZE * 7 + 20);
+        let mut out = Vec::new();
+        let protected_size = archive().len() as u64;
+        let mut damaged = Cursor::new(damaged);
+        let report = block_on(repair(&rr, 0, protected_size, &mut damaged, &mut out)).unwrap();
+        assert_eq!(report.repaired, vec![7]);
+        assert_eq!(out[..], original[..protected_size as usize]);
+    }
+
+    #[test]
+    fn test_archive_repair_finds_protect_block() {
+        let mut original = b"stub".to_vec();
+        original.extend(&crate::archive::RAR15_SIGNATURE);
+        original.extend(file_block(b"a.txt", &archive()));
+        let protected = original[4..].to_vec();
+        let record = record_data(&protected, 2);
+        let total = protected.len().div_ceil(SECTOR_SIZE);
+        let mut protect = vec![0, 0, 0x78, 0, 0x80, 26, 0];
+        protect.extend(&(record.len() as u32).to_le_bytes());
+        protect.push(1);
+        protect.extend(&2u16.to_le_bytes());
+        protect.extend(&(total as u32).to_le_bytes());
+        protect.extend(&PROTECT_MARK);
+        original.extend(with_header_crc(protect));
+        original.extend(record);
+
+        let mut damaged = original.clone();
+        damaged[SECTOR_SIZE + 100] ^= 0xff;
+        let mut archive = block_on(Archive::open(Cursor::new(damaged))).unwrap();
+        let mut out = Vec::new();
+        let report = block_on(archive.repair(&mut out)).unwrap();
+        assert_eq!(report.repaired, vec![1]);
+        assert_eq!(out, original);
+    }
+
+    #[test]
+    fn test_parse_rejects_short_data() {
+        let data = record_data(&archive(), 2);
+        assert!(RecoveryRecord::parse(2, 8, &data[..data.len() - 1]).is_err());
+        assert!(RecoveryRecord::parse(2, u64::MAX, &data).is_err());
+        assert!(RecoveryRecord::parse(0, 8, &data).is_err());
+    }
+
+    #[test]
+    fn test_parse_sub_data() {
+        let mut sub_data = RR_SUB_DATA_MARK.to_vec();
+        sub_data.extend(&[3, 0, 0, 0]);
+        sub_data.extend(&[8, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(RecoveryRecord::parse_sub_data(&sub_data).unwrap(), (3, 8));
+        assert!(RecoveryRecord::parse_sub_data(&sub_data[..12]).is_err());
+    }
+}
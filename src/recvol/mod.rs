@@ -0,0 +1,17 @@
+// Recovery volumes (.rev files), which can regenerate missing or damaged
+// volumes of a multi-volume set: RAR 3.x ones with a Reed-Solomon code over
+// GF(2^8), RAR5 ones with one over GF(2^16). `rebuild_volumes` finds the
+// .rev files of a set on disk and rebuilds what it can, and opening a
+// `VolumeSet` calls it when a volume is missing or unreadable.
+
+mod rev3;
+mod rev5;
+mod rs;
+mod rs16;
+mod set;
+
+pub use rev3::{rebuild_rev3, Rev3Volume, REV3_TRAILER_SIZE};
+pub use rev5::{rebuild_rev5, Rev5Header, RevVolumeInfo, REV5_SIGNATURE};
+pub use rs::RsCoder;
+pub use rs16::{Rs16Coder, Rs16Decoder};
+pub use set::{rebuild_volumes, RebuildReport};
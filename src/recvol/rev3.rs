@@ -0,0 +1,260 @@
+use super::rs::RsCoder;
+use crate::error::{Error, Result};
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+use crc::crc32::{self, Hasher32};
+use futures::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// RAR 3.x .rev files hold the Reed-Solomon parity of the data volumes, one
+// byte per byte offset, followed by this trailer: the number of data
+// volumes, the number of recovery volumes and the number of this recovery
+// volume (each stored less one), then the CRC32 of everything before it.
+pub const REV3_TRAILER_SIZE: usize = 7;
+
+// How many bytes of each volume to process at a time.
+const CHUNK_SIZE: usize = 0x10000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rev3Volume {
+    // 1 based number of this recovery volume.
+    pub number: usize,
+    pub rec_count: usize,
+    pub data_count: usize,
+    // Length of the parity data, which is the length of the largest data
+    // volume.
+    pub data_size: u64,
+}
+
+impl Rev3Volume {
+    // Read a whole .rev file, checking its CRC.
+    pub async fn read<T: AsyncFile>(mut f: T) -> Result<Rev3Volume> {
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        let mut tail = Vec::with_capacity(REV3_TRAILER_SIZE);
+        let mut size: u64 = 0;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let n = f.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            size += n as u64;
+            tail.extend_from_slice(&buf[..n]);
+            // Hold back what may turn out to be the trailer.
+            if tail.len() > REV3_TRAILER_SIZE {
+                let done = tail.len() - REV3_TRAILER_SIZE;
+                digest.write(&tail[..done]);
+                tail.drain(..done);
+            }
+        }
+        if tail.len() < REV3_TRAILER_SIZE {
+            return Err(Error::bad_block("Recovery volume is too short".into()));
+        }
+        // The CRC covers the volume counts but not itself.
+        digest.write(&tail[..3]);
+        if LittleEndian::read_u32(&tail[3..]) != digest.sum32() {
+            return Err(Error::bad_block("Recovery volume CRC mismatch".into()));
+        }
+
+        let volume = Rev3Volume {
+            data_count: usize::from(tail[0]) + 1,
+            rec_count: usize::from(tail[1]) + 1,
+            number: usize::from(tail[2]) + 1,
+            data_size: size - REV3_TRAILER_SIZE as u64,
+        };
+        if volume.data_count + volume.rec_count > 255 || volume.number > volume.rec_count {
+            return Err(Error::bad_block("Invalid recovery volume numbering".into()));
+        }
+        Ok(volume)
+    }
+}
+
+// Read as much of `buf` as `f` has, zero filling the rest.
+pub(super) async fn read_padded<T: AsyncFile>(f: &mut T, buf: &mut [u8]) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = f.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    for b in buf[filled..].iter_mut() {
+        *b = 0;
+    }
+    Ok(())
+}
+
+// Regenerate missing data volumes. `volumes` holds every data volume in
+// order, `None` where it's missing or damaged, and `revs` every recovery
+// volume by number, each positioned at its start. `data_size` is the
+// recovery data length from `Rev3Volume`. A writer is needed in `outputs`
+// for each missing data volume, in order. Rebuilt volumes come out
+// `data_size` long, zero padded past their end of archive block.
+pub async fn rebuild_rev3<R: AsyncFile, W: AsyncWrite + Unpin>(
+    volumes: &mut [Option<R>],
+    revs: &mut [Option<R>],
+    data_size: u64,
+    outputs: &mut [W],
+) -> Result<()> {
+    let data_count = volumes.len();
+    let total = data_count + revs.len();
+    let missing: Vec<usize> = (0..data_count).filter(|&i| volumes[i].is_none()).collect();
+    // Missing recovery volumes are erasures too.
+    let erasures: Vec<usize> = (0..total)
+        .filter(|&i| {
+            if i < data_count {
+                volumes[i].is_none()
+            } else {
+                revs[i - data_count].is_none()
+            }
+        })
+        .collect();
+    if outputs.len() != missing.len() {
+        return Err(Error::bad_data(format!(
+            "{} volumes are missing but {} outputs were given",
+            missing.len(),
+            outputs.len()
+        )));
+    }
+    if erasures.len() > revs.len() {
+        return Err(Error::bad_data(format!(
+            "{} volumes are missing but only {} recovery volumes are available",
+            missing.len(),
+            revs.iter().filter(|r| r.is_some()).count()
+        )));
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let coder = RsCoder::new(revs.len());
+    let mut bufs = vec![vec![0u8; CHUNK_SIZE]; total];
+    let mut word = vec![0u8; total];
+    let mut remaining = data_size;
+    while remaining > 0 {
+        let len = remaining.min(CHUNK_SIZE as u64) as usize;
+        for (i, buf) in bufs.iter_mut().enumerate() {
+            let source = if i < data_count {
+                volumes[i].as_mut()
+            } else {
+                revs[i - data_count].as_mut()
+            };
+            if let Some(f) = source {
+                read_padded(f, &mut buf[..len]).await?;
+            }
+        }
+
+        for pos in 0..len {
+            for (w, buf) in word.iter_mut().zip(bufs.iter()) {
+                *w = buf[pos];
+            }
+            coder.decode(&mut word, &erasures)?;
+            for &i in &missing {
+                bufs[i][pos] = word[i];
+            }
+        }
+
+        for (out, &i) in outputs.iter_mut().zip(missing.iter()) {
+            out.write_all(&bufs[i][..len]).await?;
+        }
+        remaining -= len as u64;
+    }
+    for out in outputs.iter_mut() {
+        out.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn volumes() -> Vec<Vec<u8>> {
+        vec![
+            (0..1000).map(|i| (i % 253) as u8).collect(),
+            (0..1000).map(|i| (i * 3 % 7) as u8).collect(),
+            b"the last volume is short".to_vec(),
+        ]
+    }
+
+    fn rev_files(volumes: &[Vec<u8>], rec_count: usize) -> Vec<Vec<u8>> {
+        let size = volumes.iter().map(|v| v.len()).max().unwrap();
+        let coder = RsCoder::new(rec_count);
+        let mut revs = vec![Vec::new(); rec_count];
+        let mut parity = vec![0u8; rec_count];
+        for pos in 0..size {
+            let data: Vec<u8> = volumes.iter().map(|v| *v.get(pos).unwrap_or(&0)).collect();
+            coder.encode(&data, &mut parity);
+            for (rev, &p) in revs.iter_mut().zip(parity.iter()) {
+                rev.push(p);
+            }
+        }
+        for (i, rev) in revs.iter_mut().enumerate() {
+            rev.extend(&[volumes.len() as u8 - 1, rec_count as u8 - 1, i as u8]);
+            let crc = crc32::checksum_ieee(rev);
+            rev.extend(&crc.to_le_bytes());
+        }
+        revs
+    }
+
+    #[test]
+    fn test_reads_trailer() {
+        let revs = rev_files(&volumes(), 2);
+        let volume = block_on(Rev3Volume::read(&revs[1][..])).unwrap();
+        assert_eq!(
+            volume,
+            Rev3Volume {
+                number: 2,
+                rec_count: 2,
+                data_count: 3,
+                data_size: 1000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_corrupt_rev_file() {
+        let mut revs = rev_files(&volumes(), 2);
+        revs[0][10] ^= 1;
+        assert!(block_on(Rev3Volume::read(&revs[0][..])).is_err());
+        assert!(block_on(Rev3Volume::read(&revs[0][..5])).is_err());
+    }
+
+    #[test]
+    fn test_rebuilds_missing_volumes() {
+        let original = volumes();
+        let revs = rev_files(&original, 3);
+        let mut sources: Vec<Option<&[u8]>> = vec![None, Some(&original[1][..]), None];
+        // One recovery volume is missing as well.
+        let mut rev_sources: Vec<Option<&[u8]>> =
+            vec![Some(&revs[0][..]), None, Some(&revs[2][..])];
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        block_on(rebuild_rev3(
+            &mut sources,
+            &mut rev_sources,
+            1000,
+            &mut outputs,
+        ))
+        .unwrap();
+        assert_eq!(outputs[0], original[0]);
+        assert_eq!(&outputs[1][..original[2].len()], &original[2][..]);
+        assert!(outputs[1][original[2].len()..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_rebuild_needs_enough_recovery_volumes() {
+        let original = volumes();
+        let revs = rev_files(&original, 1);
+        let mut sources: Vec<Option<&[u8]>> = vec![None, None, Some(&original[2][..])];
+        let mut rev_sources: Vec<Option<&[u8]>> = vec![Some(&revs[0][..])];
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        assert!(block_on(rebuild_rev3(
+            &mut sources,
+            &mut rev_sources,
+            1000,
+            &mut outputs
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,306 @@
+use super::rev3::read_padded;
+use super::rs16::Rs16Coder;
+use crate::error::{Error, Result};
+use crate::traits::AsyncFile;
+use byteorder::{ByteOrder, LittleEndian};
+use crc::crc32::{self, Hasher32};
+use futures::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub const REV5_SIGNATURE: [u8; 8] = *b"Rar!\x1aRev";
+
+// Largest header RAR writes to a RAR5 .rev file.
+const MAX_REV5_HEADER_SIZE: usize = 0x10_0000;
+
+// How many bytes of each volume to process at a time.
+const CHUNK_SIZE: usize = 0x10000;
+
+// Size and CRC32 of one data volume of the set.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RevVolumeInfo {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+// Header at the start of a RAR5 .rev file, after the signature: a CRC32, the
+// header size and then the header itself.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Rev5Header {
+    pub version: u8,
+    pub data_count: usize,
+    pub rec_count: usize,
+    // 0 based index of this volume among data and recovery volumes.
+    pub rec_num: usize,
+    pub rev_crc32: u32,
+    pub volumes: Vec<RevVolumeInfo>,
+    // Offset of the recovery data in the file.
+    pub data_offset: u64,
+}
+
+impl Rev5Header {
+    pub async fn read<T: AsyncFile>(f: &mut T) -> Result<Rev5Header> {
+        let mut prefix = [0u8; 16];
+        f.read_exact(&mut prefix).await?;
+        if prefix[..8] != REV5_SIGNATURE {
+            return Err(Error::bad_block("Not a RAR5 recovery volume".into()));
+        }
+        let expected_crc = LittleEndian::read_u32(&prefix[8..12]);
+        let size = LittleEndian::read_u32(&prefix[12..16]) as usize;
+        if size <= 5 || size > MAX_REV5_HEADER_SIZE {
+            return Err(Error::bad_block(format!(
+                "Invalid recovery volume header size {}",
+                size
+            )));
+        }
+        let mut buf = vec![0u8; size];
+        f.read_exact(&mut buf).await?;
+
+        // The CRC covers the size field too.
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        digest.write(&prefix[12..16]);
+        digest.write(&buf);
+        if digest.sum32() != expected_crc {
+            return Err(Error::bad_block(
+                "Recovery volume header CRC mismatch".into(),
+            ));
+        }
+        Rev5Header::parse(&buf, 16 + size as u64)
+    }
+
+    fn parse(buf: &[u8], data_offset: u64) -> Result<Rev5Header> {
+        let too_short = || Error::bad_block("Recovery volume header is too short".into());
+        if buf.len() < 11 {
+            return Err(too_short());
+        }
+        let version = buf[0];
+        if version != 1 {
+            return Err(Error::unsupported(format!(
+                "RAR5 recovery volume version {}",
+                version
+            )));
+        }
+        let data_count = usize::from(LittleEndian::read_u16(&buf[1..3]));
+        let rec_count = usize::from(LittleEndian::read_u16(&buf[3..5]));
+        let rec_num = usize::from(LittleEndian::read_u16(&buf[5..7]));
+        if rec_num >= data_count + rec_count {
+            return Err(Error::bad_block("Invalid recovery volume numbering".into()));
+        }
+        let rev_crc32 = LittleEndian::read_u32(&buf[7..11]);
+
+        let mut volumes = Vec::with_capacity(data_count);
+        let mut rest = &buf[11..];
+        for _ in 0..data_count {
+            if rest.len() < 12 {
+                return Err(too_short());
+            }
+            volumes.push(RevVolumeInfo {
+                size: LittleEndian::read_u64(&rest[..8]),
+                crc32: LittleEndian::read_u32(&rest[8..12]),
+            });
+            rest = &rest[12..];
+        }
+
+        Ok(Rev5Header {
+            version,
+            data_count,
+            rec_count,
+            rec_num,
+            rev_crc32,
+            volumes,
+            data_offset,
+        })
+    }
+
+    // Check data volume `index` of the set against the size and CRC32 the
+    // header records for it.
+    pub async fn volume_is_intact<T: AsyncFile>(&self, index: usize, mut f: T) -> Result<bool> {
+        let info = match self.volumes.get(index) {
+            Some(info) => *info,
+            None => return Ok(false),
+        };
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        let mut size: u64 = 0;
+        let mut buf = vec![0u8; 0x10000];
+        loop {
+            let n = f.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            digest.write(&buf[..n]);
+            size += n as u64;
+        }
+        Ok(size == info.size && digest.sum32() == info.crc32)
+    }
+}
+
+// Regenerate missing data volumes of the set `header` describes. `volumes`
+// holds every data volume in order, `None` where it's missing or damaged,
+// each positioned at its start, and `revs` every recovery volume by its
+// number less the number of data volumes, each positioned at its recovery
+// data. A writer is needed in `outputs` for each missing data volume, in
+// order, and gets the volume at the size the header gives.
+//
+// The recovery data is coded with `Rs16Coder`, with every data volume zero
+// padded to the size of the largest one, rounded up to whole words.
+pub async fn rebuild_rev5<R: AsyncFile, W: AsyncWrite + Unpin>(
+    header: &Rev5Header,
+    volumes: &mut [Option<R>],
+    revs: &mut [Option<R>],
+    outputs: &mut [W],
+) -> Result<()> {
+    let data_count = header.data_count;
+    if volumes.len() != data_count || revs.len() != header.rec_count {
+        return Err(Error::bad_data(
+            "Volumes don't match the recovery volume header".into(),
+        ));
+    }
+    let missing: Vec<usize> = (0..data_count).filter(|&i| volumes[i].is_none()).collect();
+    if outputs.len() != missing.len() {
+        return Err(Error::bad_data(format!(
+            "{} volumes are missing but {} outputs were given",
+            missing.len(),
+            outputs.len()
+        )));
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let intact: Vec<bool> = volumes
+        .iter()
+        .map(Option::is_some)
+        .chain(revs.iter().map(Option::is_some))
+        .collect();
+    let coder = Rs16Coder::new(data_count, header.rec_count)?;
+    let decoder = coder.decoder(&intact)?;
+
+    let largest = header.volumes.iter().map(|v| v.size).max().unwrap_or(0);
+    let data_size = largest + largest % 2;
+    let mut bufs = vec![vec![0u8; CHUNK_SIZE]; data_count + header.rec_count];
+    let mut word = vec![0u16; bufs.len()];
+    let mut done: u64 = 0;
+    while done < data_size {
+        let len = (data_size - done).min(CHUNK_SIZE as u64) as usize;
+        let sources = volumes.iter_mut().chain(revs.iter_mut());
+        for (buf, source) in bufs.iter_mut().zip(sources) {
+            if let Some(f) = source {
+                read_padded(f, &mut buf[..len]).await?;
+            }
+        }
+
+        for pos in (0..len).step_by(2) {
+            for (w, buf) in word.iter_mut().zip(bufs.iter()) {
+                *w = LittleEndian::read_u16(&buf[pos..]);
+            }
+            decoder.decode(&mut word);
+            for &i in &missing {
+                LittleEndian::write_u16(&mut bufs[i][pos..], word[i]);
+            }
+        }
+
+        for (out, &i) in outputs.iter_mut().zip(missing.iter()) {
+            let size = header.volumes[i].size;
+            let keep = size.saturating_sub(done).min(len as u64) as usize;
+            out.write_all(&bufs[i][..keep]).await?;
+        }
+        done += len as u64;
+    }
+    for out in outputs.iter_mut() {
+        out.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::rev5_files;
+    use futures::executor::block_on;
+
+    fn rev_file(version: u8) -> Vec<u8> {
+        let mut header = vec![version, 2, 0, 1, 0, 2, 0];
+        header.extend(&0xdead_beefu32.to_le_bytes());
+        for &(size, crc) in &[(4u64, crc32::checksum_ieee(b"roar")), (2, 0)] {
+            header.extend(&size.to_le_bytes());
+            header.extend(&crc.to_le_bytes());
+        }
+        let size = (header.len() as u32).to_le_bytes();
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        digest.write(&size);
+        digest.write(&header);
+
+        let mut buf = REV5_SIGNATURE.to_vec();
+        buf.extend(&digest.sum32().to_le_bytes());
+        buf.extend(&size);
+        buf.extend(header);
+        buf.extend(b"recovery data");
+        buf
+    }
+
+    #[test]
+    fn test_reads_header() {
+        let data = rev_file(1);
+        let mut f = &data[..];
+        let header = block_on(Rev5Header::read(&mut f)).unwrap();
+        assert_eq!(header.data_count, 2);
+        assert_eq!(header.rec_count, 1);
+        assert_eq!(header.rec_num, 2);
+        assert_eq!(header.rev_crc32, 0xdead_beef);
+        assert_eq!(header.volumes[1], RevVolumeInfo { size: 2, crc32: 0 });
+        assert_eq!(&data[header.data_offset as usize..], b"recovery data");
+        assert_eq!(f, b"recovery data");
+    }
+
+    #[test]
+    fn test_checks_volumes() {
+        let data = rev_file(1);
+        let header = block_on(Rev5Header::read(&mut &data[..])).unwrap();
+        assert!(block_on(header.volume_is_intact(0, &b"roar"[..])).unwrap());
+        assert!(!block_on(header.volume_is_intact(0, &b"meow"[..])).unwrap());
+        assert!(!block_on(header.volume_is_intact(5, &b"roar"[..])).unwrap());
+    }
+
+    #[test]
+    fn test_rebuilds_missing_volumes() {
+        let original: Vec<Vec<u8>> = vec![
+            (0..1001).map(|i| (i % 253) as u8).collect(),
+            b"the middle".to_vec(),
+            (0..500).map(|i| (i * 3 % 7) as u8).collect(),
+        ];
+        let revs = rev5_files(&original, 2);
+        let header = block_on(Rev5Header::read(&mut &revs[0][..])).unwrap();
+        let data = |r: usize| &revs[r][header.data_offset as usize..];
+
+        let mut sources: Vec<Option<&[u8]>> = vec![None, Some(&original[1][..]), None];
+        let mut rev_sources: Vec<Option<&[u8]>> = vec![Some(data(0)), Some(data(1))];
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        block_on(rebuild_rev5(
+            &header,
+            &mut sources,
+            &mut rev_sources,
+            &mut outputs,
+        ))
+        .unwrap();
+        assert_eq!(outputs[0], original[0]);
+        assert_eq!(outputs[1], original[2]);
+
+        // A lost recovery volume leaves only one to rebuild two volumes.
+        let mut sources: Vec<Option<&[u8]>> = vec![None, Some(&original[1][..]), None];
+        let mut rev_sources: Vec<Option<&[u8]>> = vec![Some(data(0)), None];
+        let mut outputs = vec![Vec::new(), Vec::new()];
+        assert!(block_on(rebuild_rev5(
+            &header,
+            &mut sources,
+            &mut rev_sources,
+            &mut outputs
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_headers() {
+        let mut data = rev_file(1);
+        data[20] ^= 1;
+        assert!(block_on(Rev5Header::read(&mut &data[..])).is_err());
+        assert!(block_on(Rev5Header::read(&mut &rev_file(2)[..])).is_err());
+        assert!(block_on(Rev5Header::read(&mut &b"Rar!\x1a\x07\x01\x00"[..])).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+
+// Primitive polynomial of the GF(2^8) field used by RAR 3.x recovery volumes.
+const GF_POLY: u32 = 0x11d;
+const GF_SIZE: usize = 255;
+
+// Reed-Solomon code over GF(2^8) with `par_size` parity symbols, whose
+// generator has the roots a^1..a^par_size. A codeword is the data symbols
+// followed by the parity, highest degree coefficient first, as RAR lays
+// out one byte from every volume followed by one from every .rev file.
+pub struct RsCoder {
+    par_size: usize,
+    exp: [u8; GF_SIZE * 2],
+    log: [u8; GF_SIZE + 1],
+    // Generator polynomial coefficients, lowest degree first, without the
+    // leading 1.
+    generator: Vec<u8>,
+}
+
+impl RsCoder {
+    pub fn new(par_size: usize) -> RsCoder {
+        let mut exp = [0u8; GF_SIZE * 2];
+        let mut log = [0u8; GF_SIZE + 1];
+        let mut x: u32 = 1;
+        for i in 0..GF_SIZE {
+            exp[i] = x as u8;
+            exp[i + GF_SIZE] = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x > GF_SIZE as u32 {
+                x ^= GF_POLY;
+            }
+        }
+
+        let mut coder = RsCoder {
+            par_size,
+            exp,
+            log,
+            generator: Vec::new(),
+        };
+
+        // Multiply out (x + a^1)(x + a^2)...(x + a^par_size).
+        let mut generator = vec![1u8];
+        for i in 1..=par_size {
+            let root = coder.exp[i];
+            let mut next = vec![0u8; generator.len() + 1];
+            for (j, &c) in generator.iter().enumerate() {
+                next[j] ^= coder.mul(c, root);
+                next[j + 1] ^= c;
+            }
+            generator = next;
+        }
+        generator.pop();
+        coder.generator = generator;
+        coder
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[usize::from(self.log[usize::from(a)]) + usize::from(self.log[usize::from(b)])]
+    }
+
+    fn inv(&self, a: u8) -> u8 {
+        self.exp[GF_SIZE - usize::from(self.log[usize::from(a)])]
+    }
+
+    // a^n
+    fn pow(&self, n: usize) -> u8 {
+        self.exp[n % GF_SIZE]
+    }
+
+    // Compute the parity symbols for `data` into `parity`, which must be
+    // `par_size` long.
+    pub fn encode(&self, data: &[u8], parity: &mut [u8]) {
+        let n = self.par_size;
+        let mut reg = vec![0u8; n];
+        for &d in data {
+            let feedback = d ^ reg[n - 1];
+            for j in (1..n).rev() {
+                reg[j] = reg[j - 1] ^ self.mul(self.generator[j], feedback);
+            }
+            reg[0] = self.mul(self.generator[0], feedback);
+        }
+        for (j, p) in parity.iter_mut().enumerate() {
+            *p = reg[n - 1 - j];
+        }
+    }
+
+    // Fill in the symbols at the `erasures` positions of `codeword`, given
+    // that all the other symbols are intact.
+    pub fn decode(&self, codeword: &mut [u8], erasures: &[usize]) -> Result<()> {
+        let m = erasures.len();
+        if m == 0 {
+            return Ok(());
+        }
+        if m > self.par_size || codeword.len() > GF_SIZE {
+            return Err(Error::bad_data(format!(
+                "Can't recover {} of {} symbols with {} parity symbols",
+                m,
+                codeword.len(),
+                self.par_size
+            )));
+        }
+        for &e in erasures {
+            codeword[e] = 0;
+        }
+
+        // The syndromes of the codeword with the erased symbols zeroed are
+        // what the erased symbols contribute to the (zero) syndromes of the
+        // intact codeword, which gives m equations for m unknowns.
+        let last = codeword.len() - 1;
+        let mut rows = vec![vec![0u8; m + 1]; m];
+        for (i, row) in rows.iter_mut().enumerate() {
+            let root = i + 1;
+            for (k, &c) in codeword.iter().enumerate() {
+                row[m] ^= self.mul(c, self.pow(root * (last - k)));
+            }
+            for (j, &e) in erasures.iter().enumerate() {
+                row[j] = self.pow(root * (last - e));
+            }
+        }
+
+        // Gauss-Jordan elimination.
+        for col in 0..m {
+            let pivot = (col..m)
+                .find(|&r| rows[r][col] != 0)
+                .ok_or_else(|| Error::bad_data("Singular recovery matrix".into()))?;
+            rows.swap(col, pivot);
+            let scale = self.inv(rows[col][col]);
+            for x in rows[col].iter_mut() {
+                *x = self.mul(*x, scale);
+            }
+            let pivot_row = rows[col].clone();
+            for (r, row) in rows.iter_mut().enumerate() {
+                let factor = row[col];
+                if r == col || factor == 0 {
+                    continue;
+                }
+                for (x, &p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                    *x ^= self.mul(p, factor);
+                }
+            }
+        }
+
+        for (j, &e) in erasures.iter().enumerate() {
+            codeword[e] = rows[j][m];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codeword(coder: &RsCoder, data: &[u8]) -> Vec<u8> {
+        let mut parity = vec![0u8; coder.par_size];
+        coder.encode(data, &mut parity);
+        let mut word = data.to_vec();
+        word.extend(parity);
+        word
+    }
+
+    #[test]
+    fn test_codeword_has_generator_roots() {
+        let coder = RsCoder::new(4);
+        let word = codeword(&coder, b"roar recovery");
+        let last = word.len() - 1;
+        for root in 1..=4 {
+            let mut sum = 0u8;
+            for (k, &c) in word.iter().enumerate() {
+                sum ^= coder.mul(c, coder.pow(root * (last - k)));
+            }
+            assert_eq!(sum, 0);
+        }
+    }
+
+    #[test]
+    fn test_decode_restores_erased_data_and_parity() {
+        let coder = RsCoder::new(3);
+        let original = codeword(&coder, &[0x00, 0xff, 0x13, 0x37, 0x80, 0x01]);
+        let mut damaged = original.clone();
+        let erasures = [1, 4, 7];
+        for &e in &erasures {
+            damaged[e] = 0xaa;
+        }
+        coder.decode(&mut damaged, &erasures).unwrap();
+        assert_eq!(damaged, original);
+    }
+
+    #[test]
+    fn test_decode_rejects_too_many_erasures() {
+        let coder = RsCoder::new(2);
+        let mut word = codeword(&coder, b"abc");
+        assert!(coder.decode(&mut word, &[0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::error::{Error, Result};
+
+// Primitive polynomial of the GF(2^16) field used by RAR5 recovery records
+// and recovery volumes.
+const GF_POLY: u32 = 0x1100b;
+const GF_SIZE: usize = 65535;
+
+// Reed-Solomon erasure code over GF(2^16), as RAR5 uses it. Data is coded
+// in 16 bit little endian words, one from each of `data_count` data units at
+// the same offset, and recovery unit `r` holds the sum of every data word
+// multiplied by 1 / ((r + data_count) xor d), d being the data unit's index.
+// Any square part of that Cauchy matrix can be inverted, so any
+// `rec_count` units can be lost.
+pub struct Rs16Coder {
+    data_count: usize,
+    rec_count: usize,
+    exp: Vec<u16>,
+    log: Vec<u16>,
+}
+
+// Rebuilds the missing data words of one particular set of intact units.
+pub struct Rs16Decoder<'a> {
+    coder: &'a Rs16Coder,
+    // Indices of the missing and of the intact data units, in order.
+    missing: Vec<usize>,
+    known: Vec<usize>,
+    // The recovery units standing in for them.
+    recovery: Vec<usize>,
+    // Inverse of the matrix rows of `recovery` restricted to the columns
+    // of `missing`.
+    inverse: Vec<Vec<u16>>,
+}
+
+impl Rs16Coder {
+    pub fn new(data_count: usize, rec_count: usize) -> Result<Rs16Coder> {
+        if data_count == 0 || rec_count == 0 || data_count + rec_count > GF_SIZE {
+            return Err(Error::bad_data(format!(
+                "Can't code {} data units with {} recovery units",
+                data_count, rec_count
+            )));
+        }
+        let mut exp = vec![0u16; GF_SIZE * 2];
+        let mut log = vec![0u16; GF_SIZE + 1];
+        let mut x: u32 = 1;
+        for i in 0..GF_SIZE {
+            exp[i] = x as u16;
+            exp[i + GF_SIZE] = x as u16;
+            log[x as usize] = i as u16;
+            x <<= 1;
+            if x > GF_SIZE as u32 {
+                x ^= GF_POLY;
+            }
+        }
+        Ok(Rs16Coder {
+            data_count,
+            rec_count,
+            exp,
+            log,
+        })
+    }
+
+    pub fn data_count(&self) -> usize {
+        self.data_count
+    }
+
+    pub fn rec_count(&self) -> usize {
+        self.rec_count
+    }
+
+    fn mul(&self, a: u16, b: u16) -> u16 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        self.exp[usize::from(self.log[usize::from(a)]) + usize::from(self.log[usize::from(b)])]
+    }
+
+    fn inv(&self, a: u16) -> u16 {
+        self.exp[GF_SIZE - usize::from(self.log[usize::from(a)])]
+    }
+
+    // Coefficient of data unit `data` in recovery unit `rec`.
+    fn element(&self, rec: usize, data: usize) -> u16 {
+        self.inv(((rec + self.data_count) ^ data) as u16)
+    }
+
+    // Compute the recovery words for one word of every data unit.
+    pub fn encode(&self, data: &[u16], parity: &mut [u16]) {
+        for (rec, p) in parity.iter_mut().enumerate().take(self.rec_count) {
+            *p = data
+                .iter()
+                .enumerate()
+                .take(self.data_count)
+                .fold(0, |sum, (d, &x)| sum ^ self.mul(self.element(rec, d), x));
+        }
+    }
+
+    // Prepare to rebuild the data units that aren't `intact`, which lists
+    // the data units and then the recovery units.
+    pub fn decoder(&self, intact: &[bool]) -> Result<Rs16Decoder<'_>> {
+        let is_intact = |i: usize| intact.get(i).cloned().unwrap_or(false);
+        let (known, missing): (Vec<usize>, Vec<usize>) =
+            (0..self.data_count).partition(|&i| is_intact(i));
+        let recovery: Vec<usize> = (0..self.rec_count)
+            .filter(|&r| is_intact(self.data_count + r))
+            .take(missing.len())
+            .collect();
+        if recovery.len() < missing.len() {
+            return Err(Error::bad_data(format!(
+                "{} units are missing but only {} recovery units are intact",
+                missing.len(),
+                recovery.len()
+            )));
+        }
+
+        // Gauss-Jordan elimination, with the identity alongside.
+        let m = missing.len();
+        let mut rows: Vec<Vec<u16>> = recovery
+            .iter()
+            .enumerate()
+            .map(|(i, &r)| {
+                let mut row: Vec<u16> = missing.iter().map(|&d| self.element(r, d)).collect();
+                row.extend((0..m).map(|j| if i == j { 1 } else { 0 }));
+                row
+            })
+            .collect();
+        for col in 0..m {
+            let pivot = (col..m)
+                .find(|&r| rows[r][col] != 0)
+                .ok_or_else(|| Error::bad_data("Singular recovery matrix".into()))?;
+            rows.swap(col, pivot);
+            let scale = self.inv(rows[col][col]);
+            for x in rows[col].iter_mut() {
+                *x = self.mul(*x, scale);
+            }
+            let pivot_row = rows[col].clone();
+            for (r, row) in rows.iter_mut().enumerate() {
+                let factor = row[col];
+                if r == col || factor == 0 {
+                    continue;
+                }
+                for (x, &p) in row.iter_mut().zip(&pivot_row) {
+                    *x ^= self.mul(p, factor);
+                }
+            }
+        }
+        let inverse = rows.into_iter().map(|row| row[m..].to_vec()).collect();
+
+        Ok(Rs16Decoder {
+            coder: self,
+            missing,
+            known,
+            recovery,
+            inverse,
+        })
+    }
+}
+
+impl<'a> Rs16Decoder<'a> {
+    pub fn missing(&self) -> &[usize] {
+        &self.missing
+    }
+
+    // Fill in the missing data words of `word`, which holds a word of every
+    // data unit and then of every recovery unit. Missing words are ignored.
+    pub fn decode(&self, word: &mut [u16]) {
+        let coder = self.coder;
+        // What the missing data contributes to each recovery word used.
+        let sums: Vec<u16> = self
+            .recovery
+            .iter()
+            .map(|&r| {
+                let known = self
+                    .known
+                    .iter()
+                    .fold(0, |sum, &d| sum ^ coder.mul(coder.element(r, d), word[d]));
+                word[coder.data_count + r] ^ known
+            })
+            .collect();
+        for (&d, row) in self.missing.iter().zip(&self.inverse) {
+            word[d] = row
+                .iter()
+                .zip(&sums)
+                .fold(0, |sum, (&x, &s)| sum ^ coder.mul(x, s));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codeword(coder: &Rs16Coder, data: &[u16]) -> Vec<u16> {
+        let mut parity = vec![0u16; coder.rec_count()];
+        coder.encode(data, &mut parity);
+        let mut word = data.to_vec();
+        word.extend(parity);
+        word
+    }
+
+    #[test]
+    fn test_field_inverse() {
+        let coder = Rs16Coder::new(1, 1).unwrap();
+        for &a in &[1u16, 2, 0x1234, 0xffff] {
+            assert_eq!(coder.mul(a, coder.inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_decode_restores_missing_data() {
+        let coder = Rs16Coder::new(5, 3).unwrap();
+        let original = codeword(&coder, &[0x0000, 0xffff, 0x1337, 0x8001, 0x00aa]);
+        let mut intact = vec![true; 8];
+        let mut damaged = original.clone();
+        // Two data units and a recovery unit are gone.
+        for &i in &[1, 3, 5] {
+            intact[i] = false;
+            damaged[i] = 0xdead;
+        }
+        let decoder = coder.decoder(&intact).unwrap();
+        assert_eq!(decoder.missing(), &[1, 3]);
+        decoder.decode(&mut damaged);
+        assert_eq!(damaged[..5], original[..5]);
+    }
+
+    #[test]
+    fn test_decoder_needs_enough_recovery_units() {
+        let coder = Rs16Coder::new(3, 2).unwrap();
+        assert!(coder.decoder(&[false, false, true, true, false]).is_err());
+        assert!(coder.decoder(&[false, true, true, false, true]).is_ok());
+        assert!(Rs16Coder::new(65535, 1).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+// Rebuilding the volumes of a set on disk. The recovery volumes are the .rev
+// files next to the first volume sharing its name up to the volume number,
+// told apart by the RAR5 signature. A volume is rebuilt when it's missing
+// or, for RAR5 sets, when it doesn't match the size and CRC32 the .rev
+// headers give; RAR 3.x .rev files keep no checksums of the volumes, so
+// those only count as damaged when their blocks can't be read. Rebuilt
+// volumes are written next to the others first and only moved into place
+// once the whole set was rebuilt, with damaged volumes kept as `name.bad`.
+
+use super::rev3::{rebuild_rev3, Rev3Volume};
+use super::rev5::{rebuild_rev5, Rev5Header, REV5_SIGNATURE};
+use crate::archive::Archive;
+use crate::error::{Error, Result};
+use crate::volume::volume_path;
+use futures::io::AllowStdIo;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RebuildReport {
+    // Volumes written anew.
+    pub rebuilt: Vec<PathBuf>,
+    // Where damaged volumes were moved before being rebuilt.
+    pub kept: Vec<PathBuf>,
+}
+
+// The recovery volumes found for a set.
+enum RevFiles {
+    Rar3(Vec<(PathBuf, Rev3Volume)>),
+    Rar5(Vec<(PathBuf, Rev5Header)>),
+}
+
+// Rebuild whatever volumes of the set starting with `first` are missing or
+// damaged from its recovery volumes. Nothing is touched unless all of them
+// can be rebuilt.
+pub async fn rebuild_volumes(first: &Path) -> Result<RebuildReport> {
+    match find_rev_files(first).await? {
+        RevFiles::Rar5(revs) => rebuild_rar5_set(first, revs).await,
+        RevFiles::Rar3(revs) => rebuild_rar3_set(first, revs).await,
+    }
+}
+
+// `name.part` for `name.part1.rar`, `name` for `name.rar`.
+fn set_prefix(first: &Path) -> Option<String> {
+    let name = first.file_name()?.to_str()?.to_ascii_lowercase();
+    let base = name.strip_suffix(".rar")?;
+    let numbered = base.trim_end_matches(|c: char| c.is_ascii_digit());
+    if numbered.len() < base.len() && numbered.ends_with(".part") {
+        Some(numbered.to_string())
+    } else {
+        Some(base.to_string())
+    }
+}
+
+async fn find_rev_files(first: &Path) -> Result<RevFiles> {
+    let prefix = set_prefix(first)
+        .ok_or_else(|| Error::unsupported(format!("Volume names like {}", first.display())))?;
+    let dir = match first.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let mut paths = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_ascii_lowercase(),
+            None => continue,
+        };
+        let rest = match name.strip_prefix(&prefix) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        // Don't take `name2.rev` for a recovery volume of `name.rar`.
+        let separated = rest.starts_with(|c: char| !c.is_ascii_alphabetic());
+        if separated && rest.ends_with(".rev") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut rar3: Vec<(PathBuf, Rev3Volume)> = Vec::new();
+    let mut rar5: Vec<(PathBuf, Rev5Header)> = Vec::new();
+    for path in paths {
+        let mut file = fs::File::open(&path)?;
+        let mut signature = [0u8; 8];
+        let is_rar5 = file.read_exact(&mut signature).is_ok() && signature == REV5_SIGNATURE;
+        file.seek(SeekFrom::Start(0))?;
+        // Damaged .rev files are left out like missing ones.
+        if is_rar5 {
+            if let Ok(header) = Rev5Header::read(&mut AllowStdIo::new(file)).await {
+                let same_set = rar5.iter().all(|(_, other)| {
+                    (other.data_count, other.rec_count, other.rev_crc32)
+                        == (header.data_count, header.rec_count, header.rev_crc32)
+                });
+                if same_set && header.rec_num >= header.data_count {
+                    rar5.push((path, header));
+                }
+            }
+        } else if let Ok(volume) = Rev3Volume::read(AllowStdIo::new(file)).await {
+            let same_set = rar3.iter().all(|(_, other)| {
+                (other.data_count, other.rec_count, other.data_size)
+                    == (volume.data_count, volume.rec_count, volume.data_size)
+            });
+            if same_set {
+                rar3.push((path, volume));
+            }
+        }
+    }
+    if !rar5.is_empty() {
+        Ok(RevFiles::Rar5(rar5))
+    } else if !rar3.is_empty() {
+        Ok(RevFiles::Rar3(rar3))
+    } else {
+        Err(Error::bad_data(format!(
+            "No recovery volumes found for {}",
+            first.display()
+        )))
+    }
+}
+
+// Paths of the `count` data volumes of the set.
+fn volume_paths(first: &Path, count: usize) -> Result<Vec<PathBuf>> {
+    (0..count)
+        .map(|i| {
+            volume_path(first, i)
+                .ok_or_else(|| Error::unsupported(format!("Sets of more than {} volumes", i)))
+        })
+        .collect()
+}
+
+fn rebuilt_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rebuilt");
+    path.with_file_name(name)
+}
+
+fn kept_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bad");
+    path.with_file_name(name)
+}
+
+// Create the files to rebuild the volumes at `missing` into.
+fn create_outputs(missing: &[&PathBuf]) -> Result<Vec<AllowStdIo<fs::File>>> {
+    missing
+        .iter()
+        .map(|path| Ok(AllowStdIo::new(fs::File::create(rebuilt_path(path))?)))
+        .collect()
+}
+
+// Move the rebuilt volumes into place if they all came out right, or remove
+// them all.
+fn finish(missing: &[&PathBuf], rebuilt: Result<()>) -> Result<RebuildReport> {
+    if let Err(e) = rebuilt {
+        for path in missing {
+            let _ = fs::remove_file(rebuilt_path(path));
+        }
+        return Err(e);
+    }
+    let mut report = RebuildReport::default();
+    for path in missing {
+        if path.exists() {
+            fs::rename(path, kept_path(path))?;
+            report.kept.push(kept_path(path));
+        }
+        fs::rename(rebuilt_path(path), path)?;
+        report.rebuilt.push(path.to_path_buf());
+    }
+    Ok(report)
+}
+
+async fn rebuild_rar5_set(first: &Path, revs: Vec<(PathBuf, Rev5Header)>) -> Result<RebuildReport> {
+    let header = revs[0].1.clone();
+    let paths = volume_paths(first, header.data_count)?;
+    let mut volumes = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let intact = match fs::File::open(path) {
+            Ok(file) => header.volume_is_intact(i, AllowStdIo::new(file)).await?,
+            Err(_) => false,
+        };
+        volumes.push(if intact {
+            Some(AllowStdIo::new(fs::File::open(path)?))
+        } else {
+            None
+        });
+    }
+    let missing: Vec<&PathBuf> = paths
+        .iter()
+        .zip(&volumes)
+        .filter(|(_, v)| v.is_none())
+        .map(|(p, _)| p)
+        .collect();
+    if missing.is_empty() {
+        return Ok(RebuildReport::default());
+    }
+
+    let mut rec_volumes: Vec<Option<AllowStdIo<fs::File>>> =
+        (0..header.rec_count).map(|_| None).collect();
+    for (path, rev) in &revs {
+        let mut file = fs::File::open(path)?;
+        file.seek(SeekFrom::Start(rev.data_offset))?;
+        rec_volumes[rev.rec_num - header.data_count] = Some(AllowStdIo::new(file));
+    }
+    let mut outputs = create_outputs(&missing)?;
+    let mut rebuilt = rebuild_rev5(&header, &mut volumes, &mut rec_volumes, &mut outputs).await;
+    drop(outputs);
+    for (i, path) in paths.iter().enumerate() {
+        if rebuilt.is_err() || volumes[i].is_some() {
+            continue;
+        }
+        let file = AllowStdIo::new(fs::File::open(rebuilt_path(path))?);
+        if !header.volume_is_intact(i, file).await? {
+            rebuilt = Err(Error::bad_data(format!(
+                "Rebuilt volume {} doesn't match its CRC32",
+                path.display()
+            )));
+        }
+    }
+    finish(&missing, rebuilt)
+}
+
+// Whether the blocks of a RAR 3.x volume can all be read.
+async fn rar3_volume_is_readable(path: &Path) -> bool {
+    let file = match fs::File::open(path) {
+        Ok(file) => AllowStdIo::new(file),
+        Err(_) => return false,
+    };
+    match Archive::open(file).await {
+        Ok(mut archive) => archive.build_index().await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Cut a rebuilt RAR 3.x volume, which comes out as long as the largest
+// volume, at the end of its end of archive block.
+async fn trim_rar3_volume(path: &Path) -> Result<()> {
+    let mut archive = Archive::open(AllowStdIo::new(fs::File::open(path)?)).await?;
+    archive.build_index().await?;
+    if archive.end().is_some() {
+        fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(archive.position())?;
+    }
+    Ok(())
+}
+
+async fn rebuild_rar3_set(first: &Path, revs: Vec<(PathBuf, Rev3Volume)>) -> Result<RebuildReport> {
+    let info = revs[0].1;
+    let paths = volume_paths(first, info.data_count)?;
+    let mut volumes = Vec::with_capacity(paths.len());
+    for path in &paths {
+        volumes.push(if rar3_volume_is_readable(path).await {
+            Some(AllowStdIo::new(fs::File::open(path)?))
+        } else {
+            None
+        });
+    }
+    let missing: Vec<&PathBuf> = paths
+        .iter()
+        .zip(&volumes)
+        .filter(|(_, v)| v.is_none())
+        .map(|(p, _)| p)
+        .collect();
+    if missing.is_empty() {
+        return Ok(RebuildReport::default());
+    }
+
+    let mut rec_volumes: Vec<Option<AllowStdIo<fs::File>>> =
+        (0..info.rec_count).map(|_| None).collect();
+    for (path, rev) in &revs {
+        rec_volumes[rev.number - 1] = Some(AllowStdIo::new(fs::File::open(path)?));
+    }
+    let mut outputs = create_outputs(&missing)?;
+    let mut rebuilt =
+        rebuild_rev3(&mut volumes, &mut rec_volumes, info.data_size, &mut outputs).await;
+    drop(outputs);
+    for path in &missing {
+        if rebuilt.is_ok() {
+            rebuilt = trim_rar3_volume(&rebuilt_path(path)).await;
+        }
+    }
+    finish(&missing, rebuilt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR50_SIGNATURE;
+    use crate::testing::{rar5_header, rev5_files};
+    use futures::executor::block_on;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roar-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn volumes() -> Vec<Vec<u8>> {
+        (0..3)
+            .map(|i| {
+                let mut volume = RAR50_SIGNATURE.to_vec();
+                volume.extend(rar5_header(&[0x01, 0x00, 0x03, i as u8]));
+                volume.extend((0..300 + i * 11).map(|k| (k * (i + 3)) as u8));
+                volume
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rebuilds_missing_and_damaged_rar5_volumes() {
+        let dir = scratch("rebuild5");
+        let original = volumes();
+        for (i, volume) in original.iter().enumerate() {
+            fs::write(dir.join(format!("a.part{}.rar", i + 1)), volume).unwrap();
+        }
+        for (r, rev) in rev5_files(&original, 2).iter().enumerate() {
+            fs::write(dir.join(format!("a.part{}.rev", r + 4)), rev).unwrap();
+        }
+        // Another set in the same directory.
+        fs::write(dir.join("ab.part1.rev"), b"not ours").unwrap();
+
+        let first = dir.join("a.part1.rar");
+        assert_eq!(
+            block_on(rebuild_volumes(&first)).unwrap(),
+            RebuildReport::default()
+        );
+
+        fs::remove_file(dir.join("a.part2.rar")).unwrap();
+        let mut damaged = original[2].clone();
+        damaged[100] ^= 1;
+        fs::write(dir.join("a.part3.rar"), &damaged).unwrap();
+        let report = block_on(rebuild_volumes(&first)).unwrap();
+        assert_eq!(
+            report.rebuilt,
+            vec![dir.join("a.part2.rar"), dir.join("a.part3.rar")]
+        );
+        assert_eq!(report.kept, vec![dir.join("a.part3.rar.bad")]);
+        for (i, volume) in original.iter().enumerate() {
+            assert_eq!(
+                &fs::read(dir.join(format!("a.part{}.rar", i + 1))).unwrap(),
+                volume
+            );
+        }
+        assert_eq!(fs::read(dir.join("a.part3.rar.bad")).unwrap(), damaged);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_leaves_set_alone_without_enough_recovery_volumes() {
+        let dir = scratch("rebuild5-short");
+        let original = volumes();
+        for (i, volume) in original.iter().enumerate().skip(2) {
+            fs::write(dir.join(format!("a.part{}.rar", i + 1)), volume).unwrap();
+        }
+        let revs = rev5_files(&original, 2);
+        fs::write(dir.join("a.part4.rev"), &revs[0]).unwrap();
+
+        assert!(block_on(rebuild_volumes(&dir.join("a.part1.rar"))).is_err());
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.part3.rar", "a.part4.rev"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_set_prefix() {
+        let prefix = |name: &str| set_prefix(Path::new(name));
+        assert_eq!(prefix("dir/a.part01.rar").unwrap(), "a.part");
+        assert_eq!(prefix("A.RAR").unwrap(), "a");
+        assert!(prefix("a.zip").is_none());
+    }
+}
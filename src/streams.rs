@@ -0,0 +1,192 @@
+// NTFS alternate data streams. Archives made on Windows store them as "STM"
+// service blocks following the file they belong to. The stream name is
+// UTF-16 in RAR 1.5-4.x service headers and UTF-8 in RAR5 ones, and usually
+// looks like ":Zone.Identifier:$DATA".
+//
+// Linux has no alternate streams, so they can be written either as sidecar
+// files named `file:stream` or as `user.stream` extended attributes.
+
+use crate::error::{Error, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use std::path::{Path, PathBuf};
+
+// Service type of NTFS stream blocks.
+pub const STREAM_SERVICE: &[u8] = b"STM";
+
+// Type suffix of the default data stream type.
+const DATA_STREAM_TYPE: &str = ":$DATA";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct NamedStream {
+    // The name without the leading colon and the ":$DATA" type.
+    pub name: String,
+    pub size: u64,
+    // Contents of stored streams. Compressed and encrypted streams can't be
+    // unpacked yet and are only listed.
+    pub data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StreamMode {
+    Skip,
+    Sidecar,
+    Xattr,
+}
+
+impl StreamMode {
+    pub fn from_name(name: &str) -> Option<StreamMode> {
+        match name {
+            "skip" => Some(StreamMode::Skip),
+            "sidecar" => Some(StreamMode::Sidecar),
+            "xattr" => Some(StreamMode::Xattr),
+            _ => None,
+        }
+    }
+}
+
+// Decode the UTF-16LE stream name of a RAR 1.5-4.x service header.
+pub fn utf16_stream_name(raw: &[u8]) -> Result<String> {
+    let mut units = vec![0u16; raw.len() / 2];
+    LittleEndian::read_u16_into(&raw[..units.len() * 2], &mut units);
+    while units.last() == Some(&0) {
+        units.pop();
+    }
+    clean_stream_name(&String::from_utf16_lossy(&units))
+}
+
+// Decode the UTF-8 stream name of a RAR5 service header.
+pub fn utf8_stream_name(raw: &[u8]) -> Result<String> {
+    clean_stream_name(&String::from_utf8_lossy(raw))
+}
+
+fn clean_stream_name(name: &str) -> Result<String> {
+    let name = name.trim_start_matches(':');
+    let name = name.strip_suffix(DATA_STREAM_TYPE).unwrap_or(name);
+    // The name ends up in a file name or attribute name, so refuse anything
+    // that could point elsewhere.
+    if name.is_empty() || name.contains(&['/', '\\', '\0'][..]) {
+        return Err(Error::bad_block(format!("Invalid stream name {:?}", name)));
+    }
+    Ok(name.to_string())
+}
+
+pub fn sidecar_path(file: &Path, stream: &str) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(":");
+    name.push(stream);
+    PathBuf::from(name)
+}
+
+pub fn xattr_name(stream: &str) -> String {
+    format!("user.{}", stream)
+}
+
+// Write `stream` of the already extracted `file` the way `mode` asks for.
+pub async fn write_stream(mode: StreamMode, file: &Path, stream: &NamedStream) -> Result<()> {
+    if mode == StreamMode::Skip {
+        return Ok(());
+    }
+    let data = stream.data.as_ref().ok_or_else(|| {
+        Error::unsupported(format!("Compressed or encrypted stream {}", stream.name))
+    })?;
+    match mode {
+        StreamMode::Skip => Ok(()),
+        StreamMode::Sidecar => {
+            std::fs::write(sidecar_path(file, &stream.name), data)?;
+            Ok(())
+        }
+        StreamMode::Xattr => set_xattr(file, &xattr_name(&stream.name), data),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_xattr(file: &Path, name: &str, value: &[u8]) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(file.as_os_str().as_bytes())
+        .map_err(|_| Error::bad_data(format!("Invalid path {:?}", file)))?;
+    let name = CString::new(name)
+        .map_err(|_| Error::bad_data(format!("Invalid attribute name {:?}", name)))?;
+    let ret = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_xattr(_file: &Path, _name: &str, _value: &[u8]) -> Result<()> {
+    Err(Error::unsupported(
+        "Extended attributes on this platform".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .flat_map(|u| u.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_names_drop_colon_and_type() {
+        let name = utf16(":Zone.Identifier:$DATA");
+        assert_eq!(utf16_stream_name(&name).unwrap(), "Zone.Identifier");
+        assert_eq!(utf8_stream_name(b":thumb").unwrap(), "thumb");
+    }
+
+    #[test]
+    fn test_stream_names_must_stay_in_place() {
+        assert!(utf8_stream_name(b":../../etc/passwd").is_err());
+        assert!(utf8_stream_name(b":a\\b").is_err());
+        assert!(utf16_stream_name(&utf16(":")).is_err());
+    }
+
+    #[test]
+    fn test_sidecar_and_xattr_names() {
+        let path = sidecar_path(Path::new("dir/file.txt"), "Zone.Identifier");
+        assert_eq!(path, PathBuf::from("dir/file.txt:Zone.Identifier"));
+        assert_eq!(xattr_name("thumb"), "user.thumb");
+    }
+
+    #[test]
+    fn test_writes_sidecar() {
+        let dir = std::env::temp_dir().join(format!("roar-streams-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("file.txt");
+        let stream = NamedStream {
+            name: "extra".into(),
+            size: 4,
+            data: Some(b"roar".to_vec()),
+        };
+        block_on(write_stream(StreamMode::Sidecar, &file, &stream)).unwrap();
+        let written = std::fs::read(dir.join("file.txt:extra")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, b"roar".to_vec());
+    }
+
+    #[test]
+    fn test_compressed_streams_are_unsupported() {
+        let stream = NamedStream {
+            name: "extra".into(),
+            size: 4,
+            data: None,
+        };
+        let path = Path::new("missing");
+        assert!(block_on(write_stream(StreamMode::Skip, path, &stream)).is_ok());
+        assert!(block_on(write_stream(StreamMode::Sidecar, path, &stream)).is_err());
+    }
+}
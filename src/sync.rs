@@ -0,0 +1,274 @@
+// Blocking API over std::io, for callers without an executor. The async
+// readers only return Pending when the underlying reader does, which a
+// blocking std::io reader never does, so polling each future once with a
+// no-op waker runs it to completion.
+
+use crate::archive::{self, Entry, Format, IndexEntry, Versions};
+use crate::block::{Authenticity, Block};
+use crate::error::{Error, Result};
+use crate::extract::{ExtractOptions, ExtractReport};
+use crate::integrity::TestReport;
+use crate::listing::ListEntry;
+use crate::recovery::RepairReport;
+use crate::recvol::{self, RebuildReport};
+use futures::io::{AllowStdIo, AsyncRead};
+use futures::task::{noop_waker_ref, Context, Poll};
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::pin::Pin;
+
+// Run a future over blocking readers to completion.
+pub fn complete<T, F: Future<Output = Result<T>>>(f: F) -> Result<T> {
+    let mut f = Box::pin(f);
+    let mut cx = Context::from_waker(noop_waker_ref());
+    match f.as_mut().poll(&mut cx) {
+        Poll::Ready(result) => result,
+        Poll::Pending => Err(Error::unsupported("Non-blocking readers".into())),
+    }
+}
+
+// A std::io::Read over an async reader that wraps blocking readers, like the
+// entry readers.
+pub struct Reader<R> {
+    inner: R,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader { inner }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        match Pin::new(&mut self.inner).poll_read(&mut cx, buf) {
+            Poll::Ready(result) => result,
+            Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+// Rebuild the missing or damaged volumes of the set starting with `first`
+// from the recovery volumes next to it.
+pub fn rebuild_volumes(first: &Path) -> Result<RebuildReport> {
+    complete(recvol::rebuild_volumes(first))
+}
+
+// An opened archive read through std::io. Listing works the same as with
+// `archive::Archive`; the async header readers for RAR 1.4 and RAR5 run
+// against `as_async` through `complete`.
+pub struct Archive<R> {
+    inner: archive::Archive<AllowStdIo<R>>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn open(inner: R) -> Result<Archive<R>> {
+        Archive::open_with_sfx_limit(inner, archive::DEFAULT_SFX_SCAN_LIMIT)
+    }
+
+    pub fn open_with_sfx_limit(inner: R, sfx_limit: u64) -> Result<Archive<R>> {
+        let inner = complete(archive::Archive::open_with_sfx_limit(
+            AllowStdIo::new(inner),
+            sfx_limit,
+        ))?;
+        Ok(Archive { inner })
+    }
+
+    // Open an archive using an index saved with `export_index`.
+    pub fn open_with_index(inner: R, index: &[u8]) -> Result<Archive<R>> {
+        let inner = complete(archive::Archive::open_with_index(
+            AllowStdIo::new(inner),
+            index,
+        ))?;
+        Ok(Archive { inner })
+    }
+
+    pub fn format(&self) -> Format {
+        self.inner.format()
+    }
+
+    pub fn sfx_size(&self) -> u64 {
+        self.inner.sfx_size()
+    }
+
+    pub fn is_sfx(&self) -> bool {
+        self.inner.is_sfx()
+    }
+
+    pub fn set_versions(&mut self, versions: Versions) {
+        self.inner.set_versions(versions)
+    }
+
+    pub fn set_include_deleted(&mut self, include_deleted: bool) {
+        self.inner.set_include_deleted(include_deleted)
+    }
+
+    pub fn read_block(&mut self) -> Result<Block> {
+        complete(self.inner.read_block())
+    }
+
+    pub fn read_entry(&mut self) -> Result<Option<Entry>> {
+        complete(self.inner.read_entry())
+    }
+
+    pub fn entries(&mut self) -> Entries<'_, R> {
+        Entries { archive: self }
+    }
+
+    // Walk the rest of the archive, seeking past file data, and return the
+    // index of all files.
+    pub fn build_index(&mut self) -> Result<&[IndexEntry]> {
+        complete(self.inner.build_index())
+    }
+
+    pub fn index(&self) -> &[IndexEntry] {
+        self.inner.index()
+    }
+
+    pub fn export_index(&mut self) -> Result<Vec<u8>> {
+        complete(self.inner.export_index())
+    }
+
+    // Write the contents of the file named `name` to `out`, or return `None`
+    // if there's no such file.
+    pub fn extract_by_name(&mut self, name: &str, out: &mut impl Write) -> Result<Option<u64>> {
+        let reader = match complete(self.inner.open_entry_by_name(name))? {
+            Some(reader) => reader,
+            None => return Ok(None),
+        };
+        Ok(Some(io::copy(&mut Reader::new(reader), out)?))
+    }
+
+    // List every file, in any of the formats.
+    pub fn list(&mut self) -> Result<Vec<ListEntry>> {
+        complete(self.inner.list())
+    }
+
+    // Unpack every file and check it, without writing anything.
+    pub fn test(&mut self) -> Result<TestReport> {
+        complete(self.inner.test())
+    }
+
+    // Like `test`, decrypting RAR5 files and headers with `password`.
+    pub fn test_with_password(&mut self, password: Option<&str>) -> Result<TestReport> {
+        complete(self.inner.test_with_password(password))
+    }
+
+    // Write a copy of the archive to `out` with every damaged sector its
+    // recovery record can rebuild repaired.
+    pub fn repair(&mut self, out: &mut impl Write) -> Result<RepairReport> {
+        complete(self.inner.repair(&mut AllowStdIo::new(out)))
+    }
+
+    // Extract the files picked by `options` into its output directory.
+    pub fn extract_all(&mut self, options: &ExtractOptions) -> Result<ExtractReport> {
+        complete(self.inner.extract_all(options))
+    }
+
+    pub fn authenticity(&self) -> Option<&Authenticity> {
+        self.inner.authenticity()
+    }
+
+    pub fn as_async(&mut self) -> &mut archive::Archive<AllowStdIo<R>> {
+        &mut self.inner
+    }
+
+    // Write the contents of `entry` to `out`, checking them against the
+    // stored checksum, and return how many bytes were written. Entries can
+    // be extracted in any order, while listing or afterwards.
+    pub fn extract(&mut self, entry: &Entry, out: &mut impl Write) -> Result<u64> {
+        let file = self.inner.get_mut().get_mut();
+        let resume = file.stream_position()?;
+        file.seek(SeekFrom::Start(entry.data_offset))?;
+        let copied = entry
+            .file
+            .reader(AllowStdIo::new(&mut *file))
+            .and_then(|reader| Ok(io::copy(&mut Reader::new(reader), out)?));
+        file.seek(SeekFrom::Start(resume))?;
+        copied
+    }
+}
+
+pub struct Entries<'a, R> {
+    archive: &'a mut Archive<R>,
+}
+
+impl<'a, R: Read + Seek> Iterator for Entries<'a, R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Result<Entry>> {
+        self.archive.read_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::RAR15_SIGNATURE;
+    use crate::testing::file_block;
+    use std::io::Cursor;
+
+    fn archive() -> Vec<u8> {
+        let mut buf = b"MZ stub".to_vec();
+        buf.extend(&RAR15_SIGNATURE);
+        buf.extend(file_block(b"a.txt", b"roar"));
+        buf.extend(file_block(b"b.txt", b"meow meow"));
+        buf
+    }
+
+    #[test]
+    fn test_lists_and_extracts_without_executor() {
+        let mut archive = Archive::open(Cursor::new(archive())).unwrap();
+        assert_eq!(archive.format(), Format::Rar15);
+        assert_eq!(archive.sfx_size(), 7);
+
+        let entries: Vec<_> = archive.entries().map(|e| e.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].file.header().name(), "b.txt");
+
+        // Out of order, after listing.
+        let mut out = Vec::new();
+        assert_eq!(archive.extract(&entries[1], &mut out).unwrap(), 9);
+        assert_eq!(out, b"meow meow");
+        out.clear();
+        archive.extract(&entries[0], &mut out).unwrap();
+        assert_eq!(out, b"roar");
+    }
+
+    #[test]
+    fn test_extract_by_name() {
+        let data = archive();
+        let mut archive = Archive::open(Cursor::new(data.clone())).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(archive.extract_by_name("b.txt", &mut out).unwrap(), Some(9));
+        assert_eq!(out, b"meow meow");
+        assert_eq!(archive.index().len(), 2);
+        assert!(archive
+            .extract_by_name("c.txt", &mut out)
+            .unwrap()
+            .is_none());
+
+        let saved = archive.export_index().unwrap();
+        let mut archive = Archive::open_with_index(Cursor::new(data), &saved).unwrap();
+        out.clear();
+        assert_eq!(archive.extract_by_name("a.txt", &mut out).unwrap(), Some(4));
+        assert_eq!(out, b"roar");
+    }
+
+    #[test]
+    fn test_extract_checks_crc() {
+        let mut data = archive();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let mut archive = Archive::open(Cursor::new(data)).unwrap();
+        let entries: Vec<_> = archive.entries().map(|e| e.unwrap()).collect();
+        assert!(archive.extract(&entries[1], &mut Vec::new()).is_err());
+        assert!(archive.extract(&entries[0], &mut Vec::new()).is_ok());
+    }
+}
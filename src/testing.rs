@@ -0,0 +1,220 @@
+// Builders for the archives used in tests.
+
+use crate::crypt::{Rar5Key, AES_BLOCK_SIZE};
+use crate::hash::checksum14;
+use crate::rar5::write_vint;
+use crate::recvol::{Rs16Coder, REV5_SIGNATURE};
+use byteorder::{ByteOrder, LittleEndian};
+
+// A RAR 1.5-4.x file header, or service header with `head_type` 0x7a,
+// followed by its stored data. The header CRC is filled in.
+pub(crate) struct FileBlock<'a> {
+    pub head_type: u8,
+    // Block flags besides HAS_ADD_SIZE, which is always set.
+    pub flags: u16,
+    pub name: &'a [u8],
+    pub sub_data: &'a [u8],
+    pub data: &'a [u8],
+    // The size of `data` if left out.
+    pub unpacked_size: Option<u32>,
+    pub host_os: u8,
+    // The CRC32 of `data` if left out.
+    pub file_crc: Option<u32>,
+    pub mtime: u32,
+    pub method: u8,
+    pub attributes: u32,
+}
+
+impl<'a> FileBlock<'a> {
+    // A stored file archived on Windows.
+    pub fn new(name: &'a [u8], data: &'a [u8]) -> FileBlock<'a> {
+        FileBlock {
+            head_type: 0x74,
+            flags: 0,
+            name,
+            sub_data: b"",
+            data,
+            unpacked_size: None,
+            host_os: 2,
+            file_crc: None,
+            mtime: 0,
+            method: 0x30,
+            attributes: 0x20,
+        }
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut buf = vec![0, 0, self.head_type];
+        buf.extend(&(self.flags | 0x8000).to_le_bytes());
+        buf.extend(&((32 + self.name.len() + self.sub_data.len()) as u16).to_le_bytes());
+        buf.extend(&(self.data.len() as u32).to_le_bytes());
+        let unpacked_size = self.unpacked_size.unwrap_or(self.data.len() as u32);
+        buf.extend(&unpacked_size.to_le_bytes());
+        buf.push(self.host_os);
+        let file_crc = self
+            .file_crc
+            .unwrap_or_else(|| crc::crc32::checksum_ieee(self.data));
+        buf.extend(&file_crc.to_le_bytes());
+        buf.extend(&self.mtime.to_le_bytes());
+        buf.extend(&[29, self.method]);
+        buf.extend(&(self.name.len() as u16).to_le_bytes());
+        buf.extend(&self.attributes.to_le_bytes());
+        buf.extend(self.name);
+        buf.extend(self.sub_data);
+        let mut buf = with_header_crc(buf);
+        buf.extend(self.data);
+        buf
+    }
+}
+
+// A stored file archived on Windows.
+pub(crate) fn file_block(name: &[u8], data: &[u8]) -> Vec<u8> {
+    FileBlock::new(name, data).build()
+}
+
+// A stored service block, like NTFS streams or the Unix owner.
+pub(crate) fn service_block(name: &[u8], sub_data: &[u8], data: &[u8]) -> Vec<u8> {
+    FileBlock {
+        head_type: 0x7a,
+        sub_data,
+        ..FileBlock::new(name, data)
+    }
+    .build()
+}
+
+// Fill in the header CRC of a RAR 1.5-4.x block, made of the header and
+// maybe some of its data.
+pub(crate) fn with_header_crc(mut block: Vec<u8>) -> Vec<u8> {
+    let size = usize::from(u16::from_le_bytes([block[5], block[6]]));
+    let crc = crc::crc32::checksum_ieee(&block[2..size]) as u16;
+    block[..2].copy_from_slice(&crc.to_le_bytes());
+    block
+}
+
+// A RAR 1.4 file header followed by `data`, packed with `method`.
+pub(crate) fn rar14_file_header(name: &str, data: &[u8], method: u8, flags: u8) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend(&(data.len() as u32).to_le_bytes());
+    buf.extend(&(data.len() as u32).to_le_bytes());
+    buf.extend(&checksum14(0, data).to_le_bytes());
+    buf.extend(&(21 + name.len() as u16).to_le_bytes());
+    buf.extend(&[0x21, 0x43, 0x65, 0x27]); // mtime
+    buf.push(0x20); // attributes
+    buf.push(flags);
+    buf.push(2); // unpack version
+    buf.push(name.len() as u8);
+    buf.push(method);
+    buf.extend(name.as_bytes());
+    buf.extend(data);
+    buf
+}
+
+// A whole RAR5 header with its CRC and size from the fields after the size.
+pub(crate) fn rar5_header(body: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::new();
+    write_vint(body.len() as u64, &mut raw);
+    raw.extend(body);
+    let mut out = crc::crc32::checksum_ieee(&raw).to_le_bytes().to_vec();
+    out.extend(raw);
+    out
+}
+
+// A RAR5 file or service header for `size` bytes of stored data.
+pub(crate) fn rar5_file_header(header_type: u8, name: &str, size: u8, crc: u32) -> Vec<u8> {
+    let mut body = vec![header_type, 0x02, size];
+    body.push(0x04); // file flags: crc32
+    body.push(size);
+    body.push(0x20); // attributes
+    body.extend(&crc.to_le_bytes());
+    body.push(0x00); // compression info: stored
+    body.push(0x01); // unix
+    body.push(name.len() as u8);
+    body.extend(name.as_bytes());
+    rar5_header(&body)
+}
+
+// A RAR5 file or service header followed by `data`, stored.
+pub(crate) fn rar5_stored_header(header_type: u8, name: &str, data: &[u8]) -> Vec<u8> {
+    let mut body = vec![header_type, 0x02];
+    write_vint(data.len() as u64, &mut body);
+    body.push(0x04); // file flags: crc32
+    write_vint(data.len() as u64, &mut body);
+    body.push(0x20); // attributes
+    body.extend(&crc::crc32::checksum_ieee(data).to_le_bytes());
+    body.push(0x00); // compression info: stored
+    body.push(0x01); // unix
+    body.push(name.len() as u8);
+    body.extend(name.as_bytes());
+    let mut out = rar5_header(&body);
+    out.extend(data);
+    out
+}
+
+// Encrypt `plain`, zero padded to whole blocks, with AES-256 in CBC mode.
+pub(crate) fn aes_cbc_encrypt(key: &Rar5Key, iv: &[u8; AES_BLOCK_SIZE], plain: &[u8]) -> Vec<u8> {
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockCipher, NewBlockCipher};
+    let cipher = aes::Aes256::new(GenericArray::from_slice(key.key()));
+    let mut out = Vec::new();
+    let mut prev = *iv;
+    for chunk in plain.chunks(AES_BLOCK_SIZE) {
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        block[..chunk.len()].copy_from_slice(chunk);
+        for (b, p) in block.iter_mut().zip(prev.iter()) {
+            *b ^= p;
+        }
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+        out.extend(&block);
+        prev = block;
+    }
+    out
+}
+
+// RAR5 .rev files for `volumes`, numbered after them.
+pub(crate) fn rev5_files(volumes: &[Vec<u8>], rec_count: usize) -> Vec<Vec<u8>> {
+    let size = volumes.iter().map(|v| v.len()).max().unwrap();
+    let size = size + size % 2;
+    let coder = Rs16Coder::new(volumes.len(), rec_count).unwrap();
+    let mut data: Vec<Vec<u8>> = vec![Vec::new(); rec_count];
+    let mut parity = vec![0u16; rec_count];
+    for pos in (0..size).step_by(2) {
+        let word: Vec<u16> = volumes
+            .iter()
+            .map(|v| {
+                let mut pair = [0u8; 2];
+                for (k, b) in pair.iter_mut().enumerate() {
+                    *b = *v.get(pos + k).unwrap_or(&0);
+                }
+                LittleEndian::read_u16(&pair)
+            })
+            .collect();
+        coder.encode(&word, &mut parity);
+        for (d, p) in data.iter_mut().zip(parity.iter()) {
+            d.extend(&p.to_le_bytes());
+        }
+    }
+
+    data.into_iter()
+        .enumerate()
+        .map(|(r, d)| {
+            let mut header = vec![1];
+            header.extend(&(volumes.len() as u16).to_le_bytes());
+            header.extend(&(rec_count as u16).to_le_bytes());
+            header.extend(&((volumes.len() + r) as u16).to_le_bytes());
+            header.extend(&0x1234_5678u32.to_le_bytes());
+            for v in volumes {
+                header.extend(&(v.len() as u64).to_le_bytes());
+                header.extend(&crc::crc32::checksum_ieee(v).to_le_bytes());
+            }
+            let size = (header.len() as u32).to_le_bytes();
+            let mut checked = size.to_vec();
+            checked.extend(&header);
+            let mut rev = REV5_SIGNATURE.to_vec();
+            rev.extend(&crc::crc32::checksum_ieee(&checked).to_le_bytes());
+            rev.extend(&size);
+            rev.extend(header);
+            rev.extend(d);
+            rev
+        })
+        .collect()
+}
@@ -0,0 +1,154 @@
+// MSB-first bit reader over a growable buffer of packed data.
+//
+// Reads past the end of the buffer return zero bits, so callers are expected
+// to check `available_bytes` before decoding anything they rely on.
+#[derive(Debug, Default)]
+pub struct BitReader {
+    buf: Vec<u8>,
+    // Position in bits from the start of `buf`.
+    pos: usize,
+    // Bytes dropped from the front of `buf` since the last `clear`.
+    discarded: usize,
+}
+
+impl BitReader {
+    pub fn new() -> BitReader {
+        BitReader::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        // Drop fully consumed bytes once they make up most of the buffer.
+        let consumed = self.pos / 8;
+        if consumed > 0x10000 && consumed * 2 > self.buf.len() {
+            self.buf.drain(..consumed);
+            self.pos -= consumed * 8;
+            self.discarded += consumed;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+        self.discarded = 0;
+    }
+
+    fn byte(&self, idx: usize) -> u32 {
+        u32::from(self.buf.get(idx).cloned().unwrap_or(0))
+    }
+
+    // Offset of the byte holding the next unread bit, counted from the first
+    // byte fed since the last `clear`.
+    pub fn byte_pos(&self) -> usize {
+        self.discarded + self.pos / 8
+    }
+
+    // Position in bits, relative to the same origin as `byte_pos`.
+    pub fn bit_pos(&self) -> usize {
+        self.discarded * 8 + self.pos
+    }
+
+    pub fn available_bytes(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos / 8)
+    }
+
+    // The next `n` bytes starting at the current byte, if they have been fed.
+    pub fn peek_bytes(&self, n: usize) -> Option<&[u8]> {
+        let idx = self.pos / 8;
+        self.buf.get(idx..idx + n)
+    }
+
+    // Peek at the next 16 bits.
+    pub fn peek16(&self) -> u32 {
+        let idx = self.pos / 8;
+        let v = (self.byte(idx) << 16) | (self.byte(idx + 1) << 8) | self.byte(idx + 2);
+        (v >> (8 - (self.pos & 7))) & 0xffff
+    }
+
+    // Peek at the next 32 bits.
+    pub fn peek32(&self) -> u32 {
+        let idx = self.pos / 8;
+        let v = (self.byte(idx) << 24)
+            | (self.byte(idx + 1) << 16)
+            | (self.byte(idx + 2) << 8)
+            | self.byte(idx + 3);
+        let shift = self.pos & 7;
+        if shift == 0 {
+            v
+        } else {
+            (v << shift) | (self.byte(idx + 4) >> (8 - shift))
+        }
+    }
+
+    pub fn skip(&mut self, bits: usize) {
+        self.pos += bits;
+    }
+
+    // Read up to 16 bits.
+    pub fn read(&mut self, bits: u32) -> u32 {
+        debug_assert!(bits > 0 && bits <= 16);
+        let v = self.peek16() >> (16 - bits);
+        self.skip(bits as usize);
+        v
+    }
+
+    // Read up to 32 bits.
+    pub fn read32(&mut self, bits: u32) -> u32 {
+        debug_assert!(bits > 0 && bits <= 32);
+        let v = if bits == 32 {
+            self.peek32()
+        } else {
+            self.peek32() >> (32 - bits)
+        };
+        self.skip(bits as usize);
+        v
+    }
+
+    pub fn align_to_byte(&mut self) {
+        self.pos = (self.pos + 7) & !7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(data: &[u8]) -> BitReader {
+        let mut r = BitReader::new();
+        r.feed(data);
+        r
+    }
+
+    #[test]
+    fn test_read_is_msb_first() {
+        let mut r = reader(&[0b1010_0000, 0xff]);
+        assert_eq!(r.read(1), 1);
+        assert_eq!(r.read(1), 0);
+        assert_eq!(r.read(2), 0b10);
+        assert_eq!(r.read(8), 0b0000_1111);
+    }
+
+    #[test]
+    fn test_peek32_unaligned() {
+        let mut r = reader(&[0x12, 0x34, 0x56, 0x78, 0x9a]);
+        r.skip(4);
+        assert_eq!(r.peek32(), 0x2345_6789);
+    }
+
+    #[test]
+    fn test_reads_past_end_are_zero() {
+        let mut r = reader(&[0xff]);
+        assert_eq!(r.read(16), 0xff00);
+        assert_eq!(r.available_bytes(), 0);
+    }
+
+    #[test]
+    fn test_align_to_byte() {
+        let mut r = reader(&[0, 0]);
+        r.skip(3);
+        r.align_to_byte();
+        assert_eq!(r.byte_pos(), 1);
+        r.align_to_byte();
+        assert_eq!(r.byte_pos(), 1);
+    }
+}
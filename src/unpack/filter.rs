@@ -0,0 +1,170 @@
+use crate::error::{Error, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use num::FromPrimitive;
+
+// Filters longer than this are rejected, matching the limit RAR itself uses.
+pub const MAX_FILTER_BLOCK_SIZE: u64 = 0x40_0000;
+
+#[derive(Debug, Copy, Clone, FromPrimitive, Eq, PartialEq)]
+pub enum FilterType {
+    Delta = 0,
+    E8 = 1,
+    E8E9 = 2,
+    Arm = 3,
+}
+
+impl FilterType {
+    pub fn from_u32(that: u32) -> Option<FilterType> {
+        FromPrimitive::from_u32(that)
+    }
+}
+
+// A transform applied to a range of the unpacked output once it has been
+// fully decoded. Offsets are relative to the start of the current entry.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Filter {
+    pub filter_type: FilterType,
+    pub start: u64,
+    pub length: u64,
+    pub channels: u32,
+}
+
+impl Filter {
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+
+    // Undo the filter in place over `data`, which must be exactly `length`
+    // bytes taken from offset `start` of the entry.
+    pub fn apply(&self, data: &mut [u8]) -> Result<()> {
+        debug_assert_eq!(data.len() as u64, self.length);
+        match self.filter_type {
+            FilterType::Delta => delta(data, self.channels as usize),
+            FilterType::E8 => e8e9(data, self.start as u32, false),
+            FilterType::E8E9 => e8e9(data, self.start as u32, true),
+            FilterType::Arm => arm(data, self.start as u32),
+        }
+    }
+}
+
+fn delta(data: &mut [u8], channels: usize) -> Result<()> {
+    if channels == 0 {
+        return Err(Error::bad_data("Delta filter with no channels".into()));
+    }
+    // The packed form stores each channel contiguously; interleave them
+    // back while undoing the byte differences.
+    let src = data.to_vec();
+    let mut src_pos = 0;
+    for channel in 0..channels {
+        let mut prev: u8 = 0;
+        let mut dst_pos = channel;
+        while dst_pos < data.len() {
+            prev = prev.wrapping_sub(src[src_pos]);
+            data[dst_pos] = prev;
+            src_pos += 1;
+            dst_pos += channels;
+        }
+    }
+    Ok(())
+}
+
+fn e8e9(data: &mut [u8], file_offset: u32, include_e9: bool) -> Result<()> {
+    const FILE_SIZE: u32 = 0x100_0000;
+    let cmp_byte2 = if include_e9 { 0xe9 } else { 0xe8 };
+
+    let mut pos = 0;
+    while pos + 4 < data.len() {
+        let byte = data[pos];
+        pos += 1;
+        if byte != 0xe8 && byte != cmp_byte2 {
+            continue;
+        }
+
+        let offset = pos as u32 % FILE_SIZE;
+        let offset = offset.wrapping_add(file_offset) % FILE_SIZE;
+        let addr = LittleEndian::read_u32(&data[pos..pos + 4]);
+        if addr & 0x8000_0000 != 0 {
+            if addr.wrapping_add(offset) & 0x8000_0000 == 0 {
+                LittleEndian::write_u32(&mut data[pos..pos + 4], addr.wrapping_add(FILE_SIZE));
+            }
+        } else if addr.wrapping_sub(FILE_SIZE) & 0x8000_0000 != 0 {
+            LittleEndian::write_u32(&mut data[pos..pos + 4], addr.wrapping_sub(offset));
+        }
+        pos += 4;
+    }
+    Ok(())
+}
+
+fn arm(data: &mut [u8], file_offset: u32) -> Result<()> {
+    let mut pos = 0;
+    while pos + 3 < data.len() {
+        if data[pos + 3] == 0xeb {
+            let offset = u32::from(data[pos])
+                | (u32::from(data[pos + 1]) << 8)
+                | (u32::from(data[pos + 2]) << 16);
+            let offset = offset.wrapping_sub(file_offset.wrapping_add(pos as u32) / 4);
+            data[pos] = offset as u8;
+            data[pos + 1] = (offset >> 8) as u8;
+            data[pos + 2] = (offset >> 16) as u8;
+        }
+        pos += 4;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(filter_type: FilterType, start: u64, length: u64, channels: u32) -> Filter {
+        Filter {
+            filter_type,
+            start,
+            length,
+            channels,
+        }
+    }
+
+    #[test]
+    fn test_delta_single_channel() {
+        // Each stored byte is the negated difference from the previous one.
+        let mut data = vec![0u8.wrapping_sub(1), 0u8.wrapping_sub(1), 0u8.wrapping_sub(1)];
+        filter(FilterType::Delta, 0, 3, 1).apply(&mut data).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_delta_interleaves_channels() {
+        let neg = |x: u8| 0u8.wrapping_sub(x);
+        let mut data = vec![neg(1), neg(1), neg(10), neg(10)];
+        filter(FilterType::Delta, 0, 4, 2).apply(&mut data).unwrap();
+        assert_eq!(data, vec![1, 10, 2, 20]);
+    }
+
+    #[test]
+    fn test_e8_converts_absolute_call_to_relative() {
+        // A call at offset 0 whose absolute target is 0x100 becomes a
+        // relative displacement of 0x100 - 1.
+        let mut data = vec![0xe8, 0x00, 0x01, 0x00, 0x00, 0x90];
+        filter(FilterType::E8, 0, 6, 0).apply(&mut data).unwrap();
+        assert_eq!(data, vec![0xe8, 0xff, 0x00, 0x00, 0x00, 0x90]);
+    }
+
+    #[test]
+    fn test_e8_ignores_e9_unless_asked() {
+        let original = vec![0xe9, 0x00, 0x01, 0x00, 0x00, 0x90];
+        let mut data = original.clone();
+        filter(FilterType::E8, 0, 6, 0).apply(&mut data).unwrap();
+        assert_eq!(data, original);
+
+        filter(FilterType::E8E9, 0, 6, 0).apply(&mut data).unwrap();
+        assert_eq!(data, vec![0xe9, 0xff, 0x00, 0x00, 0x00, 0x90]);
+    }
+
+    #[test]
+    fn test_arm_converts_bl_target() {
+        let mut data = vec![0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0xeb];
+        filter(FilterType::Arm, 0, 8, 0).apply(&mut data).unwrap();
+        assert_eq!(data, vec![0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0xeb]);
+    }
+}
@@ -0,0 +1,159 @@
+use super::bits::BitReader;
+use crate::error::{Error, Result};
+
+// Longest code length RAR uses for any of its tables.
+pub const MAX_CODE_LENGTH: usize = 15;
+
+// Codes up to this many bits are resolved with a single table lookup.
+const QUICK_BITS: u32 = 10;
+
+// Canonical Huffman decoder built from a list of code lengths, the way RAR
+// stores its tables: codes are assigned in order of increasing length, and
+// in symbol order within a length.
+#[derive(Debug, Clone)]
+pub struct Huffman {
+    // Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+
+    // Symbols sorted by code length, then by value.
+    symbols: Vec<u16>,
+
+    // (symbol, length) for every QUICK_BITS prefix; length 0 means the code is
+    // longer than QUICK_BITS and needs the slow path.
+    quick: Vec<(u16, u8)>,
+}
+
+impl Huffman {
+    pub fn new(lengths: &[u8]) -> Result<Huffman> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in lengths {
+            if usize::from(len) > MAX_CODE_LENGTH {
+                return Err(Error::bad_data(format!("Huffman code length {}", len)));
+            }
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        // Reject oversubscribed tables; incomplete ones are legal.
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(Error::bad_data("Oversubscribed Huffman table".into()));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+        for len in 1..=MAX_CODE_LENGTH {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_CODE_LENGTH + 1])];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                let slot = &mut offsets[usize::from(len)];
+                symbols[usize::from(*slot)] = symbol as u16;
+                *slot += 1;
+            }
+        }
+
+        let mut huffman = Huffman {
+            counts,
+            symbols,
+            quick: vec![(0, 0); 1 << QUICK_BITS],
+        };
+        huffman.build_quick_table();
+        Ok(huffman)
+    }
+
+    fn build_quick_table(&mut self) {
+        let mut code: u32 = 0;
+        let mut index = 0;
+        for len in 1..=QUICK_BITS {
+            for _ in 0..self.counts[len as usize] {
+                let symbol = self.symbols[index];
+                let fill = 1 << (QUICK_BITS - len);
+                let start = (code << (QUICK_BITS - len)) as usize;
+                for entry in &mut self.quick[start..start + fill] {
+                    *entry = (symbol, len as u8);
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+    }
+
+    pub fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        let peek = bits.peek16();
+        let (symbol, len) = self.quick[(peek >> (16 - QUICK_BITS)) as usize];
+        if len != 0 {
+            bits.skip(usize::from(len));
+            return Ok(symbol);
+        }
+
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_CODE_LENGTH {
+            code |= ((peek >> (16 - len)) & 1) as i32;
+            let count = i32::from(self.counts[len]);
+            if code - first < count {
+                bits.skip(len);
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::bad_data("Invalid Huffman code".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(data: &[u8]) -> BitReader {
+        let mut r = BitReader::new();
+        r.feed(data);
+        r
+    }
+
+    #[test]
+    fn test_decodes_canonical_codes() {
+        // Lengths 2, 1, 3, 3 give codes 10, 0, 110, 111.
+        let h = Huffman::new(&[2, 1, 3, 3]).unwrap();
+        let mut r = reader(&[0b0101_1011, 0b1000_0000]);
+        assert_eq!(h.decode(&mut r).unwrap(), 1);
+        assert_eq!(h.decode(&mut r).unwrap(), 0);
+        assert_eq!(h.decode(&mut r).unwrap(), 2);
+        assert_eq!(h.decode(&mut r).unwrap(), 3);
+        assert_eq!(r.bit_pos(), 9);
+    }
+
+    #[test]
+    fn test_decodes_long_codes() {
+        let mut lengths = vec![0u8; 16];
+        lengths[0] = 1;
+        for (i, len) in lengths.iter_mut().enumerate().skip(1) {
+            *len = if i == 15 { 15 } else { i as u8 + 1 };
+        }
+        lengths[14] = 15;
+        let h = Huffman::new(&lengths).unwrap();
+        // Fourteen ones followed by a zero is the first 15 bit code.
+        let mut r = reader(&[0xff, 0xfc]);
+        assert_eq!(h.decode(&mut r).unwrap(), 14);
+    }
+
+    #[test]
+    fn test_rejects_oversubscribed_table() {
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_incomplete_table_errors_on_missing_code() {
+        let h = Huffman::new(&[1]).unwrap();
+        let mut r = reader(&[0xff, 0xff]);
+        assert!(h.decode(&mut r).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+mod bits;
+mod filter;
+mod huffman;
+mod rar5;
+mod reader;
+
+pub use rar5::{CompressionInfo, Unpack50};
+pub use reader::EntryReader;
+
+use crate::error::Result;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    // Some bytes were written into the output buffer.
+    Output(usize),
+
+    // More packed data must be fed in before anything else can be produced.
+    NeedInput,
+
+    // The entry has been fully unpacked.
+    Done,
+}
+
+// Something that turns the packed bytes of an entry into its unpacked
+// contents. Packed data is pushed in with `feed` and unpacked data pulled out
+// with `unpack`, which lets `EntryReader` drive any decoder the same way.
+pub trait Unpacker {
+    fn feed(&mut self, data: &[u8]);
+
+    // Called once all packed data for the entry has been fed.
+    fn finish(&mut self);
+
+    fn unpack(&mut self, out: &mut [u8]) -> Result<Status>;
+}
+
+// Unpacker for entries stored without compression (method 0x30 / 0).
+#[derive(Debug, Default)]
+pub struct Store {
+    buf: Vec<u8>,
+    pos: usize,
+    finished: bool,
+    // Bytes still to hand out, when the unpacked size is known. Encrypted
+    // entries are padded, so their packed size overshoots it.
+    remaining: Option<u64>,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store::default()
+    }
+
+    pub fn with_size(unpacked_size: u64) -> Store {
+        Store {
+            remaining: Some(unpacked_size),
+            ..Store::default()
+        }
+    }
+}
+
+impl Unpacker for Store {
+    fn feed(&mut self, data: &[u8]) {
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    fn unpack(&mut self, out: &mut [u8]) -> Result<Status> {
+        if self.remaining == Some(0) {
+            return Ok(Status::Done);
+        }
+        let available = &self.buf[self.pos..];
+        if available.is_empty() {
+            return Ok(if self.finished {
+                Status::Done
+            } else {
+                Status::NeedInput
+            });
+        }
+        let mut n = available.len().min(out.len());
+        if let Some(ref mut remaining) = self.remaining {
+            n = n.min(*remaining as usize);
+            *remaining -= n as u64;
+        }
+        out[..n].copy_from_slice(&available[..n]);
+        self.pos += n;
+        Ok(Status::Output(n))
+    }
+}
@@ -0,0 +1,826 @@
+use super::bits::BitReader;
+use super::filter::{Filter, FilterType, MAX_FILTER_BLOCK_SIZE};
+use super::huffman::Huffman;
+use super::{Status, Unpacker};
+use crate::error::{Error, Result};
+use std::collections::VecDeque;
+
+// Sizes of the symbol alphabets making up a RAR5 Huffman table.
+const NC: usize = 306;
+const DC: usize = 64;
+const LDC: usize = 16;
+const RC: usize = 44;
+const BC: usize = 20;
+const HUFF_TABLE_SIZE: usize = NC + DC + LDC + RC;
+
+const MAX_UNPACK_FILTERS: usize = 8192;
+
+// Largest dictionary the version 50 algorithm can address.
+pub const MAX_DICTIONARY_SIZE: u64 = 0x1_0000_0000;
+
+// Windows for small non-solid entries are shrunk, but never below this.
+const MIN_WINDOW_SIZE: u64 = 0x4_0000;
+
+// The "compression information" field of a RAR5 file header.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CompressionInfo {
+    // Algorithm version, 0 for the version 50 algorithm.
+    pub version: u8,
+
+    // Entry continues the dictionary and tables of the previous entry.
+    pub solid: bool,
+
+    // 0 for stored entries, 1 (fastest) to 5 (best) otherwise.
+    pub method: u8,
+
+    pub dictionary_size: u64,
+}
+
+impl CompressionInfo {
+    pub fn from_raw(raw: u64) -> Result<CompressionInfo> {
+        let version = (raw & 0x3f) as u8;
+        if version != 0 {
+            return Err(Error::bad_data(format!(
+                "Unsupported RAR5 algorithm version {}",
+                version
+            )));
+        }
+
+        let dictionary_bits = (raw >> 10) & 0x1f;
+        if dictionary_bits > 15 {
+            return Err(Error::bad_data(format!(
+                "Dictionary size exponent {} too large",
+                dictionary_bits
+            )));
+        }
+
+        Ok(CompressionInfo {
+            version,
+            solid: raw & 0x40 != 0,
+            method: ((raw >> 7) & 7) as u8,
+            dictionary_size: 0x2_0000 << dictionary_bits,
+        })
+    }
+
+    pub fn is_stored(&self) -> bool {
+        self.method == 0
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct BlockHeader {
+    // Bit position just past the last bit of the block.
+    end_bits: usize,
+    last: bool,
+}
+
+#[derive(Debug)]
+struct Tables {
+    main: Huffman,
+    dist: Huffman,
+    low_dist: Huffman,
+    len: Huffman,
+}
+
+// Decoder for the RAR 5.0 LZ algorithm.
+//
+// Decoded bytes go into the sliding dictionary window and into a staging
+// buffer that holds them back until any filter covering them is complete.
+pub struct Unpack50 {
+    bits: BitReader,
+
+    window: Vec<u8>,
+    window_mask: u64,
+    // Total bytes ever written to the window, across solid entries.
+    window_pos: u64,
+
+    tables: Option<Tables>,
+    block: Option<BlockHeader>,
+    old_dist: [u64; 4],
+    last_length: u32,
+    filters: VecDeque<Filter>,
+
+    // Unpacked bytes of the current entry not handed out yet, starting at
+    // entry offset `out_offset`.
+    out: Vec<u8>,
+    out_pos: usize,
+    out_offset: u64,
+
+    unpacked_size: Option<u64>,
+    input_done: bool,
+    done: bool,
+}
+
+impl ::std::fmt::Debug for Unpack50 {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "Unpack50{{ window_size: {:?}, written: {:?}, unpacked_size: {:?}, pending_filters: {:?} }}",
+            self.window.len(),
+            self.written(),
+            self.unpacked_size,
+            self.filters.len()
+        )
+    }
+}
+
+fn window_size(dictionary_size: u64, unpacked_size: Option<u64>) -> u64 {
+    let dictionary_size = dictionary_size.min(MAX_DICTIONARY_SIZE);
+    match unpacked_size {
+        Some(size) if size < dictionary_size => size
+            .max(MIN_WINDOW_SIZE)
+            .checked_next_power_of_two()
+            .unwrap_or(dictionary_size)
+            .min(dictionary_size),
+        _ => dictionary_size,
+    }
+}
+
+// Allocate a zeroed window of `size` bytes. Sizes come from the dictionary
+// size in the header, so a failed allocation is reported instead of aborting.
+fn alloc_window(size: u64) -> Result<Vec<u8>> {
+    let mut window = Vec::new();
+    window.try_reserve_exact(size as usize).map_err(|_| {
+        Error::unsupported(format!("Dictionary of {} bytes can't be allocated", size))
+    })?;
+    window.resize(size as usize, 0);
+    Ok(window)
+}
+
+impl Unpack50 {
+    // `unpacked_size` is used to shrink the window for small entries; pass
+    // `None` for entries of solid archives, whose successors may need the
+    // full dictionary.
+    pub fn new(info: &CompressionInfo, unpacked_size: Option<u64>) -> Result<Unpack50> {
+        let size = window_size(info.dictionary_size, unpacked_size);
+        Ok(Unpack50 {
+            bits: BitReader::new(),
+            window: alloc_window(size)?,
+            window_mask: size - 1,
+            window_pos: 0,
+            tables: None,
+            block: None,
+            old_dist: [0; 4],
+            last_length: 0,
+            filters: VecDeque::new(),
+            out: Vec::new(),
+            out_pos: 0,
+            out_offset: 0,
+            unpacked_size,
+            input_done: false,
+            done: false,
+        })
+    }
+
+    // Prepare to unpack the next entry. When `info.solid` is set the window,
+    // tables and distance history of the previous entry are kept.
+    pub fn next_entry(&mut self, info: &CompressionInfo, unpacked_size: Option<u64>) -> Result<()> {
+        let required = window_size(info.dictionary_size, None);
+        if !info.solid || (self.window.len() as u64) < required {
+            let size = window_size(info.dictionary_size, unpacked_size);
+            if size != self.window.len() as u64 {
+                self.window = alloc_window(size)?;
+                self.window_mask = size - 1;
+            }
+        }
+
+        if !info.solid {
+            self.window_pos = 0;
+            self.tables = None;
+            self.old_dist = [0; 4];
+            self.last_length = 0;
+        }
+
+        self.bits.clear();
+        self.block = None;
+        self.filters.clear();
+        self.out.clear();
+        self.out_pos = 0;
+        self.out_offset = 0;
+        self.unpacked_size = unpacked_size;
+        self.input_done = false;
+        self.done = false;
+        Ok(())
+    }
+
+    // Bytes of the current entry decoded so far.
+    fn written(&self) -> u64 {
+        self.out_offset + self.out.len() as u64
+    }
+
+    // Entry offset up to which output can be handed out.
+    fn release_limit(&self) -> u64 {
+        let mut limit = self.written();
+        if let Some(f) = self.filters.front() {
+            limit = limit.min(f.start);
+        }
+        if let Some(size) = self.unpacked_size {
+            limit = limit.min(size);
+        }
+        limit
+    }
+
+    fn ready_len(&self) -> usize {
+        (self.release_limit().saturating_sub(self.out_offset) as usize).saturating_sub(self.out_pos)
+    }
+
+    fn put(&mut self, byte: u8) {
+        self.window[(self.window_pos & self.window_mask) as usize] = byte;
+        self.window_pos += 1;
+        self.out.push(byte);
+    }
+
+    fn copy_string(&mut self, length: u32, distance: u64) {
+        for _ in 0..length {
+            let src = self.window_pos.wrapping_sub(distance) & self.window_mask;
+            let byte = self.window[src as usize];
+            self.put(byte);
+        }
+    }
+
+    fn insert_old_dist(&mut self, distance: u64) {
+        self.old_dist[3] = self.old_dist[2];
+        self.old_dist[2] = self.old_dist[1];
+        self.old_dist[1] = self.old_dist[0];
+        self.old_dist[0] = distance;
+    }
+
+    // Read a block header, returning false if the whole block isn't
+    // available yet.
+    fn read_block_header(&mut self) -> Result<bool> {
+        self.bits.align_to_byte();
+        let (flags, checksum) = match self.bits.peek_bytes(2) {
+            Some(b) => (b[0], b[1]),
+            None => return Ok(false),
+        };
+
+        let byte_count = usize::from((flags >> 3) & 3) + 1;
+        if byte_count == 4 {
+            return Err(Error::bad_data("Invalid block size length".into()));
+        }
+        let header = match self.bits.peek_bytes(2 + byte_count) {
+            Some(b) => b,
+            None => return Ok(false),
+        };
+
+        let mut block_size: usize = 0;
+        let mut expected = 0x5a ^ flags;
+        for (i, &b) in header[2..].iter().enumerate() {
+            block_size |= usize::from(b) << (i * 8);
+            expected ^= b;
+        }
+        if expected != checksum {
+            return Err(Error::bad_data("Block header checksum mismatch".into()));
+        }
+        if self.bits.available_bytes() < 2 + byte_count + block_size {
+            return Ok(false);
+        }
+
+        self.bits.skip((2 + byte_count) * 8);
+        let block_bit_size = usize::from(flags & 7) + 1;
+        let start = self.bits.byte_pos();
+        let block = BlockHeader {
+            end_bits: (start + block_size).saturating_sub(1) * 8 + block_bit_size,
+            last: flags & 0x40 != 0,
+        };
+        self.block = Some(block);
+
+        if flags & 0x80 != 0 {
+            self.read_tables()?;
+            if self.bits.bit_pos() > block.end_bits {
+                return Err(Error::bad_data("Huffman tables overrun block".into()));
+            }
+        } else if self.tables.is_none() {
+            return Err(Error::bad_data("Block without Huffman tables".into()));
+        }
+        Ok(true)
+    }
+
+    fn read_tables(&mut self) -> Result<()> {
+        let bits = &mut self.bits;
+
+        let mut bit_lengths = [0u8; BC];
+        let mut i = 0;
+        while i < BC {
+            let length = bits.read(4) as u8;
+            if length == 15 {
+                let zero_count = bits.read(4);
+                if zero_count == 0 {
+                    bit_lengths[i] = 15;
+                    i += 1;
+                } else {
+                    for _ in 0..zero_count + 2 {
+                        if i >= BC {
+                            break;
+                        }
+                        bit_lengths[i] = 0;
+                        i += 1;
+                    }
+                }
+            } else {
+                bit_lengths[i] = length;
+                i += 1;
+            }
+        }
+        let bit_decoder = Huffman::new(&bit_lengths)?;
+
+        let mut table = [0u8; HUFF_TABLE_SIZE];
+        let mut i = 0;
+        while i < HUFF_TABLE_SIZE {
+            let number = bit_decoder.decode(bits)?;
+            if number < 16 {
+                table[i] = number as u8;
+                i += 1;
+            } else {
+                let count = if number & 1 == 0 {
+                    bits.read(3) + 3
+                } else {
+                    bits.read(7) + 11
+                };
+                let value = if number < 18 {
+                    if i == 0 {
+                        return Err(Error::bad_data("Repeat at start of table".into()));
+                    }
+                    table[i - 1]
+                } else {
+                    0
+                };
+                for _ in 0..count {
+                    if i >= HUFF_TABLE_SIZE {
+                        break;
+                    }
+                    table[i] = value;
+                    i += 1;
+                }
+            }
+        }
+
+        self.tables = Some(Tables {
+            main: Huffman::new(&table[..NC])?,
+            dist: Huffman::new(&table[NC..NC + DC])?,
+            low_dist: Huffman::new(&table[NC + DC..NC + DC + LDC])?,
+            len: Huffman::new(&table[NC + DC + LDC..])?,
+        });
+        Ok(())
+    }
+
+    fn slot_to_length(&mut self, slot: u32) -> u32 {
+        if slot < 8 {
+            return slot + 2;
+        }
+        let lbits = slot / 4 - 1;
+        (((4 | (slot & 3)) << lbits) + 2) + self.bits.read(lbits)
+    }
+
+    fn read_filter_data(&mut self) -> u64 {
+        let byte_count = self.bits.read(2) + 1;
+        let mut data: u64 = 0;
+        for i in 0..byte_count {
+            data |= u64::from(self.bits.read(8)) << (i * 8);
+        }
+        data
+    }
+
+    fn read_filter(&mut self) -> Result<()> {
+        let start = self.read_filter_data();
+        let length = self.read_filter_data();
+        let raw_type = self.bits.read(3);
+        let filter_type = FilterType::from_u32(raw_type)
+            .ok_or_else(|| Error::bad_data(format!("Unknown filter type {}", raw_type)))?;
+        let channels = if filter_type == FilterType::Delta {
+            self.bits.read(5) + 1
+        } else {
+            0
+        };
+
+        if length > MAX_FILTER_BLOCK_SIZE {
+            return Err(Error::bad_data(format!(
+                "Filter length {} too large",
+                length
+            )));
+        }
+        if self.filters.len() >= MAX_UNPACK_FILTERS {
+            return Err(Error::bad_data("Too many pending filters".into()));
+        }
+        if length == 0 {
+            return Ok(());
+        }
+
+        self.filters.push_back(Filter {
+            filter_type,
+            start: self.written() + start,
+            length,
+            channels,
+        });
+        Ok(())
+    }
+
+    fn decode_symbol(&mut self, tables: &Tables) -> Result<()> {
+        let main_slot = u32::from(tables.main.decode(&mut self.bits)?);
+        if main_slot < 256 {
+            self.put(main_slot as u8);
+            return Ok(());
+        }
+
+        if main_slot >= 262 {
+            let mut length = self.slot_to_length(main_slot - 262);
+
+            let dist_slot = u32::from(tables.dist.decode(&mut self.bits)?);
+            let mut distance: u64;
+            let dbits;
+            if dist_slot < 4 {
+                dbits = 0;
+                distance = 1 + u64::from(dist_slot);
+            } else {
+                dbits = dist_slot / 2 - 1;
+                distance = 1 + (u64::from(2 | (dist_slot & 1)) << dbits);
+            }
+
+            if dbits > 0 {
+                if dbits >= 4 {
+                    if dbits > 4 {
+                        distance += u64::from(self.bits.read32(dbits - 4)) << 4;
+                    }
+                    distance += u64::from(tables.low_dist.decode(&mut self.bits)?);
+                } else {
+                    distance += u64::from(self.bits.read(dbits));
+                }
+            }
+
+            if distance > 0x100 {
+                length += 1;
+                if distance > 0x2000 {
+                    length += 1;
+                    if distance > 0x4_0000 {
+                        length += 1;
+                    }
+                }
+            }
+
+            self.insert_old_dist(distance);
+            self.last_length = length;
+            self.copy_string(length, distance);
+            return Ok(());
+        }
+
+        match main_slot {
+            256 => self.read_filter(),
+            257 => {
+                if self.last_length != 0 {
+                    self.copy_string(self.last_length, self.old_dist[0]);
+                }
+                Ok(())
+            }
+            _ => {
+                let dist_num = (main_slot - 258) as usize;
+                let distance = self.old_dist[dist_num];
+                for i in (1..=dist_num).rev() {
+                    self.old_dist[i] = self.old_dist[i - 1];
+                }
+                self.old_dist[0] = distance;
+
+                let length_slot = u32::from(tables.len.decode(&mut self.bits)?);
+                let length = self.slot_to_length(length_slot);
+                self.last_length = length;
+                self.copy_string(length, distance);
+                Ok(())
+            }
+        }
+    }
+
+    // Decode until at least `target` bytes are ready to hand out, a filter
+    // completes, or the entry ends. Returns false if more input is needed.
+    fn decode(&mut self, target: usize) -> Result<bool> {
+        while !self.done && self.ready_len() < target {
+            if let Some(size) = self.unpacked_size {
+                if self.written() >= size {
+                    self.done = true;
+                    break;
+                }
+            }
+            if let Some(f) = self.filters.front() {
+                if f.end() <= self.written() {
+                    break;
+                }
+            }
+
+            let block = match self.block {
+                Some(block) => block,
+                None => {
+                    if !self.read_block_header()? {
+                        return Ok(false);
+                    }
+                    continue;
+                }
+            };
+
+            if self.bits.bit_pos() >= block.end_bits {
+                if block.last {
+                    self.done = true;
+                } else {
+                    self.block = None;
+                }
+                continue;
+            }
+
+            let tables = self.tables.take().expect("block without tables");
+            let result = self.decode_symbol(&tables);
+            self.tables = Some(tables);
+            result?;
+        }
+        Ok(true)
+    }
+
+    fn apply_filters(&mut self) -> Result<()> {
+        while let Some(f) = self.filters.front() {
+            if f.end() > self.written() {
+                break;
+            }
+            if f.start < self.out_offset + self.out_pos as u64 {
+                return Err(Error::bad_data("Overlapping filters".into()));
+            }
+            let start = (f.start - self.out_offset) as usize;
+            let end = (f.end() - self.out_offset) as usize;
+            f.apply(&mut self.out[start..end])?;
+            self.filters.pop_front();
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self, out: &mut [u8]) -> Result<usize> {
+        self.apply_filters()?;
+
+        let n = self.ready_len().min(out.len());
+        out[..n].copy_from_slice(&self.out[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+
+        if self.out_pos == self.out.len() {
+            self.out_offset += self.out.len() as u64;
+            self.out.clear();
+            self.out_pos = 0;
+        } else if self.out_pos > 0x10000 && self.out_pos * 2 > self.out.len() {
+            self.out.drain(..self.out_pos);
+            self.out_offset += self.out_pos as u64;
+            self.out_pos = 0;
+        }
+        Ok(n)
+    }
+}
+
+impl Unpacker for Unpack50 {
+    fn feed(&mut self, data: &[u8]) {
+        self.bits.feed(data);
+    }
+
+    fn finish(&mut self) {
+        self.input_done = true;
+    }
+
+    fn unpack(&mut self, out: &mut [u8]) -> Result<Status> {
+        if out.is_empty() {
+            return Ok(Status::Output(0));
+        }
+        loop {
+            let n = self.drain(out)?;
+            if n > 0 {
+                return Ok(Status::Output(n));
+            }
+
+            if self.done {
+                if self.filters.iter().any(|f| f.end() > self.written()) {
+                    return Err(Error::bad_data("Filter extends past end of data".into()));
+                }
+                return Ok(Status::Done);
+            }
+
+            if !self.decode(out.len())? {
+                if self.input_done {
+                    return Err(Error::bad_data("Unexpected end of packed data".into()));
+                }
+                return Ok(Status::NeedInput);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    // Packs bits MSB-first, for building compressed streams by hand.
+    #[derive(Default)]
+    struct BitWriter {
+        buf: Vec<u8>,
+        bits: usize,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, count: usize) {
+            for i in (0..count).rev() {
+                if self.bits.is_multiple_of(8) {
+                    self.buf.push(0);
+                }
+                if (value >> i) & 1 != 0 {
+                    *self.buf.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+                }
+                self.bits += 1;
+            }
+        }
+    }
+
+    // Canonical code for `symbol` given the table's code lengths.
+    fn code_for(lengths: &[u8], symbol: usize) -> (u32, usize) {
+        let mut code = 0u32;
+        for len in 1..=15u8 {
+            for (s, &l) in lengths.iter().enumerate() {
+                if l == len {
+                    if s == symbol {
+                        return (code, usize::from(len));
+                    }
+                    code += 1;
+                }
+            }
+            code <<= 1;
+        }
+        panic!("symbol {} has no code", symbol);
+    }
+
+    type Emit = dyn Fn(&mut BitWriter, usize);
+
+    // A single block stream. The main table gives 9 bit codes to the
+    // literals and to symbols 256, 257, 258 and 262; every other table has
+    // two 1 bit codes. `body` emits the symbols.
+    fn stream(body: &dyn Fn(&mut BitWriter, &Emit)) -> Vec<u8> {
+        let mut table = vec![0u8; HUFF_TABLE_SIZE];
+        for l in table.iter_mut().take(256) {
+            *l = 9;
+        }
+        for &s in &[256, 257, 258, 262] {
+            table[s] = 9;
+        }
+        for &s in &[NC, NC + DC, NC + DC + LDC] {
+            table[s] = 1;
+            table[s + 1] = 1;
+        }
+
+        // Only lengths 0, 1 and 9 occur, so only those need codes.
+        let mut bit_lengths = [0u8; BC];
+        bit_lengths[0] = 2;
+        bit_lengths[1] = 2;
+        bit_lengths[9] = 2;
+
+        let mut w = BitWriter::default();
+        for &l in bit_lengths.iter() {
+            w.put(u32::from(l), 4);
+        }
+        for &l in &table {
+            let (code, len) = code_for(&bit_lengths, usize::from(l));
+            w.put(code, len);
+        }
+
+        let main_lengths = table[..NC].to_vec();
+        let emit = move |w: &mut BitWriter, symbol: usize| {
+            let (code, len) = code_for(&main_lengths, symbol);
+            w.put(code, len);
+        };
+        body(&mut w, &emit);
+
+        let size = w.buf.len();
+        assert!(size < 256);
+        let bit_size = if w.bits.is_multiple_of(8) {
+            8
+        } else {
+            w.bits % 8
+        };
+        let flags = 0x80 | 0x40 | (bit_size as u8 - 1);
+        let mut out = vec![flags, 0x5a ^ flags ^ size as u8, size as u8];
+        out.extend(w.buf);
+        out
+    }
+
+    fn unpack_all(data: &[u8], unpacked_size: Option<u64>) -> Result<Vec<u8>> {
+        let info = CompressionInfo::from_raw(1 << 7).unwrap();
+        let mut u = Unpack50::new(&info, unpacked_size).unwrap();
+        u.feed(data);
+        u.finish();
+        let mut result = Vec::new();
+        let mut buf = [0u8; 7];
+        loop {
+            match u.unpack(&mut buf)? {
+                Status::Output(n) => result.extend_from_slice(&buf[..n]),
+                Status::Done => return Ok(result),
+                Status::NeedInput => panic!("all input was fed"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compression_info_from_raw() {
+        let info = CompressionInfo::from_raw(0x40 | (3 << 7) | (5 << 10)).unwrap();
+        assert_eq!(info.version, 0);
+        assert!(info.solid);
+        assert_eq!(info.method, 3);
+        assert_eq!(info.dictionary_size, 4 * 1024 * 1024);
+        assert!(!info.is_stored());
+    }
+
+    #[test]
+    fn test_compression_info_allows_4gb_dictionary() {
+        let info = CompressionInfo::from_raw(15 << 10).unwrap();
+        assert_eq!(info.dictionary_size, MAX_DICTIONARY_SIZE);
+        assert!(CompressionInfo::from_raw(16 << 10).is_err());
+    }
+
+    #[test]
+    fn test_compression_info_rejects_unknown_version() {
+        assert!(CompressionInfo::from_raw(1).is_err());
+    }
+
+    #[test]
+    fn test_window_size_shrinks_for_small_entries() {
+        assert_eq!(window_size(0x40_0000, Some(10)), MIN_WINDOW_SIZE);
+        assert_eq!(window_size(0x40_0000, Some(0x10_0001)), 0x20_0000);
+        assert_eq!(window_size(0x40_0000, None), 0x40_0000);
+        assert_eq!(window_size(0x2_0000, Some(10)), 0x2_0000);
+    }
+
+    #[test]
+    fn test_unpacks_literals() {
+        let data = stream(&|w, emit| {
+            for &b in b"roar" {
+                emit(w, usize::from(b));
+            }
+        });
+        assert_eq!(unpack_all(&data, None).unwrap(), b"roar".to_vec());
+    }
+
+    #[test]
+    fn test_unpacks_matches_and_repeats() {
+        let data = stream(&|w, emit| {
+            emit(w, usize::from(b'a'));
+            emit(w, usize::from(b'b'));
+            // Length slot 0 (length 2) at distance slot 1 (distance 2).
+            emit(w, 262);
+            w.put(1, 1);
+            // Repeat the last length and distance.
+            emit(w, 257);
+            // Reuse old distance 0 with length slot 0.
+            emit(w, 258);
+            w.put(0, 1);
+        });
+        assert_eq!(unpack_all(&data, None).unwrap(), b"abababab".to_vec());
+    }
+
+    #[test]
+    fn test_stops_at_unpacked_size() {
+        let data = stream(&|w, emit| {
+            for &b in b"roar" {
+                emit(w, usize::from(b));
+            }
+        });
+        assert_eq!(unpack_all(&data, Some(2)).unwrap(), b"ro".to_vec());
+    }
+
+    #[test]
+    fn test_applies_delta_filter() {
+        let data = stream(&|w, emit| {
+            emit(w, 256);
+            // Start 0, length 3, delta with one channel.
+            w.put(0, 2);
+            w.put(0, 8);
+            w.put(0, 2);
+            w.put(3, 8);
+            w.put(0, 3);
+            w.put(0, 5);
+            for _ in 0..3 {
+                emit(w, 0xff);
+            }
+            emit(w, usize::from(b'!'));
+        });
+        assert_eq!(unpack_all(&data, None).unwrap(), vec![1, 2, 3, b'!']);
+    }
+
+    #[test]
+    fn test_truncated_input_errors() {
+        let data = stream(&|w, emit| {
+            for &b in b"roar" {
+                emit(w, usize::from(b));
+            }
+        });
+        assert!(unpack_all(&data[..data.len() - 1], None).is_err());
+    }
+
+    #[test]
+    fn test_bad_block_checksum_errors() {
+        let mut data = stream(&|w, emit| emit(w, usize::from(b'x')));
+        data[1] ^= 1;
+        assert!(unpack_all(&data, None).is_err());
+    }
+
+    #[test]
+    fn test_unallocatable_window_errors() {
+        let err = alloc_window(u64::MAX).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::Unsupported(_)));
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Status, Unpacker};
+use futures::io::AsyncRead;
+use futures::task::{Context, Poll};
+use std::io;
+use std::pin::Pin;
+
+// How much packed data to pull from the underlying file at a time.
+const INPUT_CHUNK: usize = 0x8000;
+
+// Streams the unpacked contents of an entry. `inner` must be positioned at
+// the start of the entry's packed data; exactly `packed_size` bytes are read
+// from it.
+pub struct EntryReader<T, U> {
+    inner: T,
+    unpacker: U,
+    remaining: u64,
+    input: Vec<u8>,
+    finished_input: bool,
+}
+
+impl<T: AsyncRead + Unpin, U: Unpacker + Unpin> EntryReader<T, U> {
+    pub fn new(inner: T, unpacker: U, packed_size: u64) -> EntryReader<T, U> {
+        EntryReader {
+            inner,
+            unpacker,
+            remaining: packed_size,
+            input: Vec::new(),
+            finished_input: false,
+        }
+    }
+
+    // Give back the file and the unpacker, e.g. to carry a solid decoder over
+    // to the next entry.
+    pub fn into_parts(self) -> (T, U) {
+        (self.inner, self.unpacker)
+    }
+}
+
+impl<T: AsyncRead + Unpin, U: Unpacker + Unpin> AsyncRead for EntryReader<T, U> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            let status = match this.unpacker.unpack(buf) {
+                Ok(status) => status,
+                Err(e) => return Poll::Ready(Err(e.into())),
+            };
+
+            match status {
+                Status::Output(n) => return Poll::Ready(Ok(n)),
+                Status::Done => return Poll::Ready(Ok(0)),
+                Status::NeedInput if this.remaining == 0 => {
+                    if this.finished_input {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    this.unpacker.finish();
+                    this.finished_input = true;
+                }
+                Status::NeedInput => {
+                    let want = this.remaining.min(INPUT_CHUNK as u64) as usize;
+                    this.input.resize(want, 0);
+                    let n = match Pin::new(&mut this.inner).poll_read(cx, &mut this.input[..want]) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                        Poll::Ready(Ok(n)) => n,
+                    };
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    this.remaining -= n as u64;
+                    this.unpacker.feed(&this.input[..n]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Store;
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+
+    #[test]
+    fn test_stored_entry_reads_packed_size_bytes() {
+        let file: &[u8] = b"roar and more";
+        let mut reader = EntryReader::new(file, Store::new(), 4);
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"roar".to_vec());
+    }
+
+    #[test]
+    fn test_stored_entry_with_size_drops_padding() {
+        let file: &[u8] = b"roar\0\0\0\0";
+        let mut reader = EntryReader::new(file, Store::with_size(4), 8);
+        let mut out = Vec::new();
+        block_on(reader.read_to_end(&mut out)).unwrap();
+        assert_eq!(out, b"roar".to_vec());
+    }
+
+    #[test]
+    fn test_short_file_errors() {
+        let file: &[u8] = b"ro";
+        let mut reader = EntryReader::new(file, Store::new(), 4);
+        let mut out = Vec::new();
+        assert!(block_on(reader.read_to_end(&mut out)).is_err());
+    }
+}
@@ -0,0 +1,546 @@
+// Multi-volume archives, split over files named `name.part1.rar`,
+// `name.part2.rar`... or, in the old style, `name.rar`, `name.r00`,
+// `name.r01`... Volumes are opened one after the other for as long as the end
+// of archive header of the last one says another volume follows, and a file
+// split across volumes is read by chaining its parts, with the header of the
+// last part holding the checksum of the whole file.
+
+use crate::archive::{Archive, Format};
+use crate::block::{FileBlock, FileFlags};
+use crate::error::{Error, ErrorKind, Result};
+use crate::hash::VerifyReader;
+use crate::rar5::{FileHeader, MainHeader, Rar5EntryReader, Rar5Unpacking};
+use crate::recvol::rebuild_volumes;
+use crate::unpack::{EntryReader, Store};
+use futures::io::{AllowStdIo, AsyncRead};
+use futures::task::{Context, Poll};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+// Name of volume `index`, counting from 0, of the set whose first volume is
+// `first`. The number of `.partN.rar` names keeps its width, and old style
+// names run from `.r00` to `.r99`, then on to `.s00` and so on.
+pub fn volume_path(first: &Path, index: usize) -> Option<PathBuf> {
+    let name = first.file_name()?.to_str()?;
+    let lower = name.to_ascii_lowercase();
+    let base = lower.strip_suffix(".rar")?;
+    let digits = base.len() - base.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let prefix = &name[..base.len() - digits];
+
+    let new_name = if digits > 0 && prefix.to_ascii_lowercase().ends_with(".part") {
+        format!(
+            "{}{:0width$}{}",
+            prefix,
+            index + 1,
+            &name[base.len()..],
+            width = digits
+        )
+    } else if index == 0 {
+        name.to_string()
+    } else {
+        if index > 900 {
+            return None;
+        }
+        let letter = b'r' + ((index - 1) / 100) as u8;
+        format!(
+            "{}.{}{:02}",
+            base_name(name),
+            letter as char,
+            (index - 1) % 100
+        )
+    };
+    Some(first.with_file_name(new_name))
+}
+
+// `name.rar` without the extension, keeping its case.
+fn base_name(name: &str) -> &str {
+    &name[..name.len() - ".rar".len()]
+}
+
+// Where part of a file is stored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct VolumePart {
+    pub volume: usize,
+    pub data_offset: u64,
+    pub packed_size: u64,
+}
+
+// The header of the last part of a file seen so far.
+#[derive(Debug)]
+pub enum VolumeHeader {
+    Rar15(FileBlock),
+    Rar50(FileHeader),
+}
+
+impl VolumeHeader {
+    fn is_split_before(&self) -> bool {
+        match *self {
+            VolumeHeader::Rar15(ref file) => file
+                .header()
+                .flags()
+                .contains(FileFlags::CONTINUED_FROM_PREVIOUS_VOLUME),
+            VolumeHeader::Rar50(ref file) => file.header.is_split_before(),
+        }
+    }
+
+    fn is_split_after(&self) -> bool {
+        match *self {
+            VolumeHeader::Rar15(ref file) => file
+                .header()
+                .flags()
+                .contains(FileFlags::CONTINUED_TO_NEXT_VOLUME),
+            VolumeHeader::Rar50(ref file) => file.header.is_split_after(),
+        }
+    }
+}
+
+// A file of a multi-volume archive and the parts it's split into, in order.
+#[derive(Debug)]
+pub struct VolumeEntry {
+    pub name: String,
+    pub header: VolumeHeader,
+    pub parts: Vec<VolumePart>,
+}
+
+impl VolumeEntry {
+    pub fn packed_size(&self) -> u64 {
+        self.parts.iter().map(|p| p.packed_size).sum()
+    }
+
+    // Whether the file continues in a volume that wasn't there.
+    pub fn is_truncated(&self) -> bool {
+        self.header.is_split_after()
+    }
+}
+
+// The volumes of a multi-volume archive, or a single archive, and the files
+// in them.
+pub struct VolumeSet {
+    paths: Vec<PathBuf>,
+    format: Format,
+    // The main header of the first volume, for RAR5 archives.
+    main: Option<MainHeader>,
+    entries: Vec<VolumeEntry>,
+    // Volumes rebuilt from recovery volumes while opening the set.
+    rebuilt: Vec<PathBuf>,
+}
+
+impl VolumeSet {
+    // Open every volume of the set starting with `first`, listing the files.
+    // `password` is needed for RAR5 archives with encrypted headers. When a
+    // volume is missing or can't be read, the set is rebuilt from its
+    // recovery volumes, if there are any, and opened again.
+    pub async fn open(first: &Path, password: Option<&str>) -> Result<VolumeSet> {
+        let err = match VolumeSet::open_volumes(first, password).await {
+            Ok(set) => return Ok(set),
+            Err(e) => e,
+        };
+        match *err.kind() {
+            ErrorKind::Unsupported(_) | ErrorKind::WrongPassword => return Err(err),
+            _ => {}
+        }
+        let report = match rebuild_volumes(first).await {
+            Ok(ref report) if report.rebuilt.is_empty() => return Err(err),
+            Ok(report) => report,
+            Err(_) => return Err(err),
+        };
+        let mut set = VolumeSet::open_volumes(first, password).await?;
+        set.rebuilt = report.rebuilt;
+        Ok(set)
+    }
+
+    async fn open_volumes(first: &Path, password: Option<&str>) -> Result<VolumeSet> {
+        let mut set = VolumeSet {
+            paths: Vec::new(),
+            format: Format::Rar15,
+            main: None,
+            entries: Vec::new(),
+            rebuilt: Vec::new(),
+        };
+        loop {
+            let index = set.paths.len();
+            let path = if index == 0 {
+                first.to_path_buf()
+            } else {
+                volume_path(first, index).ok_or_else(|| {
+                    Error::unsupported(format!("Volume names like {}", first.display()))
+                })?
+            };
+            let file = fs::File::open(&path).map_err(|e| {
+                Error::bad_data(format!("Can't open volume {}: {}", path.display(), e))
+            })?;
+            let mut archive = Archive::open(AllowStdIo::new(file)).await?;
+            if index == 0 {
+                set.format = archive.format();
+            } else if archive.format() != set.format {
+                return Err(Error::bad_data(format!(
+                    "Volume {} isn't in the format of the first one",
+                    path.display()
+                )));
+            }
+            set.paths.push(path);
+            let next = match set.format {
+                Format::Rar50 => set.add_rar5_volume(&mut archive, password).await?,
+                Format::Rar15 => set.add_rar15_volume(&mut archive).await?,
+                Format::Rar14 => false,
+            };
+            if !next {
+                return Ok(set);
+            }
+        }
+    }
+
+    async fn add_rar5_volume(
+        &mut self,
+        archive: &mut Archive<AllowStdIo<fs::File>>,
+        password: Option<&str>,
+    ) -> Result<bool> {
+        let listing = archive.list_rar5_with_password(password).await?;
+        for entry in listing.entries {
+            let part = self.part(entry.data_offset, entry.file.packed_size());
+            self.add(
+                entry.file.name.clone(),
+                VolumeHeader::Rar50(entry.file),
+                part,
+            );
+        }
+        if self.main.is_none() {
+            self.main = Some(listing.main);
+        }
+        Ok(listing.end.is_some_and(|end| end.has_next_volume()))
+    }
+
+    async fn add_rar15_volume(
+        &mut self,
+        archive: &mut Archive<AllowStdIo<fs::File>>,
+    ) -> Result<bool> {
+        while let Some(entry) = archive.read_entry().await? {
+            let part = self.part(entry.data_offset, entry.file.header().packed_size());
+            let name = entry.file.header().full_name();
+            self.add(name, VolumeHeader::Rar15(entry.file), part);
+        }
+        // Volumes written before RAR 3.0 have no end block, but a file
+        // running on into the next volume says as much.
+        Ok(match archive.end() {
+            Some(end) => end.has_next_volume(),
+            None => self.entries.last().is_some_and(|e| e.is_truncated()),
+        })
+    }
+
+    fn part(&self, data_offset: u64, packed_size: u64) -> VolumePart {
+        VolumePart {
+            volume: self.paths.len() - 1,
+            data_offset,
+            packed_size,
+        }
+    }
+
+    // Add a file, or the next part of the last one.
+    fn add(&mut self, name: String, header: VolumeHeader, part: VolumePart) {
+        if header.is_split_before() {
+            if let Some(last) = self.entries.last_mut() {
+                if last.is_truncated() && last.name == name {
+                    last.header = header;
+                    last.parts.push(part);
+                    return;
+                }
+            }
+        }
+        self.entries.push(VolumeEntry {
+            name,
+            header,
+            parts: vec![part],
+        });
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn volumes(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    pub fn entries(&self) -> &[VolumeEntry] {
+        &self.entries
+    }
+
+    pub fn rebuilt(&self) -> &[PathBuf] {
+        &self.rebuilt
+    }
+
+    // The packed data of `entry`, read from each volume in turn.
+    pub fn packed_reader(&self, entry: &VolumeEntry) -> PartsReader {
+        PartsReader {
+            paths: self.paths.clone(),
+            parts: entry.parts.clone(),
+            next: 0,
+            current: None,
+        }
+    }
+
+    // What `open_rar5_entry` needs to carry from one file to the next.
+    pub fn rar5_unpacking(&self, password: Option<String>) -> Result<Rar5Unpacking> {
+        match self.main {
+            Some(ref main) => Ok(Rar5Unpacking::from_main(main, password)),
+            None => Err(Error::bad_data("Not a RAR5 archive".into())),
+        }
+    }
+
+    // Stream the contents of a file of a RAR5 set. Files of solid archives
+    // must be read in order, sharing `state`.
+    pub fn open_rar5_entry<'a>(
+        &self,
+        entry: &VolumeEntry,
+        state: &'a mut Rar5Unpacking,
+    ) -> Result<Rar5EntryReader<'a, PartsReader>> {
+        let file = match entry.header {
+            VolumeHeader::Rar50(ref file) => file,
+            VolumeHeader::Rar15(_) => return Err(Error::bad_data("Not a RAR5 file".into())),
+        };
+        if entry.is_truncated() {
+            return Err(Error::bad_data(format!(
+                "{} is missing its last part",
+                entry.name
+            )));
+        }
+        // The last part has the checksum of the whole file.
+        let mut whole = file.clone();
+        whole.header.data_size = entry.packed_size();
+        Rar5EntryReader::new(self.packed_reader(entry), &whole, state)
+    }
+
+    // Stream the contents of a file of a RAR 1.5-4.x set, checking them
+    // against the CRC of the last part. Only stored files can be read.
+    pub fn open_rar15_entry(
+        &self,
+        entry: &VolumeEntry,
+    ) -> Result<VerifyReader<EntryReader<PartsReader, Store>>> {
+        let file = match entry.header {
+            VolumeHeader::Rar15(ref file) => file.header(),
+            VolumeHeader::Rar50(_) => return Err(Error::bad_data("Not a RAR 1.5-4.x file".into())),
+        };
+        if entry.is_truncated() {
+            return Err(Error::bad_data(format!(
+                "{} is missing its last part",
+                entry.name
+            )));
+        }
+        if file.is_encrypted() {
+            return Err(Error::unsupported("RAR 1.5-4.x encryption".into()));
+        }
+        if !file.is_stored() {
+            return Err(Error::unsupported(format!(
+                "RAR 1.5-4.x compression method {:#x}",
+                file.prefix().raw_packing_method()
+            )));
+        }
+        let reader = EntryReader::new(self.packed_reader(entry), Store::new(), entry.packed_size());
+        Ok(VerifyReader::new(reader, file.hash(), None))
+    }
+}
+
+// Reads the parts of a file from their volumes one after the other. A part
+// cut short by a truncated volume ends early, which the entry readers catch
+// as the data running out.
+pub struct PartsReader {
+    paths: Vec<PathBuf>,
+    parts: Vec<VolumePart>,
+    next: usize,
+    current: Option<io::Take<fs::File>>,
+}
+
+impl PartsReader {
+    fn read_parts(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                let n = current.read(buf)?;
+                if n > 0 || buf.is_empty() {
+                    return Ok(n);
+                }
+            }
+            let part = match self.parts.get(self.next) {
+                Some(part) => *part,
+                None => return Ok(0),
+            };
+            self.next += 1;
+            let mut file = fs::File::open(&self.paths[part.volume])?;
+            file.seek(SeekFrom::Start(part.data_offset))?;
+            self.current = Some(file.take(part.packed_size));
+        }
+    }
+}
+
+impl AsyncRead for PartsReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(self.read_parts(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{RAR15_SIGNATURE, RAR50_SIGNATURE};
+    use crate::rar5::write_vint;
+    use crate::testing::{rar5_header, rev5_files, with_header_crc, FileBlock as TestFileBlock};
+    use futures::executor::block_on;
+    use futures::io::AsyncReadExt;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("roar-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_volume_paths() {
+        let path = |first: &str, index| volume_path(Path::new(first), index).unwrap();
+        assert_eq!(path("dir/a.part1.rar", 0), Path::new("dir/a.part1.rar"));
+        assert_eq!(path("dir/a.part1.rar", 11), Path::new("dir/a.part12.rar"));
+        assert_eq!(path("a.part001.RAR", 9), Path::new("a.part010.RAR"));
+        assert_eq!(path("a.rar", 0), Path::new("a.rar"));
+        assert_eq!(path("a.rar", 1), Path::new("a.r00"));
+        assert_eq!(path("a.rar", 100), Path::new("a.r99"));
+        assert_eq!(path("a.rar", 101), Path::new("a.s00"));
+        assert_eq!(path("v1.rar", 1), Path::new("v1.r00"));
+        assert!(volume_path(Path::new("a.zip"), 1).is_none());
+    }
+
+    // A RAR5 volume holding part `number` of a stored file.
+    fn rar5_volume(number: usize, parts: &[&[u8]], contents: &[u8]) -> Vec<u8> {
+        let mut data = RAR50_SIGNATURE.to_vec();
+        let mut main = vec![0x01, 0x00, 0x03];
+        write_vint(number as u64, &mut main);
+        data.extend(rar5_header(&main));
+
+        let last = number + 1 == parts.len();
+        let mut flags = 0x02;
+        if number > 0 {
+            flags |= 0x08;
+        }
+        if !last {
+            flags |= 0x10;
+        }
+        let mut body = vec![0x02, flags];
+        write_vint(parts[number].len() as u64, &mut body);
+        body.push(0x04); // file flags: crc32
+        write_vint(contents.len() as u64, &mut body);
+        body.push(0x20);
+        body.extend(&crc::crc32::checksum_ieee(contents).to_le_bytes());
+        body.extend(&[0x00, 0x01, 0x05]);
+        body.extend(b"a.txt");
+        data.extend(rar5_header(&body));
+        data.extend(parts[number]);
+        data.extend(rar5_header(&[0x05, 0x00, if last { 0x00 } else { 0x01 }]));
+        data
+    }
+
+    #[test]
+    fn test_reads_rar5_file_split_across_volumes() {
+        let dir = scratch("volumes5");
+        let contents = b"roar and meow";
+        let parts = [&contents[..4], &contents[4..9], &contents[9..]];
+        for i in 0..parts.len() {
+            let volume = rar5_volume(i, &parts, contents);
+            fs::write(dir.join(format!("a.part{}.rar", i + 1)), volume).unwrap();
+        }
+
+        let set = block_on(VolumeSet::open(&dir.join("a.part1.rar"), None)).unwrap();
+        assert_eq!(set.volumes().len(), 3);
+        assert_eq!(set.entries().len(), 1);
+        let entry = &set.entries()[0];
+        assert_eq!(entry.packed_size(), contents.len() as u64);
+        let mut state = set.rar5_unpacking(None).unwrap();
+        let mut out = Vec::new();
+        block_on(
+            set.open_rar5_entry(entry, &mut state)
+                .unwrap()
+                .read_to_end(&mut out),
+        )
+        .unwrap();
+        assert_eq!(out, contents);
+
+        // Without the last volume.
+        fs::remove_file(dir.join("a.part3.rar")).unwrap();
+        assert!(block_on(VolumeSet::open(&dir.join("a.part1.rar"), None)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebuilds_missing_volume_when_opening() {
+        let dir = scratch("volumes-rev");
+        let contents = b"roar and meow";
+        let parts = [&contents[..4], &contents[4..9], &contents[9..]];
+        let volumes: Vec<Vec<u8>> = (0..parts.len())
+            .map(|i| rar5_volume(i, &parts, contents))
+            .collect();
+        for (i, volume) in volumes.iter().enumerate().skip(1) {
+            fs::write(dir.join(format!("a.part{}.rar", i + 1)), volume).unwrap();
+        }
+        fs::write(dir.join("a.part4.rev"), &rev5_files(&volumes, 1)[0]).unwrap();
+
+        let first = dir.join("a.part1.rar");
+        let set = block_on(VolumeSet::open(&first, None)).unwrap();
+        assert_eq!(set.rebuilt(), &[dir.join("a.part1.rar")]);
+        assert_eq!(fs::read(&first).unwrap(), volumes[0]);
+        let mut state = set.rar5_unpacking(None).unwrap();
+        let mut out = Vec::new();
+        let entry = &set.entries()[0];
+        block_on(
+            set.open_rar5_entry(entry, &mut state)
+                .unwrap()
+                .read_to_end(&mut out),
+        )
+        .unwrap();
+        assert_eq!(out, contents);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // A RAR 1.5-4.x volume holding one stored file part.
+    fn rar15_volume(part: &[u8], flags: u16, next: bool, crc: u32) -> Vec<u8> {
+        let mut data = RAR15_SIGNATURE.to_vec();
+        data.extend(with_header_crc(vec![
+            0, 0, 0x73, 0x01, 0x00, 13, 0, 0, 0, 0, 0, 0, 0,
+        ]));
+        let mut block = TestFileBlock::new(b"a.txt", part);
+        block.flags = flags;
+        block.file_crc = Some(crc);
+        data.extend(block.build());
+        data.extend(with_header_crc(vec![0, 0, 0x7b, next as u8, 0, 7, 0]));
+        data
+    }
+
+    #[test]
+    fn test_reads_rar15_file_split_across_old_style_volumes() {
+        let dir = scratch("volumes15");
+        let contents = b"roar and meow";
+        let crc = crc::crc32::checksum_ieee(contents);
+        fs::write(
+            dir.join("a.rar"),
+            rar15_volume(&contents[..6], 0x02, true, 0),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("a.r00"),
+            rar15_volume(&contents[6..], 0x01, false, crc),
+        )
+        .unwrap();
+
+        let set = block_on(VolumeSet::open(&dir.join("a.rar"), None)).unwrap();
+        assert_eq!(set.format(), Format::Rar15);
+        assert_eq!(set.volumes().len(), 2);
+        let entry = &set.entries()[0];
+        assert_eq!(entry.parts[1].volume, 1);
+        let mut out = Vec::new();
+        block_on(set.open_rar15_entry(entry).unwrap().read_to_end(&mut out)).unwrap();
+        assert_eq!(out, contents);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}